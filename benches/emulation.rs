@@ -0,0 +1,121 @@
+use chip_8::{
+    block::BlockCache,
+    context::Context,
+    palette::Palette,
+    parser::{decode_instruction, Variant},
+    render::convert_graphics_buffer,
+    test_data::DATA,
+};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+const TICKS: u64 = 1000;
+
+// Thirty copies of `opcode` followed by a jump back to the start. The count is
+// even so skips always land on an instruction of the program.
+fn repeat_opcode(opcode: u16) -> Vec<u8> {
+    let mut program = vec![];
+    for _ in 0..30 {
+        program.extend(opcode.to_be_bytes());
+    }
+    program.extend([0x12, 0x00]);
+    program
+}
+
+fn run_ticks(program: &[u8]) -> Context {
+    let mut context = Context::new(program, 1);
+    for _ in 0..TICKS {
+        context.tick();
+    }
+    context
+}
+
+fn bench_roms(c: &mut Criterion) {
+    let ibm_logo = include_bytes!("../assets/ibm_logo.ch8");
+    let mut group = c.benchmark_group("rom");
+    group.throughput(Throughput::Elements(TICKS));
+
+    group.bench_function("ibm_logo/tick", |b| {
+        b.iter_batched(
+            || Context::new(ibm_logo, 1),
+            |mut context| {
+                for _ in 0..TICKS {
+                    context.tick();
+                }
+                context
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("ibm_logo/block_cache", |b| {
+        b.iter_batched(
+            || (Context::new(ibm_logo, 1), BlockCache::new()),
+            |(mut context, mut cache)| {
+                cache.run(&mut context, TICKS as usize);
+                context
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_opcodes(c: &mut Criterion) {
+    let classes: [(&str, Vec<u8>); 9] = [
+        ("load", repeat_opcode(0x6A42)),
+        ("alu", repeat_opcode(0x8A13)),
+        ("set_i", repeat_opcode(0xA300)),
+        ("skip", repeat_opcode(0x3000)),
+        ("random", repeat_opcode(0xC1FF)),
+        ("draw", repeat_opcode(0xD01F)),
+        ("store", repeat_opcode(0xFF55)),
+        ("load_range", repeat_opcode(0xFF65)),
+        // Call a return at 0x204, which lands back on the call
+        ("call_return", vec![0x22, 0x04, 0x12, 0x00, 0x00, 0xEE]),
+    ];
+
+    let mut group = c.benchmark_group("opcode");
+    group.throughput(Throughput::Elements(TICKS));
+    for (name, program) in classes.iter() {
+        group.bench_function(*name, |b| b.iter(|| run_ticks(black_box(program))));
+    }
+    group.finish();
+}
+
+fn bench_parser(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parser");
+    group.throughput(Throughput::Elements(0x10000));
+    group.bench_function("decode_instruction", |b| {
+        let dialect = Variant::Chip8.dialect();
+        b.iter(|| {
+            for opcode in 0..=u16::MAX {
+                let _ = black_box(decode_instruction(
+                    black_box(&opcode.to_be_bytes()),
+                    dialect,
+                ));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_render(c: &mut Criterion) {
+    let mut context = Context::new(&DATA, 1);
+    for _ in 0..50 {
+        context.tick();
+    }
+    let buffer = context.get_flat_graphics_buffer();
+    let palette = Palette::default();
+
+    c.bench_function("render/convert_graphics_buffer", |b| {
+        b.iter(|| convert_graphics_buffer(black_box(&buffer), &palette))
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_roms,
+    bench_opcodes,
+    bench_parser,
+    bench_render
+);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+#![no_main]
+
+use chip_8::parser::{decode_instruction, encode_instruction, ParseInstructionError, Variant};
+use libfuzzer_sys::fuzz_target;
+
+// Every opcode either decodes to an instruction that encodes back to the same
+// bytes or is reported unknown, in every variant
+fuzz_target!(|input: ([u8; 4], u8)| {
+    let (bytes, variant) = input;
+    let variants = [
+        Variant::Chip8,
+        Variant::Chip8X,
+        Variant::Chip8E,
+        Variant::MegaChip,
+    ];
+    let variant = variants[variant as usize % variants.len()];
+    match decode_instruction(&bytes, variant.dialect()) {
+        Ok(instruction) => assert_eq!(
+            encode_instruction(instruction),
+            bytes[..instruction.length()],
+            "{instruction:?}"
+        ),
+        Err(error) => assert_eq!(
+            error,
+            ParseInstructionError::UnknownOpcode(u16::from_be_bytes([bytes[0], bytes[1]]))
+        ),
+    }
+});
@@ -0,0 +1,73 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use chip_8::{
+    context::{Context, Quirks, MEMORY_SIZE},
+    megachip,
+    parser::Variant,
+};
+use libfuzzer_sys::fuzz_target;
+
+// Instructions run between two timer updates
+const TICKS_PER_FRAME: usize = 10;
+// Keeps every run short enough to try many ROMs
+const MAX_TICKS: usize = 10_000;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    rom: Vec<u8>,
+    seed: u64,
+    shift_uses_vy: bool,
+    load_store_increments_i: bool,
+    jump_uses_vx: bool,
+    logic_resets_vf: bool,
+    stack_in_memory: bool,
+    // Return addresses the stack can hold, from 1 to 16
+    stack_depth: u8,
+    // CHIP-8, CHIP-8X, CHIP-8E or MEGA-CHIP
+    variant: u8,
+    // Key held down during each instruction
+    keys: Vec<Option<u8>>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut context = Context::new(&input.rom, input.seed);
+    context.quirks = Quirks {
+        shift_uses_vy: input.shift_uses_vy,
+        load_store_increments_i: input.load_store_increments_i,
+        jump_uses_vx: input.jump_uses_vx,
+        logic_resets_vf: input.logic_resets_vf,
+        stack_in_memory: input.stack_in_memory,
+    };
+    context.stack_depth = (input.stack_depth % 16) as usize + 1;
+    let variants = [
+        Variant::Chip8,
+        Variant::Chip8X,
+        Variant::Chip8E,
+        Variant::MegaChip,
+    ];
+    context.set_variant(variants[input.variant as usize % variants.len()]);
+    let memory_size = match context.variant {
+        Variant::MegaChip => megachip::MEMORY_SIZE,
+        _ => MEMORY_SIZE,
+    };
+
+    for (tick, key) in input.keys.iter().take(MAX_TICKS).enumerate() {
+        if context.fault.is_some() {
+            break;
+        }
+        context.keyboard_input = key.map(|key| key & 0xF);
+        // The same key on the second keypad, and as input port byte
+        context.second_keypad = context.keyboard_input;
+        context.port_input = *key;
+        context.tick();
+        if tick % TICKS_PER_FRAME == 0 {
+            context.update_timers();
+        }
+
+        assert!((context.program_counter as usize) < MEMORY_SIZE);
+        assert!(context.stack_pointer.len() <= context.stack_depth);
+        assert_eq!(context.memory_map.len(), memory_size);
+        assert_eq!(context.get_flat_graphics_buffer().len(), 32 * 8);
+    }
+});
@@ -0,0 +1,158 @@
+use crate::context::Quirks;
+
+// Register to register operations, `8xyN`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Operation {
+    Set,
+    Or,
+    And,
+    Xor,
+    Add,
+    Sub,
+    ShiftRight,
+    SubN,
+    ShiftLeft,
+}
+
+// New values of Vx and VF. VF must be written after Vx, so `8Fy4` and
+// friends leave the flag in VF rather than the result.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Output {
+    pub vx: u8,
+    // None when VF is left alone
+    pub vf: Option<u8>,
+}
+
+/**
+ *  Runs an operation on 8 bit registers, wrapping around on overflow:
+ *
+ *  - `Add` sets VF to the carry
+ *  - `Sub` and `SubN` set VF to 1 when there's no borrow, so equal
+ *    operands set it
+ *  - shifts set VF to the bit shifted out, and shift Vy instead of Vx with
+ *    `shift_uses_vy`
+ *  - `Or`, `And` and `Xor` only reset VF with `logic_resets_vf`
+ */
+pub fn apply(operation: Operation, vx: u8, vy: u8, quirks: &Quirks) -> Output {
+    let shifted = if quirks.shift_uses_vy { vy } else { vx };
+    let logic_flag = quirks.logic_resets_vf.then_some(0);
+    let (vx, vf) = match operation {
+        Operation::Set => (vy, None),
+        Operation::Or => (vx | vy, logic_flag),
+        Operation::And => (vx & vy, logic_flag),
+        Operation::Xor => (vx ^ vy, logic_flag),
+        Operation::Add => {
+            let (sum, carry) = vx.overflowing_add(vy);
+            (sum, Some(carry as u8))
+        }
+        Operation::Sub => (vx.wrapping_sub(vy), Some((vx >= vy) as u8)),
+        Operation::ShiftRight => (shifted >> 1, Some(shifted & 0x1)),
+        Operation::SubN => (vy.wrapping_sub(vx), Some((vy >= vx) as u8)),
+        Operation::ShiftLeft => (shifted << 1, Some(shifted >> 7)),
+    };
+    Output { vx, vf }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply, Operation, Output};
+    use crate::context::{Context, Quirks};
+
+    // Every pair of operands, `vx` first
+    fn operands() -> impl Iterator<Item = (u8, u8)> {
+        (0..=u16::MAX).map(|word| {
+            let [vx, vy] = word.to_be_bytes();
+            (vx, vy)
+        })
+    }
+
+    #[test]
+    fn add_with_carry() {
+        let quirks = Quirks::default();
+        for (vx, vy) in operands() {
+            let sum = vx as u16 + vy as u16;
+            let output = apply(Operation::Add, vx, vy, &quirks);
+            assert_eq!(output.vx, (sum % 0x100) as u8);
+            assert_eq!(output.vf, Some((sum > 0xFF) as u8));
+        }
+    }
+
+    #[test]
+    fn subtract_with_borrow() {
+        let quirks = Quirks::default();
+        for (vx, vy) in operands() {
+            let difference = vx as i16 - vy as i16;
+            let output = apply(Operation::Sub, vx, vy, &quirks);
+            assert_eq!(output.vx, difference.rem_euclid(0x100) as u8);
+            assert_eq!(output.vf, Some((difference >= 0) as u8));
+
+            let output = apply(Operation::SubN, vx, vy, &quirks);
+            assert_eq!(output.vx, (-difference).rem_euclid(0x100) as u8);
+            assert_eq!(output.vf, Some((difference <= 0) as u8));
+        }
+    }
+
+    #[test]
+    fn shift_with_quirks() {
+        let shift_uses_vy = Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        };
+        for (vx, vy) in operands() {
+            for (quirks, source) in [(Quirks::default(), vx), (shift_uses_vy, vy)] {
+                let output = apply(Operation::ShiftRight, vx, vy, &quirks);
+                assert_eq!(output.vx, source / 2);
+                assert_eq!(output.vf, Some(source % 2));
+
+                let output = apply(Operation::ShiftLeft, vx, vy, &quirks);
+                assert_eq!(output.vx, (source as u16 * 2 % 0x100) as u8);
+                assert_eq!(output.vf, Some((source >= 0x80) as u8));
+            }
+        }
+    }
+
+    #[test]
+    fn logic_with_quirks() {
+        let logic_resets_vf = Quirks {
+            logic_resets_vf: true,
+            ..Quirks::default()
+        };
+        for (vx, vy) in operands() {
+            for (quirks, vf) in [(Quirks::default(), None), (logic_resets_vf, Some(0))] {
+                let or = apply(Operation::Or, vx, vy, &quirks);
+                let and = apply(Operation::And, vx, vy, &quirks);
+                let xor = apply(Operation::Xor, vx, vy, &quirks);
+                assert_eq!(or, Output { vx: vx | vy, vf });
+                assert_eq!(and, Output { vx: vx & vy, vf });
+                assert_eq!(xor, Output { vx: vx ^ vy, vf });
+            }
+            let set = apply(Operation::Set, vx, vy, &logic_resets_vf);
+            assert_eq!(set, Output { vx: vy, vf: None });
+        }
+    }
+
+    #[test]
+    fn write_flag_after_vf_as_operand() {
+        // 8F1N with V1 = 0x81 and VF = 0x7F
+        let operations = [
+            (Operation::Set, 0x0),
+            (Operation::Or, 0x1),
+            (Operation::And, 0x2),
+            (Operation::Xor, 0x3),
+            (Operation::Add, 0x4),
+            (Operation::Sub, 0x5),
+            (Operation::ShiftRight, 0x6),
+            (Operation::SubN, 0x7),
+            (Operation::ShiftLeft, 0xE),
+        ];
+        for (operation, n) in operations {
+            let mut context = Context::new(&[0x8F, 0x10 | n], 1);
+            context.registers[0x1] = 0x81;
+            context.registers[0xF] = 0x7F;
+            context.tick();
+
+            let output = apply(operation, 0x7F, 0x81, &Quirks::default());
+            assert_eq!(context.registers[0xF], output.vf.unwrap_or(output.vx));
+        }
+    }
+}
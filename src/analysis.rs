@@ -0,0 +1,451 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    ops::Range,
+};
+
+use serde_json::json;
+
+use crate::{
+    context::{Context, MEMORY_SIZE, PROGRAM_ADDRESS},
+    instructions::Instruction,
+    parser::Dialect,
+};
+
+// A run of instructions only entered at the start and only left at the end
+#[derive(PartialEq, Debug)]
+pub struct BasicBlock {
+    pub start: u16,
+    pub instructions: Vec<(u16, Instruction)>,
+    // Blocks that can run next. The callee of a `Call` is in the call graph,
+    // the block after it is the successor.
+    pub successors: Vec<u16>,
+    // Ends with a `Bnnn` computed jump, whose targets are unknown
+    pub unknown_successors: bool,
+}
+
+impl BasicBlock {
+    // Address right after the last instruction of the block
+    pub fn end(&self) -> u16 {
+        let length: usize = self
+            .instructions
+            .iter()
+            .map(|(_, instruction)| instruction.length())
+            .sum();
+        self.start + length as u16
+    }
+}
+
+/**
+ *  Control flow of a ROM, found by following every jump, call and both
+ *  outcomes of skips from 0x200 without running anything. Code only reached
+ *  through `Bnnn` isn't found, those jumps are listed in `computed_jumps`.
+ */
+#[derive(PartialEq, Debug)]
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<u16, BasicBlock>,
+    // Subroutine (or 0x200 for the main program) to the subroutines it calls
+    pub calls: BTreeMap<u16, BTreeSet<u16>>,
+    // Addresses of `Bnnn` instructions
+    pub computed_jumps: Vec<u16>,
+    // Bytes of the program that are never reached as code, like sprites
+    pub data: Vec<Range<u16>>,
+}
+
+// Where execution can go after the instruction at `address`
+fn successors(address: u16, instruction: &Instruction) -> Vec<u16> {
+    let next = address + instruction.length() as u16;
+    match *instruction {
+        Instruction::Jump(target) => vec![target],
+        Instruction::Call(_) => vec![next],
+        Instruction::JumpBackward(offset) => {
+            vec![address.wrapping_sub(offset as u16) % MEMORY_SIZE as u16]
+        }
+        Instruction::JumpForward(offset) => vec![address + offset as u16],
+        Instruction::SkipNext => vec![next + 2],
+        Instruction::Return
+        | Instruction::JumpToPlusV0(_)
+        | Instruction::SkipBytes(_)
+        | Instruction::Stop
+        | Instruction::Exit => vec![],
+        Instruction::SkipIfEqual(_, _)
+        | Instruction::SkipIfNotEqual(_, _)
+        | Instruction::SkipIfEqualReg(_, _)
+        | Instruction::SkipIfNotEqualReg(_, _)
+        | Instruction::SkipIfKeyPressed(_)
+        | Instruction::SkipIfKeyNotPressed(_)
+        | Instruction::SkipIfKeyPressed2(_)
+        | Instruction::SkipIfKeyNotPressed2(_)
+        | Instruction::SkipIfGreater(_, _) => vec![next, next + 2],
+        _ => vec![next],
+    }
+}
+
+fn ends_block(instruction: &Instruction) -> bool {
+    successors(0, instruction) != [instruction.length() as u16]
+        || matches!(instruction, Instruction::Call(_))
+}
+
+// Whether a whole instruction fits in memory at `address`
+fn in_memory(address: u16) -> bool {
+    (address as usize) + 1 < MEMORY_SIZE
+}
+
+// Opcodes are decoded as `dialect` has them
+pub fn analyze(program: &[u8], dialect: Dialect) -> ControlFlowGraph {
+    let mut context = Context::new(program, 0);
+    context.dialect = dialect;
+
+    // Find every reachable instruction, and where blocks start
+    let mut code = BTreeMap::new();
+    let mut leaders = BTreeSet::from([PROGRAM_ADDRESS]);
+    let mut pending = vec![PROGRAM_ADDRESS];
+    while let Some(address) = pending.pop() {
+        if !in_memory(address) || code.contains_key(&address) {
+            continue;
+        }
+        let instruction = context.fetch_at(address);
+        code.insert(address, instruction);
+
+        let mut targets = successors(address, &instruction);
+        if let Instruction::Call(target) = instruction {
+            targets.push(target);
+        }
+        if ends_block(&instruction) {
+            leaders.extend(&targets);
+        }
+        pending.extend(targets);
+    }
+
+    let mut blocks = BTreeMap::new();
+    let mut computed_jumps = vec![];
+    for &start in leaders.iter().filter(|start| code.contains_key(start)) {
+        let mut instructions = vec![];
+        let mut address = start;
+        let (successors, unknown_successors) = loop {
+            let instruction = code[&address];
+            instructions.push((address, instruction));
+            if let Instruction::JumpToPlusV0(_) = instruction {
+                computed_jumps.push(address);
+            }
+
+            let next = address + instruction.length() as u16;
+            if ends_block(&instruction) {
+                let unknown = matches!(instruction, Instruction::JumpToPlusV0(_));
+                break (successors(address, &instruction), unknown);
+            }
+            if leaders.contains(&next) || !code.contains_key(&next) {
+                break (successors(address, &instruction), false);
+            }
+            address = next;
+        };
+        let successors = successors.into_iter().filter(|&address| in_memory(address));
+        let successors = successors.collect();
+
+        blocks.insert(
+            start,
+            BasicBlock {
+                start,
+                instructions,
+                successors,
+                unknown_successors,
+            },
+        );
+    }
+
+    let calls = call_graph(&blocks);
+    let data = data_regions(&code, program.len());
+    ControlFlowGraph {
+        blocks,
+        calls,
+        computed_jumps,
+        data,
+    }
+}
+
+// Walks the blocks of every subroutine to find the calls it makes
+fn call_graph(blocks: &BTreeMap<u16, BasicBlock>) -> BTreeMap<u16, BTreeSet<u16>> {
+    let mut calls = BTreeMap::new();
+    let mut pending = vec![PROGRAM_ADDRESS];
+
+    while let Some(entry) = pending.pop() {
+        if calls.contains_key(&entry) {
+            continue;
+        }
+        let mut callees = BTreeSet::new();
+        let mut visited = BTreeSet::new();
+        let mut walk = vec![entry];
+        while let Some(start) = walk.pop() {
+            let Some(block) = blocks.get(&start) else {
+                continue;
+            };
+            if !visited.insert(start) {
+                continue;
+            }
+            if let Some((_, Instruction::Call(target))) = block.instructions.last() {
+                callees.insert(*target);
+            }
+            walk.extend(&block.successors);
+        }
+        pending.extend(&callees);
+        calls.insert(entry, callees);
+    }
+
+    calls
+}
+
+// Ranges of the program not covered by any reachable instruction
+fn data_regions(code: &BTreeMap<u16, Instruction>, length: usize) -> Vec<Range<u16>> {
+    let start = PROGRAM_ADDRESS as usize;
+    let end = (start + length).min(MEMORY_SIZE);
+    let mut covered = vec![false; MEMORY_SIZE];
+    for (&address, instruction) in code {
+        let address = address as usize;
+        let end = (address + instruction.length()).min(MEMORY_SIZE);
+        covered[address..end].fill(true);
+    }
+
+    let mut regions: Vec<Range<u16>> = vec![];
+    for address in (start..end).filter(|address| !covered[*address]) {
+        let address = address as u16;
+        match regions.last_mut() {
+            Some(region) if region.end == address => region.end += 1,
+            _ => regions.push(address..address + 1),
+        }
+    }
+    regions
+}
+
+// Label of a subroutine, `start` for the main program
+pub fn label(entry: u16) -> String {
+    if entry == PROGRAM_ADDRESS {
+        "start".to_owned()
+    } else {
+        format!("sub_{entry:03X}")
+    }
+}
+
+impl ControlFlowGraph {
+    // `address` as an offset into the subroutine before it, like
+    // `sub_2A4+0x6`
+    pub fn symbolize(&self, address: u16) -> String {
+        match self.calls.range(..=address).next_back() {
+            Some((&entry, _)) if entry == address => label(entry),
+            Some((&entry, _)) => format!("{}+{:#X}", label(entry), address - entry),
+            None => format!("{address:#05X}"),
+        }
+    }
+
+    // The block containing the instruction at `address`
+    pub fn block_at(&self, address: u16) -> Option<&BasicBlock> {
+        let (_, block) = self.blocks.range(..=address).next_back()?;
+        (address < block.end()).then_some(block)
+    }
+
+    pub fn is_code(&self, address: u16) -> bool {
+        self.block_at(address).is_some_and(|block| {
+            block
+                .instructions
+                .iter()
+                .any(|(start, _)| *start == address)
+        })
+    }
+
+    // Graphviz graph with a node per block. Calls are dashed edges,
+    // computed jumps point to a `?` node.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph rom {\n    node [shape=box fontname=monospace];\n");
+        for block in self.blocks.values() {
+            let mut label = block
+                .instructions
+                .iter()
+                .map(|(address, instruction)| format!("{address:03X}  {instruction}\\l"))
+                .collect::<String>();
+            if self.calls.contains_key(&block.start) {
+                label.insert_str(0, &format!("{}:\\l", self::label(block.start)));
+            }
+            let _ = writeln!(dot, "    b{:03X} [label=\"{label}\"];", block.start);
+            for successor in &block.successors {
+                let _ = writeln!(dot, "    b{:03X} -> b{successor:03X};", block.start);
+            }
+            if let Some((_, Instruction::Call(target))) = block.instructions.last() {
+                let _ = writeln!(
+                    dot,
+                    "    b{:03X} -> b{target:03X} [style=dashed];",
+                    block.start
+                );
+            }
+            if block.unknown_successors {
+                let _ = writeln!(dot, "    b{:03X} -> unknown;", block.start);
+            }
+        }
+        if !self.computed_jumps.is_empty() {
+            dot.push_str("    unknown [label=\"?\" shape=circle];\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> String {
+        let blocks = self
+            .blocks
+            .values()
+            .map(|block| {
+                json!({
+                    "start": block.start,
+                    "end": block.end(),
+                    "instructions": block
+                        .instructions
+                        .iter()
+                        .map(|(address, instruction)| json!([address, instruction.to_string()]))
+                        .collect::<Vec<_>>(),
+                    "successors": block.successors,
+                    "unknownSuccessors": block.unknown_successors,
+                })
+            })
+            .collect::<Vec<_>>();
+        let calls = self
+            .calls
+            .iter()
+            .map(|(entry, callees)| (entry.to_string(), json!(callees)))
+            .collect::<serde_json::Map<_, _>>();
+        let data = self
+            .data
+            .iter()
+            .map(|region| json!({ "start": region.start, "end": region.end }))
+            .collect::<Vec<_>>();
+
+        let graph = json!({
+            "blocks": blocks,
+            "calls": calls,
+            "computedJumps": self.computed_jumps,
+            "data": data,
+        });
+        serde_json::to_string_pretty(&graph).expect("the graph is valid JSON")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::analyze;
+    use crate::{
+        instructions::Instruction,
+        parser::{Dialect, Variant},
+        test_data::DATA,
+    };
+
+    #[test]
+    fn find_ibm_logo_code_and_sprites() {
+        let graph = analyze(&DATA, Dialect::CHIP8);
+
+        assert_eq!(graph.blocks.len(), 2);
+        assert_eq!(graph.blocks[&0x200].instructions.len(), 20);
+        assert_eq!(graph.blocks[&0x200].successors, [0x228]);
+        let last = graph.block_at(0x228).unwrap();
+        assert_eq!(last.instructions, [(0x228, Instruction::Jump(0x228))]);
+        assert_eq!(last.successors, [0x228]);
+
+        assert_eq!(graph.data, vec![0x22A..0x284]);
+        assert!(graph.is_code(0x226));
+        assert!(!graph.is_code(0x22A));
+    }
+
+    #[test]
+    fn follow_skips_and_calls() {
+        let program = [
+            0x30, 0x01, // 200: SE V0, 0x01
+            0x22, 0x0A, // 202: CALL 0x20A
+            0x22, 0x0E, // 204: CALL 0x20E
+            0x12, 0x06, // 206: JP 0x206
+            0xFF, 0xFF, // 208: data
+            0x22, 0x0E, // 20A: CALL 0x20E
+            0x00, 0xEE, // 20C: RET
+            0x00, 0xEE, // 20E: RET
+        ];
+        let graph = analyze(&program, Dialect::CHIP8);
+
+        assert_eq!(graph.blocks[&0x200].successors, [0x202, 0x204]);
+        assert_eq!(graph.blocks[&0x202].successors, [0x204]);
+        assert_eq!(graph.calls[&0x200], BTreeSet::from([0x20A, 0x20E]));
+        assert_eq!(graph.calls[&0x20A], BTreeSet::from([0x20E]));
+        assert!(graph.calls[&0x20E].is_empty());
+        assert_eq!(graph.data, vec![0x208..0x20A]);
+
+        assert_eq!(graph.symbolize(0x200), "start");
+        assert_eq!(graph.symbolize(0x206), "start+0x6");
+        assert_eq!(graph.symbolize(0x20C), "sub_20A+0x2");
+        assert_eq!(graph.symbolize(0x1FE), "0x1FE");
+    }
+
+    #[test]
+    fn flag_computed_jumps() {
+        // LD V0, 2 ; JP V0, 0x206 ; data
+        let program = [0x60, 0x02, 0xB2, 0x06, 0xAB, 0xCD];
+        let graph = analyze(&program, Dialect::CHIP8);
+
+        assert_eq!(graph.computed_jumps, [0x202]);
+        let block = &graph.blocks[&0x200];
+        assert!(block.unknown_successors);
+        assert!(block.successors.is_empty());
+        assert_eq!(graph.data, vec![0x204..0x206]);
+    }
+
+    #[test]
+    fn follow_chip8e_jumps_and_skips() {
+        let program = [
+            0x01, 0x88, // 200: SKIP
+            0xFF, 0xFF, // 202: data
+            0xBF, 0x04, // 204: JP +4
+            0xFF, 0xFF, // 206: data
+            0x60, 0x01, // 208: LD V0, 0x01
+            0xBB, 0x06, // 20A: JP -6
+        ];
+        let graph = analyze(&program, Variant::Chip8E.dialect());
+
+        assert_eq!(graph.blocks[&0x200].successors, [0x204]);
+        assert_eq!(graph.blocks[&0x204].successors, [0x208]);
+        assert_eq!(graph.blocks[&0x208].successors, [0x204]);
+        assert_eq!(graph.data, vec![0x202..0x204, 0x206..0x208]);
+
+        // Plain CHIP-8 runs into the data instead
+        let graph = analyze(&program, Dialect::CHIP8);
+        assert!(graph.is_code(0x202));
+    }
+
+    #[test]
+    fn end_blocks_after_long_instructions() {
+        let program = [
+            0x01, 0x01, 0x23, 0x45, // 200: LDHI I, 0x012345
+            0x60, 0x01, // 204: LD V0, 0x01
+            0x12, 0x06, // 206: JP 0x206
+        ];
+        let graph = analyze(&program, Variant::MegaChip.dialect());
+
+        let first = &graph.blocks[&0x200];
+        assert_eq!(first.instructions.len(), 2);
+        assert_eq!(first.end(), 0x206);
+        assert_eq!(first.successors, [0x206]);
+        assert_eq!(graph.block_at(0x204), Some(first));
+        assert!(graph.is_code(0x204));
+        assert!(graph.data.is_empty());
+    }
+
+    #[test]
+    fn export_graph() {
+        let graph = analyze(&[0x22, 0x04, 0x12, 0x02, 0xB3, 0x00], Dialect::CHIP8);
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph rom {"));
+        assert!(dot.contains("b200 -> b202;"));
+        assert!(dot.contains("b200 -> b204 [style=dashed];"));
+        assert!(dot.contains("b204 -> unknown;"));
+        assert!(dot.contains("b204 [label=\"sub_204:\\l204  JP V0, 0x300\\l\"];"));
+
+        let json: serde_json::Value = serde_json::from_str(&graph.to_json()).unwrap();
+        assert_eq!(json["blocks"][0]["instructions"][0][1], "CALL 0x204");
+        assert_eq!(json["calls"]["512"][0], 0x204);
+        assert_eq!(json["computedJumps"][0], 0x204);
+    }
+}
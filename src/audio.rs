@@ -0,0 +1,77 @@
+// Amplitude of the generated wave, the volume is applied on playback
+const AMPLITUDE: i16 = i16::MAX / 4;
+
+// One second of a square wave as a 16-bit mono WAV file, meant to be played
+// in a loop while the sound timer is active.
+pub fn square_wave(frequency: f32, sample_rate: u32) -> Vec<u8> {
+    let samples = (0..sample_rate).map(|sample| {
+        let phase = (sample as f32 * frequency / sample_rate as f32).fract();
+        if phase < 0.5 {
+            AMPLITUDE
+        } else {
+            -AMPLITUDE
+        }
+    });
+
+    let mut wav = wav_header(sample_rate * 2, sample_rate, 2);
+    for sample in samples {
+        wav.extend(sample.to_le_bytes());
+    }
+    wav
+}
+
+// 8-bit unsigned mono samples, like MEGA-CHIP digitized sounds, as a WAV file
+pub fn pcm_wav(samples: &[u8], sample_rate: u32) -> Vec<u8> {
+    let mut wav = wav_header(samples.len() as u32, sample_rate, 1);
+    wav.extend(samples);
+    wav
+}
+
+// Header of a mono PCM WAV file with `data_size` bytes of samples
+fn wav_header(data_size: u32, sample_rate: u32, sample_bytes: u16) -> Vec<u8> {
+    let mut wav = Vec::with_capacity(44 + data_size as usize);
+    wav.extend(b"RIFF");
+    wav.extend((36 + data_size).to_le_bytes());
+    wav.extend(b"WAVE");
+    wav.extend(b"fmt ");
+    wav.extend(16u32.to_le_bytes());
+    wav.extend(1u16.to_le_bytes()); // PCM
+    wav.extend(1u16.to_le_bytes()); // Mono
+    wav.extend(sample_rate.to_le_bytes());
+    wav.extend((sample_rate * sample_bytes as u32).to_le_bytes()); // Bytes per second
+    wav.extend(sample_bytes.to_le_bytes()); // Bytes per sample
+    wav.extend((sample_bytes * 8).to_le_bytes()); // Bits per sample
+    wav.extend(b"data");
+    wav.extend(data_size.to_le_bytes());
+    wav
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pcm_wav, square_wave, AMPLITUDE};
+
+    #[test]
+    fn generate_square_wave() {
+        let wav = square_wave(2.0, 8);
+
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(wav.len(), 44 + 8 * 2);
+
+        let samples = wav[44..]
+            .chunks(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect::<Vec<i16>>();
+        let (high, low) = (AMPLITUDE, -AMPLITUDE);
+        assert_eq!(samples, [high, high, low, low, high, high, low, low]);
+    }
+
+    #[test]
+    fn wrap_8_bit_samples() {
+        let wav = pcm_wav(&[0x00, 0x80, 0xFF], 8000);
+
+        assert_eq!(wav.len(), 44 + 3);
+        assert_eq!(wav[24..28], 8000u32.to_le_bytes());
+        assert_eq!(wav[34..36], 8u16.to_le_bytes());
+        assert_eq!(wav[44..], [0x00, 0x80, 0xFF]);
+    }
+}
@@ -0,0 +1,262 @@
+use std::{collections::HashMap, ops::Range, rc::Rc};
+
+use crate::{context::Context, instructions::Instruction};
+
+// Upper bound for a single block, so long runs of straight-line code (or data
+// that happens to decode as such) don't get translated and invalidated as one
+// huge unit.
+const MAX_BLOCK_LENGTH: usize = 64;
+
+// A pre-decoded run of straight-line instructions. Only the last instruction
+// can change the program counter in a non-sequential way.
+pub struct Block {
+    pub start: u16,
+    pub instructions: Vec<Instruction>,
+}
+
+impl Block {
+    // Address right after the last instruction of the block
+    pub fn end(&self) -> u16 {
+        let length: usize = self.instructions.iter().map(Instruction::length).sum();
+        self.start + length as u16
+    }
+
+    fn overlaps(&self, range: &Range<usize>) -> bool {
+        (self.start as usize) < range.end && range.start < self.end() as usize
+    }
+}
+
+/**
+ *  Translates code into blocks the first time they're reached and runs whole
+ *  blocks afterwards, skipping the fetch and decode done by `Context::tick`.
+ *  Blocks are dropped as soon as the memory they were translated from is
+ *  written, so self-modifying code still sees its own changes.
+ */
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: HashMap<u16, Rc<Block>>,
+}
+
+impl BlockCache {
+    pub fn new() -> BlockCache {
+        BlockCache::default()
+    }
+
+    // Executes up to `budget` instructions and returns how many were executed.
+    // Blocks are cut short at the budget, so the caller can update timers at
+    // exactly the same instruction boundaries as when calling `Context::tick`.
+    pub fn run(&mut self, context: &mut Context, budget: usize) -> usize {
+        let mut executed = 0;
+
+        while executed < budget {
+            let start = context.program_counter;
+            let block = Rc::clone(
+                self.blocks
+                    .entry(start)
+                    .or_insert_with(|| Rc::new(translate(context, start))),
+            );
+
+            for instruction in block.instructions.iter().take(budget - executed) {
+                let written = context.memory_written(instruction);
+                context.execute(*instruction);
+                executed += 1;
+
+                if let Some(range) = written {
+                    self.invalidate(range.clone());
+                    // The rest of this block may have been overwritten, so
+                    // translate it again from the current program counter.
+                    if block.overlaps(&range) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        executed
+    }
+
+    // Drops every block translated from memory in `range`. Must be called
+    // after changing `memory_map` from outside of `run`.
+    pub fn invalidate(&mut self, range: Range<usize>) {
+        self.blocks.retain(|_, block| !block.overlaps(&range));
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+pub fn translate(context: &Context, start: u16) -> Block {
+    let mut instructions = vec![];
+    let mut address = start;
+
+    loop {
+        let instruction = context.fetch_at(address);
+        instructions.push(instruction);
+        address += instruction.length() as u16;
+
+        if ends_block(&instruction)
+            || instructions.len() == MAX_BLOCK_LENGTH
+            || (address + 1) as usize >= context.memory_map.len()
+        {
+            break;
+        }
+    }
+
+    Block {
+        start,
+        instructions,
+    }
+}
+
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Return
+            | Instruction::Jump(_)
+            | Instruction::Call(_)
+            | Instruction::JumpToPlusV0(_)
+            | Instruction::SkipIfEqual(_, _)
+            | Instruction::SkipIfNotEqual(_, _)
+            | Instruction::SkipIfEqualReg(_, _)
+            | Instruction::SkipIfNotEqualReg(_, _)
+            | Instruction::SkipIfKeyPressed(_)
+            | Instruction::SkipIfKeyNotPressed(_)
+            | Instruction::Display(_, _, _)
+            | Instruction::WaitForKey(_)
+            | Instruction::SkipIfKeyPressed2(_)
+            | Instruction::SkipIfKeyNotPressed2(_)
+            | Instruction::WaitForInput(_)
+            | Instruction::Stop
+            | Instruction::WaitForDelay
+            | Instruction::SkipNext
+            | Instruction::SkipIfGreater(_, _)
+            | Instruction::JumpBackward(_)
+            | Instruction::JumpForward(_)
+            | Instruction::SkipBytes(_)
+            | Instruction::DelayFor(_)
+            | Instruction::WaitForStrobe(_)
+            | Instruction::Exit
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    use super::BlockCache;
+    use crate::{context::Context, test_data::DATA};
+
+    fn assert_same_state(interpreter: &Context, compiled: &Context) {
+        assert_eq!(interpreter.program_counter, compiled.program_counter);
+        assert_eq!(interpreter.registers, compiled.registers);
+        assert_eq!(interpreter.i_register, compiled.i_register);
+        assert_eq!(interpreter.delay_timer, compiled.delay_timer);
+        assert_eq!(interpreter.sound_timer, compiled.sound_timer);
+        assert_eq!(interpreter.stack_pointer, compiled.stack_pointer);
+        assert_eq!(interpreter.memory_map, compiled.memory_map);
+        assert_eq!(interpreter.graphics_buffer, compiled.graphics_buffer);
+    }
+
+    // Runs `program` for `steps` instructions through both engines, comparing
+    // the state every `budget` instructions.
+    fn run_differential(program: &[u8], steps: usize, budget: usize) {
+        let mut interpreter = Context::new(program, 1);
+        let mut compiled = Context::new(program, 1);
+        let mut cache = BlockCache::new();
+
+        let mut executed = 0;
+        while executed < steps {
+            let budget = budget.min(steps - executed);
+            for _ in 0..budget {
+                interpreter.tick();
+            }
+            assert_eq!(cache.run(&mut compiled, budget), budget);
+            executed += budget;
+
+            assert_same_state(&interpreter, &compiled);
+        }
+    }
+
+    #[test]
+    fn run_ibm_logo_like_interpreter() {
+        for budget in [1, 3, 7, 64, 500] {
+            run_differential(&DATA, 500, budget);
+        }
+    }
+
+    #[test]
+    fn run_self_modifying_code() {
+        let test_data = [
+            0x60, 0x62, // V0 = 0x62
+            0x61, 0x77, // V1 = 0x77
+            0xA2, 0x0A, // I = 0x20A
+            0xF1, 0x55, // [I] = V0..V1, overwrites 0x20A with 6277
+            0x63, 0x01, // V3 = 0x01
+            0x62, 0x00, // V2 = 0x00
+            0x12, 0x0C, // Jump to 0x20C
+        ];
+        let mut context = Context::new(&test_data, 1);
+        let mut cache = BlockCache::new();
+        cache.run(&mut context, 10);
+
+        assert_eq!(context.registers[2], 0x77);
+        run_differential(&test_data, 10, 10);
+    }
+
+    #[test]
+    fn invalidate_blocks_in_range() {
+        let mut context = Context::new(&DATA, 1);
+        let mut cache = BlockCache::new();
+        cache.run(&mut context, 50);
+        let translated = cache.len();
+
+        // Only the first block starts in this range
+        cache.invalidate(0x200..0x202);
+        assert_eq!(cache.len(), translated - 1);
+
+        cache.invalidate(0x000..0x1000);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn run_random_programs_like_interpreter() {
+        let mut rng = SmallRng::seed_from_u64(8);
+        for _ in 0..100 {
+            // Only opcodes that can't fault, with jumps and I kept inside the program
+            let length: u16 = 32;
+            let mut program = (0..length)
+                .flat_map(|_| {
+                    let x = rng.gen_range(0..0xF);
+                    let y = rng.gen_range(0..0xF);
+                    let kk = rng.gen::<u8>();
+                    let address = 0x200 + 2 * rng.gen_range(0..length);
+                    let opcode: u16 = match rng.gen_range(0..9) {
+                        0 => 0x6000 | x << 8 | kk as u16,
+                        1 => 0x8001 | x << 8 | y << 4,
+                        2 => 0x8002 | x << 8 | y << 4,
+                        3 => 0x8003 | x << 8 | y << 4,
+                        4 => 0xC000 | x << 8 | kk as u16,
+                        5 => 0x3000 | x << 8 | (kk & 0x3) as u16,
+                        6 => 0x4000 | x << 8 | (kk & 0x3) as u16,
+                        7 => 0xA000 | address,
+                        _ => 0x1000 | address,
+                    };
+                    opcode.to_be_bytes()
+                })
+                .collect::<Vec<u8>>();
+            // Skips on the last instruction land on the second jump
+            program.extend([0x12, 0x00, 0x12, 0x00]);
+
+            run_differential(&program, 200, rng.gen_range(1..40));
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// Writes RGBA pixels to a PNG file
+pub fn save_png(path: &Path, frame: &[u8], width: usize, height: usize) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(frame).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+enum Output {
+    Gif(gif::Encoder<BufWriter<File>>),
+    // Directory where numbered frames are written
+    PngSequence(PathBuf),
+}
+
+/**
+ *  Records every frame of a session, either to an animated GIF or to a
+ *  directory of numbered PNG files (`frame_00000.png`, ...). Each frame is
+ *  pushed with the time it was shown, and the GIF keeps the time until the
+ *  next frame, or until the end of the recording, as its delay.
+ */
+pub struct Recorder {
+    output: Output,
+    width: usize,
+    height: usize,
+    // When the first frame was shown, times are kept relative to it
+    start: Option<Duration>,
+    // The last GIF frame and when it was shown, written once the next frame
+    // or the end of the recording tells how long it stayed
+    pending: Option<(Vec<u8>, Duration)>,
+    frames: u32,
+}
+
+impl Recorder {
+    // Records to a GIF when `path` ends in `.gif`, otherwise `path` is used as
+    // the directory for a PNG sequence.
+    pub fn create(path: &Path, width: usize, height: usize) -> io::Result<Recorder> {
+        let is_gif = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+
+        let output = if is_gif {
+            let file = BufWriter::new(File::create(path)?);
+            let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &[])
+                .map_err(io::Error::other)?;
+            encoder
+                .set_repeat(gif::Repeat::Infinite)
+                .map_err(io::Error::other)?;
+            Output::Gif(encoder)
+        } else {
+            fs::create_dir_all(path)?;
+            Output::PngSequence(path.to_path_buf())
+        };
+
+        Ok(Recorder {
+            output,
+            width,
+            height,
+            start: None,
+            pending: None,
+            frames: 0,
+        })
+    }
+
+    // Adds a frame shown at `time`, on any clock that doesn't go back
+    pub fn push_frame(&mut self, frame: &[u8], time: Duration) -> io::Result<()> {
+        let start = *self.start.get_or_insert(time);
+        let time = time.saturating_sub(start);
+        match &self.output {
+            Output::Gif(_) => {
+                self.write_pending(time)?;
+                self.pending = Some((frame.to_vec(), time));
+            }
+            Output::PngSequence(directory) => {
+                let path = directory.join(format!("frame_{:05}.png", self.frames));
+                save_png(&path, frame, self.width, self.height)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Writes the pending GIF frame, shown until `end`
+    fn write_pending(&mut self, end: Duration) -> io::Result<()> {
+        let (Output::Gif(encoder), Some((mut pixels, shown))) =
+            (&mut self.output, self.pending.take())
+        else {
+            return Ok(());
+        };
+        let mut gif_frame =
+            gif::Frame::from_rgba_speed(self.width as u16, self.height as u16, &mut pixels, 10);
+        gif_frame.delay = gif_delay(shown, end);
+        encoder.write_frame(&gif_frame).map_err(io::Error::other)
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    // Ends the recording at `time`, on the clock of `push_frame`. Dropping
+    // the recorder also ends the GIF, but without its last frame and without
+    // reporting errors.
+    pub fn finish(mut self, time: Duration) -> io::Result<()> {
+        let end = time.saturating_sub(self.start.unwrap_or(time));
+        self.write_pending(end)?;
+        if let Output::Gif(encoder) = self.output {
+            encoder.into_inner()?;
+        }
+        Ok(())
+    }
+}
+
+// GIF delays are in hundredths of a second. Rounding the times frames are
+// shown instead of their durations keeps the total length of the animation
+// right, e.g. 60 FPS alternates between 2 and 1.
+pub fn gif_delay(shown: Duration, end: Duration) -> u16 {
+    let centiseconds = |time: Duration| (time.as_micros() + 5_000) / 10_000;
+    (centiseconds(end) - centiseconds(shown)) as u16
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, time::Duration};
+
+    use super::{gif_delay, save_png, Recorder};
+
+    #[test]
+    fn keep_gif_delays_in_sync() {
+        let frame_duration = Duration::from_millis(60);
+        let delay = |frame| gif_delay(frame_duration * frame, frame_duration * (frame + 1));
+        assert!((0..10).all(|frame| delay(frame) == 6));
+
+        let frame_duration = Duration::from_secs(1) / 60;
+        let total = (0..60)
+            .map(|frame| gif_delay(frame_duration * frame, frame_duration * (frame + 1)) as u32)
+            .sum::<u32>();
+        assert_eq!(total, 100);
+    }
+
+    #[test]
+    fn save_png_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("screenshot.png");
+        let frame = [0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF, 0xFF];
+        save_png(&path, &frame, 2, 1).unwrap();
+
+        let decoder = png::Decoder::new(fs::File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(pixels, frame);
+    }
+
+    #[test]
+    fn record_png_sequence() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("recording");
+        let mut recorder = Recorder::create(&path, 1, 1).unwrap();
+        let duration = Duration::from_millis(60);
+        recorder
+            .push_frame(&[0, 0, 0, 0xFF], Duration::ZERO)
+            .unwrap();
+        recorder
+            .push_frame(&[0xFF, 0xFF, 0xFF, 0xFF], duration)
+            .unwrap();
+        assert_eq!(recorder.frames(), 2);
+        recorder.finish(duration * 2).unwrap();
+
+        assert!(path.join("frame_00000.png").exists());
+        assert!(path.join("frame_00001.png").exists());
+    }
+
+    #[test]
+    fn record_gif_with_frame_times() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("recording.gif");
+        let mut recorder = Recorder::create(&path, 2, 1).unwrap();
+        // Shown at 1s, 1.06s and 1.185s, and the recording stops at 1.4s
+        let frames: [(&[u8], u64); 3] = [
+            (&[0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF], 1000),
+            (&[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0xFF], 1060),
+            (&[0, 0, 0, 0xFF, 0, 0, 0, 0xFF], 1185),
+        ];
+        for (frame, time) in frames {
+            recorder
+                .push_frame(frame, Duration::from_millis(time))
+                .unwrap();
+        }
+        recorder.finish(Duration::from_millis(1400)).unwrap();
+
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(fs::File::open(&path).unwrap())
+            .unwrap();
+        let mut delays = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        assert_eq!(delays, [6, 13, 21]);
+    }
+}
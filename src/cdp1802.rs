@@ -0,0 +1,439 @@
+// Everything the CPU is wired to: memory, the I/O ports and the EF flags
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+    // OUT 1 - 7, `value` is the byte at R(X)
+    fn output(&mut self, port: u8, value: u8);
+    // INP 1 - 7
+    fn input(&mut self, port: u8) -> u8;
+    // Whether EF1 - EF4 is asserted
+    fn flag(&self, flag: u8) -> bool;
+}
+
+/**
+ *  The RCA CDP1802 CPU of the COSMAC VIP. Instructions take 2 machine
+ *  cycles, long branches and skips 3, and interrupts and DMA cycles 1.
+ *  Registers start at 0 on reset, with interrupts enabled.
+ */
+#[derive(Clone, Debug)]
+pub struct Cdp1802 {
+    pub r: [u16; 16],
+    pub d: u8,
+    pub df: bool,
+    // Index of the program counter register
+    pub p: u8,
+    // Index of the data pointer register
+    pub x: u8,
+    // X and P saved by interrupts and MARK
+    pub t: u8,
+    pub ie: bool,
+    pub q: bool,
+    // Set by IDL until the next interrupt or DMA cycle
+    pub idle: bool,
+}
+
+impl Default for Cdp1802 {
+    fn default() -> Self {
+        Cdp1802 {
+            r: [0; 16],
+            d: 0,
+            df: false,
+            p: 0,
+            x: 0,
+            t: 0,
+            ie: true,
+            q: false,
+            idle: false,
+        }
+    }
+}
+
+impl Cdp1802 {
+    pub fn new() -> Cdp1802 {
+        Cdp1802::default()
+    }
+
+    // Runs one instruction and returns the machine cycles it took
+    pub fn step(&mut self, bus: &mut impl Bus) -> u32 {
+        if self.idle {
+            return 1;
+        }
+        let opcode = self.immediate(bus);
+        let (high, n) = (opcode >> 4, opcode & 0xF);
+        let n_usize = n as usize;
+        match high {
+            0x0 if n == 0 => self.idle = true,
+            0x0 => self.d = bus.read(self.r[n_usize]),
+            0x1 => self.r[n_usize] = self.r[n_usize].wrapping_add(1),
+            0x2 => self.r[n_usize] = self.r[n_usize].wrapping_sub(1),
+            0x3 => {
+                // 38 is SKP, the opposite of always branching
+                let taken = self.condition(n & 0x7, bus) != (n & 0x8 != 0);
+                self.short_branch(bus, taken);
+            }
+            0x4 => {
+                self.d = bus.read(self.r[n_usize]);
+                self.r[n_usize] = self.r[n_usize].wrapping_add(1);
+            }
+            0x5 => bus.write(self.r[n_usize], self.d),
+            0x6 => self.input_output(bus, n),
+            0x7 => self.control(bus, n),
+            0x8 => self.d = self.r[n_usize] as u8,
+            0x9 => self.d = (self.r[n_usize] >> 8) as u8,
+            0xA => self.r[n_usize] = self.r[n_usize] & 0xFF00 | self.d as u16,
+            0xB => self.r[n_usize] = self.r[n_usize] & 0x00FF | (self.d as u16) << 8,
+            0xC => {
+                self.long_branch(bus, n);
+                return 3;
+            }
+            0xD => self.p = n,
+            0xE => self.x = n,
+            _ => self.arithmetic(bus, n),
+        }
+        2
+    }
+
+    // Saves X and P to T and jumps to R(1) with R(2) as the data pointer
+    pub fn interrupt(&mut self) {
+        self.t = self.x << 4 | self.p;
+        self.x = 2;
+        self.p = 1;
+        self.ie = false;
+        self.idle = false;
+    }
+
+    // A DMA out cycle, reads the byte at R(0) for the device
+    pub fn dma_out(&mut self, bus: &mut impl Bus) -> u8 {
+        let value = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        value
+    }
+
+    // Reads the byte at the program counter and moves past it
+    fn immediate(&mut self, bus: &mut impl Bus) -> u8 {
+        let p = self.p as usize;
+        let value = bus.read(self.r[p]);
+        self.r[p] = self.r[p].wrapping_add(1);
+        value
+    }
+
+    fn data_pointer(&self) -> u16 {
+        self.r[self.x as usize]
+    }
+
+    fn increment_x(&mut self) {
+        let x = self.x as usize;
+        self.r[x] = self.r[x].wrapping_add(1);
+    }
+
+    // Branch conditions shared by short and long branches: always, Q, D = 0,
+    // DF, then EF1 - EF4
+    fn condition(&self, condition: u8, bus: &impl Bus) -> bool {
+        match condition {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            flag => bus.flag(flag - 3),
+        }
+    }
+
+    fn short_branch(&mut self, bus: &mut impl Bus, taken: bool) {
+        let p = self.p as usize;
+        if taken {
+            let target = bus.read(self.r[p]);
+            self.r[p] = self.r[p] & 0xFF00 | target as u16;
+        } else {
+            self.r[p] = self.r[p].wrapping_add(1);
+        }
+    }
+
+    // C0 - CF, branches to the next 2 bytes or skips them
+    fn long_branch(&mut self, bus: &mut impl Bus, n: u8) {
+        let p = self.p as usize;
+        let skip = match n {
+            // NOP
+            0x4 => false,
+            0x5 => !self.q,
+            0x6 => self.d != 0,
+            0x7 => !self.df,
+            0xC => self.ie,
+            0xD => self.q,
+            0xE => self.d == 0,
+            0xF => self.df,
+            // Branches, C8 is LSKP, the opposite of always branching
+            _ => {
+                if self.condition(n & 0x3, bus) != (n & 0x8 != 0) {
+                    let high = bus.read(self.r[p]);
+                    let low = bus.read(self.r[p].wrapping_add(1));
+                    self.r[p] = u16::from_be_bytes([high, low]);
+                    return;
+                }
+                true
+            }
+        };
+        if skip {
+            self.r[p] = self.r[p].wrapping_add(2);
+        }
+    }
+
+    // 60 IRX, 61 - 67 OUT, 68 is unused, 69 - 6F INP
+    fn input_output(&mut self, bus: &mut impl Bus, n: u8) {
+        match n {
+            0x0 => self.increment_x(),
+            0x1..=0x7 => {
+                let value = bus.read(self.data_pointer());
+                bus.output(n, value);
+                self.increment_x();
+            }
+            0x8 => {}
+            _ => {
+                let value = bus.input(n & 0x7);
+                bus.write(self.data_pointer(), value);
+                self.d = value;
+            }
+        }
+    }
+
+    fn control(&mut self, bus: &mut impl Bus, n: u8) {
+        match n {
+            // RET and DIS
+            0x0 | 0x1 => {
+                let value = bus.read(self.data_pointer());
+                self.increment_x();
+                self.x = value >> 4;
+                self.p = value & 0xF;
+                self.ie = n == 0x0;
+            }
+            // LDXA
+            0x2 => {
+                self.d = bus.read(self.data_pointer());
+                self.increment_x();
+            }
+            // STXD
+            0x3 => {
+                bus.write(self.data_pointer(), self.d);
+                let x = self.x as usize;
+                self.r[x] = self.r[x].wrapping_sub(1);
+            }
+            // SAV
+            0x8 => bus.write(self.data_pointer(), self.t),
+            // MARK
+            0x9 => {
+                self.t = self.x << 4 | self.p;
+                bus.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            0xA => self.q = false,
+            0xB => self.q = true,
+            // SHRC and SHLC
+            0x6 => {
+                let carry = self.d & 0x1 != 0;
+                self.d = self.d >> 1 | (self.df as u8) << 7;
+                self.df = carry;
+            }
+            0xE => {
+                let carry = self.d & 0x80 != 0;
+                self.d = self.d << 1 | self.df as u8;
+                self.df = carry;
+            }
+            // ADC, SDB, SMB, then their immediate forms
+            _ => {
+                let operand = if n & 0x8 != 0 {
+                    self.immediate(bus)
+                } else {
+                    bus.read(self.data_pointer())
+                };
+                self.add_or_subtract(n & 0x7, operand, self.df);
+            }
+        }
+    }
+
+    // F0 - FF, LDX and the logic and arithmetic on M(R(X)), with immediate
+    // forms from F8
+    fn arithmetic(&mut self, bus: &mut impl Bus, n: u8) {
+        match n {
+            0x6 => {
+                self.df = self.d & 0x1 != 0;
+                self.d >>= 1;
+            }
+            0xE => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            }
+            _ => {
+                let operand = if n & 0x8 != 0 {
+                    self.immediate(bus)
+                } else {
+                    bus.read(self.data_pointer())
+                };
+                match n & 0x7 {
+                    0x0 => self.d = operand,
+                    0x1 => self.d |= operand,
+                    0x2 => self.d &= operand,
+                    0x3 => self.d ^= operand,
+                    // ADD, SD and SM have no carry in, so no borrow
+                    operation => self.add_or_subtract(operation, operand, operation != 0x4),
+                }
+            }
+        }
+    }
+
+    // 4 adds, 5 subtracts D from the operand and 7 the operand from D. DF is
+    // the carry, or 1 when there's no borrow.
+    fn add_or_subtract(&mut self, operation: u8, operand: u8, carry: bool) {
+        let (a, b) = match operation {
+            0x4 => {
+                let sum = self.d as u16 + operand as u16 + carry as u16;
+                self.d = sum as u8;
+                self.df = sum > 0xFF;
+                return;
+            }
+            0x5 => (operand, self.d),
+            _ => (self.d, operand),
+        };
+        let difference = a as i16 - b as i16 - !carry as i16;
+        self.d = difference as u8;
+        self.df = difference >= 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Bus, Cdp1802};
+
+    struct Memory {
+        bytes: Vec<u8>,
+        flags: [bool; 4],
+        outputs: Vec<(u8, u8)>,
+    }
+
+    impl Bus for Memory {
+        fn read(&mut self, address: u16) -> u8 {
+            self.bytes[address as usize % self.bytes.len()]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            let length = self.bytes.len();
+            self.bytes[address as usize % length] = value;
+        }
+
+        fn output(&mut self, port: u8, value: u8) {
+            self.outputs.push((port, value));
+        }
+
+        fn input(&mut self, port: u8) -> u8 {
+            0x10 | port
+        }
+
+        fn flag(&self, flag: u8) -> bool {
+            self.flags[flag as usize - 1]
+        }
+    }
+
+    // Runs `program` from 0 until the CPU reaches `end`, returns the cycles
+    fn run(program: &[u8], end: u16) -> (Cdp1802, Memory, u32) {
+        let mut bytes = vec![0; 0x100];
+        bytes[..program.len()].copy_from_slice(program);
+        let mut memory = Memory {
+            bytes,
+            flags: [false, false, true, false],
+            outputs: vec![],
+        };
+        let mut cpu = Cdp1802::new();
+        let mut cycles = 0;
+        while cpu.r[cpu.p as usize] != end {
+            cycles += cpu.step(&mut memory);
+        }
+        (cpu, memory, cycles)
+    }
+
+    #[test]
+    fn load_and_store() {
+        // LDI 80; PLO R5; LDI 42; STR R5; LDI 0; LDN R5; PHI R6
+        let (cpu, memory, cycles) = run(
+            &[0xF8, 0x80, 0xA5, 0xF8, 0x42, 0x55, 0xF8, 0x00, 0x05, 0xB6],
+            10,
+        );
+        assert_eq!(memory.bytes[0x80], 0x42);
+        assert_eq!(cpu.r[5], 0x0080);
+        assert_eq!(cpu.r[6], 0x4200);
+        assert_eq!(cycles, 7 * 2);
+    }
+
+    #[test]
+    fn add_and_subtract() {
+        // SEX R3 with R3 pointing at 0xF0: LDI F0; PLO R3; SEX R3
+        let setup = [0xF8, 0xF0, 0xA3, 0xE3];
+        let cases = [
+            // LDI 80; ADI 90
+            ([0xF8, 0x80, 0xFC, 0x90], 0x10, true),
+            // LDI 10; SMI 20
+            ([0xF8, 0x10, 0xFF, 0x20], 0xF0, false),
+            // LDI 10; SDI 20
+            ([0xF8, 0x10, 0xFD, 0x20], 0x10, true),
+            // LDI 81; SHL, then DF
+            ([0xF8, 0x81, 0xFE, 0xC4], 0x02, true),
+        ];
+        for (code, d, df) in cases {
+            let program = [&setup[..], &code[..]].concat();
+            let (cpu, _, _) = run(&program, program.len() as u16);
+            assert_eq!((cpu.d, cpu.df), (d, df), "{code:02X?}");
+        }
+        // LDI FF; ADI 1 sets DF, then LDI 0; ADCI 0 adds it
+        let (cpu, _, _) = run(&[0xF8, 0xFF, 0xFC, 0x01, 0xF8, 0x00, 0x7C, 0x00], 8);
+        assert_eq!(cpu.d, 1);
+    }
+
+    #[test]
+    fn branch() {
+        // LDI 0; BZ 06; LDI 1; LBR 000B; IDL; IDL; LDI 2; B3 20
+        let program = [
+            0xF8, 0x00, 0x32, 0x06, 0xF8, 0x01, 0xC0, 0x00, 0x0B, 0x00, 0x00, 0xF8, 0x02, 0x36,
+            0x20,
+        ];
+        let mut memory = Memory {
+            bytes: [&program[..], &[0; 0x100]].concat(),
+            flags: [false, false, true, false],
+            outputs: vec![],
+        };
+        let mut cpu = Cdp1802::new();
+        let mut cycles = 0;
+        while cpu.r[0] != 0x20 {
+            cycles += cpu.step(&mut memory);
+        }
+        assert_eq!(cpu.d, 2);
+        assert_eq!(cycles, 2 + 2 + 3 + 2 + 2);
+    }
+
+    #[test]
+    fn call_with_mark_and_return() {
+        // LDI 0x40; PLO R2; SEX R2; SEQ; LDI 0x20; PLO R3; MARK; SEP R3
+        // then at 0x20: SEX R2; IRX; REQ; RET
+        let mut program = vec![0xF8, 0x40, 0xA2, 0xE2, 0x7B, 0xF8, 0x20, 0xA3, 0x79, 0xD3];
+        program.resize(0x20, 0);
+        program.extend([0xE2, 0x60, 0x7A, 0x70]);
+        let (cpu, memory, _) = run(&program, 0x0A);
+        assert_eq!(memory.bytes[0x40], 0x20);
+        assert_eq!((cpu.x, cpu.p, cpu.q, cpu.ie), (2, 0, false, true));
+    }
+
+    #[test]
+    fn input_output_and_dma() {
+        // LDI 0x80; PLO R1; SEX R1; LDI 7; STR R1; OUT 2; INP 3
+        let (mut cpu, mut memory, _) =
+            run(&[0xF8, 0x80, 0xA1, 0xE1, 0xF8, 0x07, 0x51, 0x62, 0x6B], 9);
+        assert_eq!(memory.outputs, [(2, 7)]);
+        assert_eq!(memory.bytes[0x81], 0x13);
+        assert_eq!(cpu.d, 0x13);
+
+        cpu.r[0] = 0x80;
+        assert_eq!(cpu.dma_out(&mut memory), 7);
+        assert_eq!(cpu.r[0], 0x81);
+
+        cpu.interrupt();
+        assert_eq!((cpu.t, cpu.x, cpu.p, cpu.ie), (0x10, 2, 1, false));
+    }
+}
@@ -0,0 +1,356 @@
+use std::{fmt, fs, io, path::Path};
+
+use crate::context::{Context, MEMORY_SIZE};
+
+#[derive(Debug)]
+pub enum CheatError {
+    Io(io::Error),
+    // Line number of the invalid cheat, starting at 1
+    Parse(usize),
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheatError::Io(error) => write!(f, "{error}"),
+            CheatError::Parse(line) => write!(f, "invalid cheat on line {line}"),
+        }
+    }
+}
+
+// A byte of emulator state a cheat can look at or change
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Target {
+    Memory(u16),
+    Register(u8),
+}
+
+impl Target {
+    pub fn read(&self, context: &Context) -> u8 {
+        match self {
+            Target::Memory(address) => context.memory_map[*address as usize],
+            Target::Register(x) => context.registers[*x as usize],
+        }
+    }
+
+    pub fn write(&self, context: &mut Context, value: u8) {
+        match self {
+            Target::Memory(address) => context.memory_map[*address as usize] = value,
+            Target::Register(x) => context.registers[*x as usize] = value,
+        }
+    }
+
+    // `V0` - `VF`, or a memory address like `0x3F0`
+    pub fn parse(source: &str) -> Option<Target> {
+        match source.strip_prefix(['V', 'v']) {
+            Some(register) if register.len() == 1 => {
+                u8::from_str_radix(register, 16).ok().map(Target::Register)
+            }
+            _ => parse_number(source)
+                .filter(|address| (*address as usize) < MEMORY_SIZE)
+                .map(Target::Memory),
+        }
+    }
+}
+
+// In the format `parse` reads
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Memory(address) => write!(f, "{address:#05X}"),
+            Target::Register(x) => write!(f, "V{x:X}"),
+        }
+    }
+}
+
+// How the value of a target compares to the previous snapshot
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Comparison {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+/**
+ *  Narrows down where a game keeps a value, like the number of lives. Starts
+ *  with every register and memory address, and every `refine` keeps the ones
+ *  that match the comparison against the snapshot of the previous step.
+ */
+pub struct Search {
+    candidates: Vec<(Target, u8)>,
+}
+
+impl Search {
+    pub fn new(context: &Context) -> Search {
+        let registers = (0..16).map(Target::Register);
+        let memory = (0..MEMORY_SIZE as u16).map(Target::Memory);
+        let candidates = registers
+            .chain(memory)
+            .map(|target| (target, target.read(context)))
+            .collect();
+        Search { candidates }
+    }
+
+    pub fn refine(&mut self, context: &Context, comparison: Comparison) {
+        self.candidates.retain_mut(|(target, snapshot)| {
+            let value = target.read(context);
+            let matches = match comparison {
+                Comparison::Equal(expected) => value == expected,
+                Comparison::Changed => value != *snapshot,
+                Comparison::Unchanged => value == *snapshot,
+                Comparison::Increased => value > *snapshot,
+                Comparison::Decreased => value < *snapshot,
+            };
+            *snapshot = value;
+            matches
+        });
+    }
+
+    pub fn candidates(&self) -> impl Iterator<Item = Target> + '_ {
+        self.candidates.iter().map(|(target, _)| *target)
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Effect {
+    // Keeps the target at a value
+    Freeze(Target, u8),
+    // Replaces the instruction at an address
+    Patch(u16, u16),
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Cheat {
+    pub name: String,
+    pub effect: Effect,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    // Keeps a target at the value it has now, e.g. a match of a search
+    pub fn freeze(target: Target, context: &Context) -> Cheat {
+        Cheat {
+            name: target.to_string(),
+            effect: Effect::Freeze(target, target.read(context)),
+            enabled: true,
+        }
+    }
+
+    pub fn apply(&self, context: &mut Context) {
+        match self.effect {
+            Effect::Freeze(target, value) => target.write(context, value),
+            Effect::Patch(address, opcode) => {
+                let address = address as usize;
+                context.memory_map[address..address + 2].copy_from_slice(&opcode.to_be_bytes());
+            }
+        }
+    }
+}
+
+/**
+ *  Cheats in a text file, one per line. Lines have an optional name, then
+ *  the effect. Numbers are decimal, or hex with `0x`. Patched opcodes are
+ *  always hex.
+ *
+ *  ```text
+ *  # Comments start with #
+ *  Infinite lives: freeze 0x3F0 = 3
+ *  freeze V5 = 0x10
+ *  No collisions: patch 0x2A4 = 1200
+ *  ```
+ */
+#[derive(PartialEq, Eq, Default, Debug)]
+pub struct CheatList {
+    pub cheats: Vec<Cheat>,
+}
+
+impl CheatList {
+    pub fn load(path: &Path) -> Result<CheatList, CheatError> {
+        let source = fs::read_to_string(path).map_err(CheatError::Io)?;
+        CheatList::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<CheatList, CheatError> {
+        let mut cheats = vec![];
+        for (index, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let cheat = parse_cheat(line).ok_or(CheatError::Parse(index + 1))?;
+            cheats.push(cheat);
+        }
+        Ok(CheatList { cheats })
+    }
+
+    // Must be called every frame for frozen values to stick
+    pub fn apply(&self, context: &mut Context) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            cheat.apply(context);
+        }
+    }
+}
+
+fn parse_cheat(line: &str) -> Option<Cheat> {
+    let (name, effect) = match line.split_once(':') {
+        Some((name, effect)) => (name.trim(), effect.trim()),
+        None => ("", line),
+    };
+    let (command, arguments) = effect.split_once(' ')?;
+    let (target, value) = arguments.split_once('=')?;
+    let (target, value) = (target.trim(), value.trim());
+
+    let effect = match command {
+        "freeze" => Effect::Freeze(
+            Target::parse(target)?,
+            parse_number(value)?.try_into().ok()?,
+        ),
+        "patch" => {
+            let address =
+                parse_number(target).filter(|address| *address < MEMORY_SIZE as u16 - 1)?;
+            let opcode = value.strip_prefix("0x").unwrap_or(value);
+            if opcode.len() != 4 {
+                return None;
+            }
+            Effect::Patch(address, u16::from_str_radix(opcode, 16).ok()?)
+        }
+        _ => return None,
+    };
+
+    Some(Cheat {
+        name: name.to_owned(),
+        effect,
+        enabled: true,
+    })
+}
+
+fn parse_number(source: &str) -> Option<u16> {
+    match source.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => source.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cheat, CheatError, CheatList, Comparison, Effect, Search, Target};
+    use crate::context::Context;
+
+    const CHEATS: &str = "
+        # Lives of the player
+        Infinite lives: freeze 0x3F0 = 3
+        freeze VA = 0x10
+
+        Skip: patch 0x200 = 1206
+    ";
+
+    #[test]
+    fn find_value_across_snapshots() {
+        let mut context = Context::new(&[], 1);
+        context.memory_map[0x300] = 3;
+        context.memory_map[0x301] = 3;
+        context.registers[0x2] = 3;
+        let mut search = Search::new(&context);
+
+        search.refine(&context, Comparison::Equal(3));
+        assert_eq!(search.len(), 3);
+
+        context.memory_map[0x300] = 2;
+        context.memory_map[0x301] = 4;
+        search.refine(&context, Comparison::Decreased);
+        assert_eq!(
+            search.candidates().collect::<Vec<Target>>(),
+            [Target::Memory(0x300)]
+        );
+
+        search.refine(&context, Comparison::Unchanged);
+        assert_eq!(search.len(), 1);
+        search.refine(&context, Comparison::Changed);
+        assert!(search.is_empty());
+    }
+
+    #[test]
+    fn freeze_search_match() {
+        let mut context = Context::new(&[], 1);
+        context.memory_map[0x3F0] = 3;
+        context.registers[0xA] = 7;
+
+        let lives = Cheat::freeze(Target::Memory(0x3F0), &context);
+        assert_eq!(lives.name, "0x3F0");
+        assert_eq!(lives.effect, Effect::Freeze(Target::Memory(0x3F0), 3));
+        assert_eq!(Target::parse(&lives.name), Some(Target::Memory(0x3F0)));
+
+        let register = Cheat::freeze(Target::Register(0xA), &context);
+        assert_eq!(register.name, "VA");
+        assert_eq!(register.effect, Effect::Freeze(Target::Register(0xA), 7));
+
+        context.memory_map[0x3F0] = 2;
+        lives.apply(&mut context);
+        assert_eq!(context.memory_map[0x3F0], 3);
+    }
+
+    #[test]
+    fn parse_cheat_list() {
+        let list = CheatList::parse(CHEATS).unwrap();
+
+        assert_eq!(
+            list.cheats,
+            [
+                Cheat {
+                    name: "Infinite lives".to_owned(),
+                    effect: Effect::Freeze(Target::Memory(0x3F0), 3),
+                    enabled: true,
+                },
+                Cheat {
+                    name: String::new(),
+                    effect: Effect::Freeze(Target::Register(0xA), 0x10),
+                    enabled: true,
+                },
+                Cheat {
+                    name: "Skip".to_owned(),
+                    effect: Effect::Patch(0x200, 0x1206),
+                    enabled: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_invalid_cheats() {
+        assert!(matches!(
+            CheatList::parse("freeze 0x3F0 = 3\nfreeze VG = 1"),
+            Err(CheatError::Parse(2))
+        ));
+        assert!(CheatList::parse("freeze 0x1000 = 3").is_err());
+        assert!(CheatList::parse("freeze V0 = 256").is_err());
+        assert!(CheatList::parse("patch 0x200 = 12").is_err());
+    }
+
+    #[test]
+    fn apply_enabled_cheats() {
+        // LD VA, 0 ; JP 0x200
+        let mut context = Context::new(&[0x6A, 0x00, 0x12, 0x00], 1);
+        let mut list = CheatList::parse(CHEATS).unwrap();
+        list.cheats[2].enabled = false;
+
+        context.tick();
+        list.apply(&mut context);
+        assert_eq!(context.memory_map[0x3F0], 3);
+        assert_eq!(context.registers[0xA], 0x10);
+        assert_eq!(context.memory_map[0x200..0x202], [0x6A, 0x00]);
+
+        list.cheats[2].enabled = true;
+        list.apply(&mut context);
+        assert_eq!(context.memory_map[0x200..0x202], [0x12, 0x06]);
+    }
+}
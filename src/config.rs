@@ -0,0 +1,317 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::{context::Quirks, parser::Variant};
+
+// Keyboard keys for the CHIP-8 keypad, indexed by keypad key:
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D  ->  Q W E R
+//   7 8 9 E      A S D F
+//   A 0 B F      Z X C V
+pub const DEFAULT_KEYS: [&str; 16] = [
+    "X", "1", "2", "3", "Q", "W", "E", "A", "S", "D", "Z", "C", "4", "R", "F", "V",
+];
+
+// Keyboard keys for the CHIP-8X second keypad, on the numeric keypad:
+//   1 2 3 C      7 8 9 /
+//   4 5 6 D  ->  4 5 6 *
+//   7 8 9 E      1 2 3 -
+//   A 0 B F      0 . Enter +
+pub const DEFAULT_SECOND_KEYS: [&str; 16] = [
+    "KP.", "KP7", "KP8", "KP9", "KP4", "KP5", "KP6", "KP1", "KP2", "KP3", "KP0", "KPENTER", "KP/",
+    "KP*", "KP-", "KP+",
+];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(error) => write!(f, "{error}"),
+            ConfigError::Parse(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+/**
+ *  Everything that can be configured, both globally and per ROM. Settings
+ *  that aren't set fall back to the next layer: command line flags, then the
+ *  section of the ROM, then the ROM database, then the global settings, then
+ *  the defaults.
+ */
+#[derive(PartialEq, Clone, Default, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    pub palette: Option<String>,
+    pub scale: Option<usize>,
+    // CPU speed, in instructions per frame
+    pub ticks_per_frame: Option<u32>,
+    // Run instructions for the cycles they take on the COSMAC VIP instead
+    // of `ticks_per_frame`
+    pub vip_timing: Option<bool>,
+    pub frame_duration_ms: Option<u64>,
+    pub quirks: QuirkSettings,
+    // Dialect with opcodes of its own, `chip8x` or `chip8e`
+    pub variant: Option<Variant>,
+    // Most return addresses the stack can hold
+    pub stack_depth: Option<usize>,
+    pub audio_frequency: Option<f32>,
+    pub audio_volume: Option<f32>,
+    // Sprites for the hex digits 0-F, 5 bytes each
+    pub font: Option<Vec<u8>>,
+    // Keypad key (0-F) to keyboard key name, e.g. `A = "Z"`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, String>,
+    // The same for the CHIP-8X second keypad
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub second_keys: BTreeMap<String, String>,
+}
+
+impl Settings {
+    // Settings from `overrides` take precedence over these
+    pub fn merge(&self, overrides: &Settings) -> Settings {
+        let mut keys = self.keys.clone();
+        keys.extend(overrides.keys.clone());
+        let mut second_keys = self.second_keys.clone();
+        second_keys.extend(overrides.second_keys.clone());
+
+        Settings {
+            palette: overrides.palette.clone().or_else(|| self.palette.clone()),
+            scale: overrides.scale.or(self.scale),
+            ticks_per_frame: overrides.ticks_per_frame.or(self.ticks_per_frame),
+            vip_timing: overrides.vip_timing.or(self.vip_timing),
+            frame_duration_ms: overrides.frame_duration_ms.or(self.frame_duration_ms),
+            quirks: self.quirks.merge(&overrides.quirks),
+            variant: overrides.variant.or(self.variant),
+            stack_depth: overrides.stack_depth.or(self.stack_depth),
+            audio_frequency: overrides.audio_frequency.or(self.audio_frequency),
+            audio_volume: overrides.audio_volume.or(self.audio_volume),
+            font: overrides.font.clone().or_else(|| self.font.clone()),
+            keys,
+            second_keys,
+        }
+    }
+
+    // Keyboard key bound to the keypad `key`
+    pub fn key_name(&self, key: u8) -> &str {
+        bound_key(&self.keys, key).unwrap_or(DEFAULT_KEYS[key as usize])
+    }
+
+    // Keyboard key bound to `key` of the CHIP-8X second keypad
+    pub fn second_key_name(&self, key: u8) -> &str {
+        bound_key(&self.second_keys, key).unwrap_or(DEFAULT_SECOND_KEYS[key as usize])
+    }
+}
+
+/**
+ *  Quirks set by one layer of settings. The ones left unset keep the value of
+ *  the layers below, so the section of a ROM can change a single quirk of the
+ *  global settings.
+ */
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct QuirkSettings {
+    pub shift_uses_vy: Option<bool>,
+    pub load_store_increments_i: Option<bool>,
+    pub jump_uses_vx: Option<bool>,
+    pub logic_resets_vf: Option<bool>,
+    pub stack_in_memory: Option<bool>,
+}
+
+impl QuirkSettings {
+    // Quirks set in `overrides` take precedence over these
+    pub fn merge(&self, overrides: &QuirkSettings) -> QuirkSettings {
+        QuirkSettings {
+            shift_uses_vy: overrides.shift_uses_vy.or(self.shift_uses_vy),
+            load_store_increments_i: overrides
+                .load_store_increments_i
+                .or(self.load_store_increments_i),
+            jump_uses_vx: overrides.jump_uses_vx.or(self.jump_uses_vx),
+            logic_resets_vf: overrides.logic_resets_vf.or(self.logic_resets_vf),
+            stack_in_memory: overrides.stack_in_memory.or(self.stack_in_memory),
+        }
+    }
+
+    // `quirks` with the ones set here changed
+    pub fn apply(&self, quirks: Quirks) -> Quirks {
+        Quirks {
+            shift_uses_vy: self.shift_uses_vy.unwrap_or(quirks.shift_uses_vy),
+            load_store_increments_i: self
+                .load_store_increments_i
+                .unwrap_or(quirks.load_store_increments_i),
+            jump_uses_vx: self.jump_uses_vx.unwrap_or(quirks.jump_uses_vx),
+            logic_resets_vf: self.logic_resets_vf.unwrap_or(quirks.logic_resets_vf),
+            stack_in_memory: self.stack_in_memory.unwrap_or(quirks.stack_in_memory),
+        }
+    }
+}
+
+fn bound_key(keys: &BTreeMap<String, String>, key: u8) -> Option<&str> {
+    keys.iter()
+        .find(|(keypad, _)| u8::from_str_radix(keypad, 16) == Ok(key))
+        .map(|(_, name)| name.as_str())
+}
+
+#[derive(PartialEq, Default, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(flatten)]
+    pub settings: Settings,
+    // Overrides keyed by the SHA-1 of the ROM, see `rom_hash`
+    pub roms: BTreeMap<String, Settings>,
+}
+
+impl Config {
+    // `config.toml` in the `chip-8` directory of the user config directory
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|directory| directory.join("chip-8").join("config.toml"))
+    }
+
+    // A missing file is the same as an empty one
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(source) => Config::parse(&source),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(error) => Err(ConfigError::Io(error)),
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<Config, ConfigError> {
+        toml::from_str(source).map_err(ConfigError::Parse)
+    }
+
+    // Global settings with the overrides for `rom` applied
+    pub fn settings_for(&self, rom: &[u8]) -> Settings {
+        match self.rom_overrides(rom) {
+            Some(overrides) => self.settings.merge(overrides),
+            None => self.settings.clone(),
+        }
+    }
+
+    pub fn rom_overrides(&self, rom: &[u8]) -> Option<&Settings> {
+        self.roms.get(&rom_hash(rom))
+    }
+}
+
+// SHA-1 of the ROM bytes in lowercase hex
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha1::digest(rom)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{rom_hash, Config, QuirkSettings, Settings};
+    use crate::context::Quirks;
+
+    const CONFIG: &str = r#"
+        palette = "green"
+        ticks_per_frame = 10
+
+        [keys]
+        0 = "Space"
+
+        [second_keys]
+        5 = "M"
+
+        [roms.a9993e364706816aba3e25717850c26c9cd0d89d]
+        palette = "amber"
+        quirks = { shift_uses_vy = true }
+        keys = { A = "Enter" }
+    "#;
+
+    #[test]
+    fn hash_roms_with_sha1() {
+        assert_eq!(rom_hash(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn apply_rom_overrides() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        let settings = config.settings_for(b"abc");
+        assert_eq!(settings.palette.as_deref(), Some("amber"));
+        assert_eq!(settings.ticks_per_frame, Some(10));
+        assert_eq!(
+            settings.quirks,
+            QuirkSettings {
+                shift_uses_vy: Some(true),
+                ..QuirkSettings::default()
+            }
+        );
+        assert_eq!(settings.key_name(0x0), "Space");
+        assert_eq!(settings.key_name(0xA), "Enter");
+        assert_eq!(settings.second_key_name(0x5), "M");
+        assert_eq!(settings.second_key_name(0xB), "KPENTER");
+
+        let settings = config.settings_for(b"another rom");
+        assert_eq!(settings.palette.as_deref(), Some("green"));
+        assert_eq!(settings.key_name(0xA), "Z");
+    }
+
+    #[test]
+    fn override_single_quirks() {
+        let config = Config::parse(
+            r#"
+            quirks = { shift_uses_vy = true, load_store_increments_i = true }
+
+            [roms.a9993e364706816aba3e25717850c26c9cd0d89d]
+            quirks = { load_store_increments_i = false, jump_uses_vx = true }
+            "#,
+        )
+        .unwrap();
+
+        let quirks = config.settings_for(b"abc").quirks.apply(Quirks::default());
+        assert_eq!(
+            quirks,
+            Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: false,
+                jump_uses_vx: true,
+                ..Quirks::default()
+            }
+        );
+
+        let quirks = config.settings_for(b"another rom").quirks;
+        assert_eq!(quirks.shift_uses_vy, Some(true));
+        assert_eq!(quirks.jump_uses_vx, None);
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let config = Config::parse(CONFIG).unwrap();
+        let flags = Settings {
+            palette: Some("lcd".to_owned()),
+            ..Settings::default()
+        };
+
+        let settings = config.settings_for(b"abc").merge(&flags);
+        assert_eq!(settings.palette.as_deref(), Some("lcd"));
+        assert_eq!(settings.ticks_per_frame, Some(10));
+    }
+
+    #[test]
+    fn load_missing_config() {
+        let config = Config::load(Path::new("/nonexistent/chip-8/config.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn reject_invalid_config() {
+        assert!(Config::parse("ticks_per_frame = \"fast\"").is_err());
+    }
+}
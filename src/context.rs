@@ -1,10 +1,102 @@
+use std::{
+    fmt,
+    ops::{Range, RangeInclusive},
+};
+
 use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    alu::{self, Operation},
+    instructions::Instruction,
+    megachip::{self, BlendMode, DigitizedSound, MegaChip},
+    parser::{decode_instruction, Dialect, Variant},
+};
+
+pub const MEMORY_SIZE: usize = 0x1000;
+pub const PROGRAM_ADDRESS: u16 = 0x200;
+pub const FONT_ADDRESS: u16 = 0x050;
+// Return addresses kept by `Call`, as in the original interpreter. Some
+// platforms only have room for 12.
+pub const DEFAULT_STACK_DEPTH: usize = 16;
+// Where the original interpreter keeps the stack, 2 bytes per address
+pub const STACK_ADDRESS: u16 = 0xEA0;
+// RPL user flags: SUPER-CHIP uses the first 8, XO-CHIP all 16
+pub const FLAG_COUNT: usize = 16;
 
-use crate::{instructions::Instruction, parser::parse_instruction};
+// CHIP-8X colors the display in zones of 8x4 pixels, 8 across and 8 down
+pub const COLOR_ZONES: usize = 8;
+// Background colors `CycleBackground` goes through: blue, black, green, red
+pub const BACKGROUND_COLORS: u8 = 4;
+// Foreground color of every zone at first, red
+pub const DEFAULT_ZONE_COLOR: u8 = 1;
+
+// Hex digits 0-F, 5 bytes per sprite
+pub const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// Behaviors that differ between CHIP-8 interpreters. Enabling one switches to
+// the behavior of the original COSMAC VIP interpreter, except for
+// `jump_uses_vx` which comes from SUPER-CHIP.
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Quirks {
+    // 8xy6 / 8xyE shift Vy and store the result in Vx, instead of shifting Vx
+    pub shift_uses_vy: bool,
+    // Fx55 / Fx65 leave I pointing right after the last register
+    pub load_store_increments_i: bool,
+    // Bnnn jumps to nnn + Vx, x being the highest nibble of nnn, instead of V0
+    pub jump_uses_vx: bool,
+    // 8xy1 / 8xy2 / 8xy3 set VF to 0
+    pub logic_resets_vf: bool,
+    // 2nnn / 00EE keep return addresses in memory at `STACK_ADDRESS`, so
+    // programs can read and change them
+    pub stack_in_memory: bool,
+}
+
+// Errors that stop the program, with the address of the instruction
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Fault {
+    // `Call` with a full stack
+    StackOverflow(u16),
+    // `Return` with an empty stack
+    StackUnderflow(u16),
+    // The program ended with CHIP-8E `Stop` or MEGA-CHIP `Exit`
+    Stopped(u16),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::StackOverflow(address) => write!(f, "stack overflow at {address:#05X}"),
+            Fault::StackUnderflow(address) => {
+                write!(f, "return with an empty stack at {address:#05X}")
+            }
+            Fault::Stopped(address) => write!(f, "program stopped at {address:#05X}"),
+        }
+    }
+}
 
 pub struct Context {
     pub registers: [u8; 16],
-    pub i_register: u16,
+    // 16 bits, 24 on MEGA-CHIP
+    pub i_register: u32,
     pub delay_timer: u8,
     pub sound_timer: u8,
     pub program_counter: u16,
@@ -13,21 +105,49 @@ pub struct Context {
     pub keyboard_input: Option<u8>,
     pub memory_map: Vec<u8>,
     pub graphics_buffer: Vec<Vec<u8>>,
-    pub key_pressed: u8,
+    pub quirks: Quirks,
+    pub flags: [u8; FLAG_COUNT],
+    // Most return addresses the stack can hold
+    pub stack_depth: usize,
+    // Set when the program can't go on. Nothing runs until it's cleared.
+    pub fault: Option<Fault>,
+    // Which dialect's opcodes are decoded
+    pub variant: Variant,
+    // Opcode families decoded, those of `variant` unless changed after
+    // `set_variant`
+    pub dialect: Dialect,
+    // CHIP-8X background color, 0 - 3
+    pub background: u8,
+    // CHIP-8X foreground colors, 0 - 7, by zone row then column
+    pub zone_colors: [[u8; COLOR_ZONES]; COLOR_ZONES],
+    // Key down on the CHIP-8X second keypad
+    pub second_keypad: Option<u8>,
+    // Last byte sent to the output port by CHIP-8X and CHIP-8E
+    pub port_output: u8,
+    // Byte waiting on the input port, taken by the instructions that wait
+    // for one
+    pub port_input: Option<u8>,
+    // MEGA-CHIP display and sound, only with that variant
+    pub mega_chip: Option<MegaChip>,
+    // Set while CHIP-8E `DelayFor` waits for the delay timer
+    delaying: bool,
     rng: SmallRng,
 }
 
 impl Context {
     // Provide RNG seed
     pub fn new(data: &[u8], seed: u64) -> Context {
-        let mut memory = vec![0u8; 0x200];
-        memory.append(&mut Vec::from(data));
+        // Programs that don't fit in memory are cut short
+        let mut memory = vec![0u8; MEMORY_SIZE];
+        let length = data.len().min(MEMORY_SIZE - PROGRAM_ADDRESS as usize);
+        memory[PROGRAM_ADDRESS as usize..][..length].copy_from_slice(&data[..length]);
+        memory[FONT_ADDRESS as usize..][..FONT.len()].copy_from_slice(&FONT);
         let graphics = vec![vec![0; 8]; 32];
         let rng = SmallRng::seed_from_u64(seed);
         Context {
             data: Vec::from(data),
             memory_map: memory,
-            program_counter: 0x200,
+            program_counter: PROGRAM_ADDRESS,
             graphics_buffer: graphics,
             rng,
             registers: [0; 16],
@@ -36,19 +156,94 @@ impl Context {
             sound_timer: 0,
             stack_pointer: vec![],
             keyboard_input: None,
-            key_pressed: 0,
+            quirks: Quirks::default(),
+            flags: [0; FLAG_COUNT],
+            stack_depth: DEFAULT_STACK_DEPTH,
+            fault: None,
+            variant: Variant::default(),
+            dialect: Variant::default().dialect(),
+            background: 0,
+            zone_colors: [[DEFAULT_ZONE_COLOR; COLOR_ZONES]; COLOR_ZONES],
+            second_keypad: None,
+            port_output: 0,
+            port_input: None,
+            mega_chip: None,
+            delaying: false,
         }
     }
 
+    /**
+     *  Switches to the opcodes of `variant`. MEGA-CHIP also gets 16MB of
+     *  memory with the whole program in it, and its own display.
+     */
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+        self.dialect = variant.dialect();
+        if variant != Variant::MegaChip {
+            self.memory_map.truncate(MEMORY_SIZE);
+            self.mega_chip = None;
+        } else if self.mega_chip.is_none() {
+            self.memory_map.resize(megachip::MEMORY_SIZE, 0);
+            let length = self
+                .data
+                .len()
+                .min(megachip::MEMORY_SIZE - PROGRAM_ADDRESS as usize);
+            self.memory_map[PROGRAM_ADDRESS as usize..][..length]
+                .copy_from_slice(&self.data[..length]);
+            self.mega_chip = Some(MegaChip::new());
+        }
+    }
+
+    // Replaces the built-in hex digit sprites, 5 bytes per digit
+    pub fn load_font(&mut self, font: &[u8; 80]) {
+        self.memory_map[FONT_ADDRESS as usize..][..font.len()].copy_from_slice(font);
+    }
+
+    // Restores RPL user flags saved by a previous run, extra bytes are ignored
+    pub fn load_flags(&mut self, flags: &[u8]) {
+        let length = flags.len().min(FLAG_COUNT);
+        self.flags[..length].copy_from_slice(&flags[..length]);
+    }
+
+    // Counts both timers down, must be called at 60Hz
+    pub fn update_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
     pub fn tick(&mut self) -> Instruction {
-        let bytes: [u8; 2] = [
-            self.memory_map[self.program_counter as usize],
-            self.memory_map[(self.program_counter + 1) as usize],
-        ];
-        let instruction = parse_instruction(bytes);
+        let instruction = self.fetch();
+        self.execute(instruction);
+        instruction
+    }
 
+    // Decode the instruction at the program counter without executing it
+    pub fn fetch(&self) -> Instruction {
+        self.fetch_at(self.program_counter)
+    }
+
+    pub fn fetch_at(&self, address: u16) -> Instruction {
+        // Addresses are 12 bits, an instruction at 0xFFF ends at 0x000
+        let address = address as usize % MEMORY_SIZE;
+        // Enough bytes for the longest instruction. Words no opcode family
+        // of the dialect knows are data, running them does nothing.
+        let bytes = [0, 1, 2, 3].map(|offset| self.memory_map[(address + offset) % MEMORY_SIZE]);
+        decode_instruction(&bytes, self.dialect)
+            .unwrap_or_else(|_| Instruction::Data(u16::from_be_bytes([bytes[0], bytes[1]])))
+    }
+
+    pub fn execute(&mut self, instruction: Instruction) {
+        if self.fault.is_some() {
+            return;
+        }
         match instruction {
             Instruction::ClearScreen => {
+                // MEGA-CHIP shows what was drawn before clearing
+                if let Some(mega) = self.mega_chip.as_mut().filter(|mega| mega.enabled) {
+                    mega.present();
+                    self.increment_program_counter(1);
+                    return;
+                }
                 // Send clear screen command
                 for i in 0..self.graphics_buffer.len() {
                     for j in 0..self.graphics_buffer[i].len() {
@@ -64,21 +259,38 @@ impl Context {
             }
             Instruction::Return => {
                 // Set the program counter to the address at the top of the SP
-                if let Some(address) = self.stack_pointer.pop() {
-                    self.program_counter = address;
-                } else {
-                    self.increment_program_counter(1);
+                let Some(mut address) = self.stack_pointer.pop() else {
+                    self.fault = Some(Fault::StackUnderflow(self.program_counter));
+                    return;
+                };
+                if self.quirks.stack_in_memory {
+                    let slot = self.stack_slot(self.stack_pointer.len());
+                    if let [high, low] = self.memory_map[slot] {
+                        address = u16::from_be_bytes([high, low]);
+                    }
                 }
+                self.program_counter = wrap_address(address);
             }
             Instruction::Jump(address) => {
                 // Set the program counter to a new address
-                self.program_counter = address;
+                self.program_counter = wrap_address(address);
             }
             Instruction::Call(address) => {
-                // Increments the stack pointer, put the the current PC
-                // at the top of the stack. PC is set to address.
-                self.stack_pointer.push(self.program_counter);
-                self.program_counter = address
+                // Increments the stack pointer, put the address of the
+                // next instruction at the top of the stack. PC is set to
+                // address.
+                if self.stack_pointer.len() >= self.stack_depth {
+                    self.fault = Some(Fault::StackOverflow(self.program_counter));
+                    return;
+                }
+                let next = wrap_address(self.program_counter + 2);
+                if self.quirks.stack_in_memory {
+                    let slot = self.stack_slot(self.stack_pointer.len());
+                    let length = slot.len();
+                    self.memory_map[slot].copy_from_slice(&next.to_be_bytes()[..length]);
+                }
+                self.stack_pointer.push(next);
+                self.program_counter = wrap_address(address)
             }
             Instruction::SkipIfEqual(x, value) => {
                 // if Vx == kk { increment PC twice }
@@ -111,85 +323,79 @@ impl Context {
             }
             Instruction::Add(x, value) => {
                 // Vx = Vx + kk
-                self.registers[x as usize] += value;
+                self.registers[x as usize] = self.registers[x as usize].wrapping_add(value);
                 self.increment_program_counter(1)
             }
             Instruction::SetReg(x, y) => {
                 // Vx = Vy
-                self.registers[x as usize] = self.registers[y as usize];
+                self.alu(Operation::Set, x, y);
                 self.increment_program_counter(1)
             }
             Instruction::Or(x, y) => {
                 // Vx = Vx | Vy
-                self.registers[x as usize] |= self.registers[y as usize];
+                self.alu(Operation::Or, x, y);
                 self.increment_program_counter(1)
             }
             Instruction::And(x, y) => {
                 // Vx = Vx & Vy
-                self.registers[x as usize] &= self.registers[y as usize];
+                self.alu(Operation::And, x, y);
                 self.increment_program_counter(1)
             }
             Instruction::Xor(x, y) => {
                 // Vx = Vx ^ Vy
-                self.registers[x as usize] ^= self.registers[y as usize];
+                self.alu(Operation::Xor, x, y);
                 self.increment_program_counter(1)
             }
             Instruction::AddReg(x, y) => {
-                // let sum = Vx + Vy
-                // if sum > 0xFF { VF = 0x1 }
-                // Vx = (sum << 4) >> 4
-                let sum = (self.registers[x as usize] as u16) + (self.registers[y as usize] as u16);
-                self.registers[0xF] = if sum > 0xFF { 0x1 } else { 0x0 };
-                self.registers[x as usize] = ((sum << 4) >> 4) as u8;
+                // Vx = Vx + Vy
+                // VF = carry
+                self.alu(Operation::Add, x, y);
                 self.increment_program_counter(1)
             }
             Instruction::SubReg(x, y) => {
-                // let sub = Vx - Vy
-                // VF = Vx > Vy
-                // Vx = sub
-                self.registers[0xF] =
-                    (self.registers[x as usize] > self.registers[y as usize]) as u8;
-                self.registers[x as usize] -= self.registers[y as usize];
+                // Vx = Vx - Vy
+                // VF = Vx >= Vy
+                self.alu(Operation::Sub, x, y);
                 self.increment_program_counter(1)
             }
             Instruction::ShiftRight(x, y) => {
-                // Vx = Vx >> (Vy or 1)
+                // Vx = (Vy or Vx) >> 1
                 // VF = Least-signficant bit
-                self.registers[0xF] = self.registers[x as usize] & 1;
-                self.registers[x as usize] >>= self.registers[y as usize];
+                self.alu(Operation::ShiftRight, x, y);
                 self.increment_program_counter(1)
             }
             Instruction::SubN(x, y) => {
                 // Vx = Vy - Vx
-                // VF = Vy > Vx
-                self.registers[0xF] =
-                    (self.registers[y as usize] > self.registers[x as usize]) as u8;
-                self.registers[x as usize] =
-                    self.registers[y as usize] - self.registers[x as usize];
+                // VF = Vy >= Vx
+                self.alu(Operation::SubN, x, y);
                 self.increment_program_counter(1)
             }
             Instruction::ShiftLeft(x, y) => {
-                // Vx = Vx << (Vy or 1)
+                // Vx = (Vy or Vx) << 1
                 // VF = Most-significant bit
-                self.registers[0xF] = (self.registers[x as usize] & 0x80) >> 7;
-                self.registers[x as usize] <<= self.registers[y as usize];
+                self.alu(Operation::ShiftLeft, x, y);
                 self.increment_program_counter(1)
             }
             Instruction::SkipIfNotEqualReg(x, y) => {
                 // if Vx != Vy { increment PC twice }
                 if self.registers[x as usize] != self.registers[y as usize] {
                     self.increment_program_counter(2)
+                } else {
+                    self.increment_program_counter(1)
                 }
-                self.increment_program_counter(1)
             }
             Instruction::SetI(address) => {
-                self.i_register = address;
+                self.i_register = address as u32;
                 self.increment_program_counter(1)
             }
             Instruction::JumpToPlusV0(address) => {
-                // PC set to nnn + V0
-                self.program_counter = address + (self.registers[0] as u16);
-                self.increment_program_counter(1)
+                // PC set to nnn + V0 (or Vx)
+                let register = if self.quirks.jump_uses_vx {
+                    (address >> 8) as usize
+                } else {
+                    0
+                };
+                self.program_counter = wrap_address(address + self.registers[register] as u16);
             }
             Instruction::SetRandom(x, value) => {
                 // Vx = random & kk
@@ -203,34 +409,43 @@ impl Context {
                 // - values from the Draw command should be XORed on the existing screen
                 // - if any pixel is erased, VF = 1 else 0
                 // - use modulo for the coordinates of the display
+                // - rows past the end of memory aren't drawn
                 let x = self.registers[x as usize];
                 let y = self.registers[y as usize];
-                let start = self.i_register as usize;
-                let end = (self.i_register + n as u16) as usize;
-                let range_i = start..end;
-                let sprites = &self.memory_map[range_i];
+                if let Some(mega) = self.mega_chip.as_mut().filter(|mega| mega.enabled) {
+                    // MEGA-CHIP sprites have the size set by `03nn` and `04nn`
+                    let length = mega.sprite_width * mega.sprite_height;
+                    let size = self.memory_map.len();
+                    let start = (self.i_register as usize).min(size);
+                    let sprite = &self.memory_map[start..(start + length).min(size)];
+                    self.registers[0xF] = mega.draw(x, y, sprite) as u8;
+                    self.increment_program_counter(1);
+                    return;
+                }
+                let sprites = &self.memory_map[self.memory_range(n as usize)];
                 let mut collision = 0;
 
                 // For each byte from sprites range
+                let height = self.graphics_buffer.len();
                 for (index, bit) in sprites.iter().enumerate() {
-                    let pixel_row = &mut self.graphics_buffer[(y as usize) + index];
+                    let pixel_row = &mut self.graphics_buffer[(y as usize + index) % height];
                     let is_collision = proccess_graphics_row(pixel_row, x, *bit);
                     collision |= is_collision as u8;
                 }
                 self.registers[0xF] = collision;
                 self.increment_program_counter(1);
             }
-            Instruction::SkipIfKeyPressed(key) => {
-                // - if keyboard_input == x { increment PC twice }
-                if self.key_pressed == key {
+            Instruction::SkipIfKeyPressed(x) => {
+                // - if keyboard_input == Vx { increment PC twice }
+                if self.keyboard_input == Some(self.registers[x as usize]) {
                     self.increment_program_counter(2);
                 } else {
                     self.increment_program_counter(1);
                 }
             }
-            Instruction::SkipIfKeyNotPressed(key) => {
-                // - if keyboard_input != x { increment PC twice }
-                if self.key_pressed != key {
+            Instruction::SkipIfKeyNotPressed(x) => {
+                // - if keyboard_input != Vx { increment PC twice }
+                if self.keyboard_input != Some(self.registers[x as usize]) {
                     self.increment_program_counter(2);
                 } else {
                     self.increment_program_counter(1);
@@ -238,14 +453,16 @@ impl Context {
             }
             Instruction::SetDelayTimer(x) => {
                 // Vx = delay_timer
-                self.delay_timer = x;
+                self.registers[x as usize] = self.delay_timer;
                 self.increment_program_counter(1);
             }
             Instruction::WaitForKey(x) => {
                 // Stops execution. Wait for key press
                 // Vx = key
-                self.key_pressed = x;
-                self.increment_program_counter(1);
+                if let Some(key) = self.keyboard_input {
+                    self.registers[x as usize] = key;
+                    self.increment_program_counter(1);
+                }
             }
             Instruction::SetDelayTimerReg(x) => {
                 // delay_timer = Vx
@@ -259,46 +476,352 @@ impl Context {
             }
             Instruction::AddToI(x) => {
                 // i_register = i_register + Vx
-                self.i_register += self.registers[x as usize] as u16;
+                self.add_to_i(self.registers[x as usize] as u32);
                 self.increment_program_counter(1);
             }
             Instruction::SetSpriteLocation(x) => {
                 // i_register = sprite_location[Vx]
-                self.i_register = self.registers[x as usize] as u16;
+                let digit = (self.registers[x as usize] & 0xF) as u16;
+                self.i_register = (FONT_ADDRESS + 5 * digit) as u32;
                 self.increment_program_counter(1);
             }
             Instruction::StoreBCD(x) => {
                 let value = self.registers[x as usize] as u16;
                 let bcd = dec_to_bcd(value);
-                self.memory_map[self.i_register as usize] = bcd.0;
-                self.memory_map[self.i_register as usize] = bcd.1;
-                self.memory_map[self.i_register as usize] = bcd.2;
+                // memory[I..I + 3] = hundreds, tens, ones
+                let range = self.memory_range(3);
+                let length = range.len();
+                self.memory_map[range].copy_from_slice(&[bcd.0, bcd.1, bcd.2][..length]);
                 self.increment_program_counter(1);
             }
             Instruction::StoreRegRange(x) => {
-                for i in 0..=x {
-                    let value = self.registers[i as usize];
-                    self.memory_map[(self.i_register + (i as u16)) as usize] = value;
+                let range = self.memory_range(x as usize + 1);
+                let length = range.len();
+                self.memory_map[range].copy_from_slice(&self.registers[..length]);
+                if self.quirks.load_store_increments_i {
+                    self.add_to_i(x as u32 + 1);
                 }
                 self.increment_program_counter(1);
             }
             Instruction::LoadRegRange(x) => {
-                for i in 0..=x {
-                    let position = (self.i_register + (i as u16)) as usize;
-                    self.registers[i as usize] = self.memory_map[position];
+                let range = self.memory_range(x as usize + 1);
+                let length = range.len();
+                self.registers[..length].copy_from_slice(&self.memory_map[range]);
+                if self.quirks.load_store_increments_i {
+                    self.add_to_i(x as u32 + 1);
+                }
+                self.increment_program_counter(1);
+            }
+            Instruction::StoreFlags(x) => {
+                // flags[0..=x] = V0..Vx
+                let length = x as usize + 1;
+                self.flags[..length].copy_from_slice(&self.registers[..length]);
+                self.increment_program_counter(1);
+            }
+            Instruction::LoadFlags(x) => {
+                // V0..Vx = flags[0..=x]
+                let length = x as usize + 1;
+                self.registers[..length].copy_from_slice(&self.flags[..length]);
+                self.increment_program_counter(1);
+            }
+            Instruction::CycleBackground => {
+                self.background = (self.background + 1) % BACKGROUND_COLORS;
+                self.increment_program_counter(1);
+            }
+            Instruction::AddNibbles(x, y) => {
+                // Vx = Vx + Vy, each nibble modulo 8
+                let (vx, vy) = (self.registers[x as usize], self.registers[y as usize]);
+                let high = ((vx >> 4) + (vy >> 4)) % 8;
+                let low = ((vx & 0xF) + (vy & 0xF)) % 8;
+                self.registers[x as usize] = high << 4 | low;
+                self.increment_program_counter(1);
+            }
+            Instruction::SetColorZones(x, y) => {
+                // Zones from the low to the high nibble of Vx across and of
+                // Vx+1 down
+                let columns = self.registers[x as usize];
+                let rows = self.registers[(x as usize + 1) % 16];
+                let color = self.registers[y as usize];
+                self.color_zones(columns & 0xF..=columns >> 4, rows & 0xF..=rows >> 4, color);
+                self.increment_program_counter(1);
+            }
+            Instruction::SetColorArea(x, y, n) => {
+                // Zones under an 8 by n sprite at Vx, Vx+1
+                let left = self.registers[x as usize] % 64;
+                let top = self.registers[(x as usize + 1) % 16] % 32;
+                let color = self.registers[y as usize];
+                let columns = left / 8..=left.div_ceil(8);
+                self.color_zones(columns, top / 4..=(top + n).saturating_sub(1) / 4, color);
+                self.increment_program_counter(1);
+            }
+            Instruction::SkipIfKeyPressed2(x) => {
+                if self.second_keypad == Some(self.registers[x as usize]) {
+                    self.increment_program_counter(2);
+                } else {
+                    self.increment_program_counter(1);
+                }
+            }
+            Instruction::SkipIfKeyNotPressed2(x) => {
+                if self.second_keypad != Some(self.registers[x as usize]) {
+                    self.increment_program_counter(2);
+                } else {
+                    self.increment_program_counter(1);
+                }
+            }
+            Instruction::OutputTone(x) | Instruction::Output(x) => {
+                self.port_output = self.registers[x as usize];
+                self.increment_program_counter(1);
+            }
+            Instruction::WaitForInput(x) | Instruction::WaitForStrobe(x) => {
+                // Stops execution until a byte is on the input port
+                if let Some(byte) = self.port_input.take() {
+                    self.registers[x as usize] = byte;
+                    self.increment_program_counter(1);
+                }
+            }
+            Instruction::Input(x) => {
+                self.registers[x as usize] = self.port_input.take().unwrap_or(0);
+                self.increment_program_counter(1);
+            }
+            Instruction::Stop => {
+                self.fault = Some(Fault::Stopped(self.program_counter));
+            }
+            Instruction::WaitForDelay => {
+                if self.delay_timer == 0 {
+                    self.increment_program_counter(1);
+                }
+            }
+            Instruction::SkipNext => {
+                self.increment_program_counter(2);
+            }
+            Instruction::SkipIfGreater(x, y) => {
+                if self.registers[x as usize] > self.registers[y as usize] {
+                    self.increment_program_counter(2);
+                } else {
+                    self.increment_program_counter(1);
+                }
+            }
+            Instruction::StoreRange(x, y) => {
+                // memory[I..] = Vx..Vy, nothing when x > y
+                if x <= y {
+                    let range = self.memory_range((y - x) as usize + 1);
+                    let length = range.len();
+                    self.memory_map[range].copy_from_slice(&self.registers[x as usize..][..length]);
+                    self.add_to_i((y - x) as u32 + 1);
+                }
+                self.increment_program_counter(1);
+            }
+            Instruction::LoadRange(x, y) => {
+                // Vx..Vy = memory[I..], nothing when x > y
+                if x <= y {
+                    let range = self.memory_range((y - x) as usize + 1);
+                    let length = range.len();
+                    self.registers[x as usize..][..length].copy_from_slice(&self.memory_map[range]);
+                    self.add_to_i((y - x) as u32 + 1);
+                }
+                self.increment_program_counter(1);
+            }
+            Instruction::JumpBackward(offset) => {
+                let address = self.program_counter + MEMORY_SIZE as u16 - offset as u16;
+                self.program_counter = wrap_address(address);
+            }
+            Instruction::JumpForward(offset) => {
+                self.program_counter = wrap_address(self.program_counter + offset as u16);
+            }
+            Instruction::SkipBytes(x) => {
+                let next = self.program_counter + 2;
+                self.program_counter = wrap_address(next + self.registers[x as usize] as u16);
+            }
+            Instruction::DelayFor(x) => {
+                // Sets the delay timer once, then waits for it
+                if !self.delaying {
+                    self.delay_timer = self.registers[x as usize];
+                    self.delaying = true;
+                }
+                if self.delay_timer == 0 {
+                    self.delaying = false;
+                    self.increment_program_counter(1);
+                }
+            }
+            Instruction::DisableMegaChip | Instruction::EnableMegaChip => {
+                if let Some(mega) = self.mega_chip.as_mut() {
+                    mega.enabled = instruction == Instruction::EnableMegaChip;
+                }
+                self.increment_program_counter(1);
+            }
+            Instruction::SetLongI(address) => {
+                // An instruction of 4 bytes
+                self.i_register = address;
+                self.increment_program_counter(2);
+            }
+            Instruction::LoadPalette(count) => {
+                let range = self.memory_range(4 * count as usize);
+                if let Some(mega) = self.mega_chip.as_mut() {
+                    mega.load_palette(&self.memory_map[range]);
+                }
+                self.increment_program_counter(1);
+            }
+            Instruction::SetSpriteWidth(width) => {
+                if let Some(mega) = self.mega_chip.as_mut() {
+                    mega.set_sprite_size(Some(width), None);
+                }
+                self.increment_program_counter(1);
+            }
+            Instruction::SetSpriteHeight(height) => {
+                if let Some(mega) = self.mega_chip.as_mut() {
+                    mega.set_sprite_size(None, Some(height));
+                }
+                self.increment_program_counter(1);
+            }
+            Instruction::SetScreenAlpha(alpha) => {
+                if let Some(mega) = self.mega_chip.as_mut() {
+                    mega.alpha = alpha;
+                }
+                self.increment_program_counter(1);
+            }
+            Instruction::PlaySound(mode) => {
+                let start = (self.i_register as usize).min(self.memory_map.len());
+                if let Some(mega) = self.mega_chip.as_mut() {
+                    mega.sound = DigitizedSound::read(&self.memory_map[start..], mode == 0);
+                }
+                self.increment_program_counter(1);
+            }
+            Instruction::StopSound => {
+                if let Some(mega) = self.mega_chip.as_mut() {
+                    mega.sound = None;
+                }
+                self.increment_program_counter(1);
+            }
+            Instruction::SetBlendMode(mode) => {
+                if let Some(mega) = self.mega_chip.as_mut() {
+                    mega.blend_mode = BlendMode::from_mode(mode);
+                }
+                self.increment_program_counter(1);
+            }
+            Instruction::SetCollisionColor(color) => {
+                if let Some(mega) = self.mega_chip.as_mut() {
+                    mega.collision_color = Some(color);
                 }
                 self.increment_program_counter(1);
             }
-            _ => {
+            Instruction::ScrollUp(n) => {
+                self.scroll(0, -(n as isize));
+                self.increment_program_counter(1);
+            }
+            Instruction::ScrollDown(n) => {
+                self.scroll(0, n as isize);
+                self.increment_program_counter(1);
+            }
+            Instruction::ScrollRight => {
+                self.scroll(4, 0);
+                self.increment_program_counter(1);
+            }
+            Instruction::ScrollLeft => {
+                self.scroll(-4, 0);
+                self.increment_program_counter(1);
+            }
+            Instruction::Exit => {
+                self.fault = Some(Fault::Stopped(self.program_counter));
+            }
+            Instruction::Data(_) => {
                 self.increment_program_counter(1);
             }
         };
+    }
 
-        instruction
+    // Moves the display, positive amounts go right and down
+    fn scroll(&mut self, columns: isize, rows: isize) {
+        if let Some(mega) = self.mega_chip.as_mut().filter(|mega| mega.enabled) {
+            mega.scroll(columns, rows);
+            return;
+        }
+        // Each row of 64 pixels is shifted as one number
+        let height = self.graphics_buffer.len() as isize;
+        let shifted = (0..height)
+            .map(|row| {
+                let from = row - rows;
+                if !(0..height).contains(&from) {
+                    return 0;
+                }
+                let pixels = &self.graphics_buffer[from as usize];
+                let pixels = u64::from_be_bytes(pixels.as_slice().try_into().unwrap_or_default());
+                if columns >= 0 {
+                    pixels >> columns
+                } else {
+                    pixels << -columns
+                }
+            })
+            .collect::<Vec<u64>>();
+        for (row, pixels) in self.graphics_buffer.iter_mut().zip(shifted) {
+            row.copy_from_slice(&pixels.to_be_bytes());
+        }
+    }
+
+    // Sets the CHIP-8X foreground color of zones, those off the display are
+    // left out
+    fn color_zones(&mut self, columns: RangeInclusive<u8>, rows: RangeInclusive<u8>, color: u8) {
+        for row in rows.filter(|row| (*row as usize) < COLOR_ZONES) {
+            for column in columns
+                .clone()
+                .filter(|column| (*column as usize) < COLOR_ZONES)
+            {
+                self.zone_colors[row as usize][column as usize] = color & 0x7;
+            }
+        }
+    }
+
+    // Memory that will be written by executing `instruction` in the current state
+    pub fn memory_written(&self, instruction: &Instruction) -> Option<Range<usize>> {
+        match *instruction {
+            Instruction::StoreBCD(_) => Some(self.memory_range(3)),
+            Instruction::StoreRegRange(x) => Some(self.memory_range(x as usize + 1)),
+            Instruction::StoreRange(x, y) if x <= y => {
+                Some(self.memory_range((y - x) as usize + 1))
+            }
+            Instruction::Call(_) if self.quirks.stack_in_memory => {
+                Some(self.stack_slot(self.stack_pointer.len()))
+            }
+            _ => None,
+        }
+    }
+
+    // Memory of the return address at `depth` with `stack_in_memory`, cut
+    // short at the end of memory
+    fn stack_slot(&self, depth: usize) -> Range<usize> {
+        let start = (STACK_ADDRESS as usize + 2 * depth).min(MEMORY_SIZE);
+        start..(start + 2).min(MEMORY_SIZE)
+    }
+
+    // `length` bytes of memory from I, cut short at the end of memory
+    fn memory_range(&self, length: usize) -> Range<usize> {
+        let size = self.memory_map.len();
+        let start = (self.i_register as usize).min(size);
+        start..(start + length).min(size)
+    }
+
+    // Runs an `8xyN` operation, writing VF after Vx
+    fn alu(&mut self, operation: Operation, x: u8, y: u8) {
+        let vx = self.registers[x as usize];
+        let vy = self.registers[y as usize];
+        let output = alu::apply(operation, vx, vy, &self.quirks);
+        self.registers[x as usize] = output.vx;
+        if let Some(vf) = output.vf {
+            self.registers[0xF] = vf;
+        }
+    }
+
+    // I wraps at 16 bits, or 24 on MEGA-CHIP
+    fn add_to_i(&mut self, offset: u32) {
+        let bits = if self.variant == Variant::MegaChip {
+            24
+        } else {
+            16
+        };
+        self.i_register = self.i_register.wrapping_add(offset) & ((1 << bits) - 1);
     }
 
     fn increment_program_counter(&mut self, times: u16) {
-        self.program_counter += 2 * times;
+        self.program_counter = wrap_address(self.program_counter + 2 * times);
     }
 
     pub fn get_flat_graphics_buffer(&self) -> Vec<u8> {
@@ -306,6 +829,11 @@ impl Context {
     }
 }
 
+// Keeps an address within the 4KB of memory
+fn wrap_address(address: u16) -> u16 {
+    address % MEMORY_SIZE as u16
+}
+
 pub fn dec_to_bcd(n: u16) -> (u8, u8, u8) {
     let hundreds = (n / 100) % 10;
     let tens = (n / 10) % 10;
@@ -314,7 +842,6 @@ pub fn dec_to_bcd(n: u16) -> (u8, u8, u8) {
 }
 
 pub fn proccess_graphics_row(input: &mut [u8], x: u8, pixels: u8) -> bool {
-    dbg!(&input, &x, &pixels);
     let index = (x as usize / 8) % input.len();
     let octet = input[index] as usize;
     let mut collision: bool;
@@ -334,7 +861,7 @@ pub fn proccess_graphics_row(input: &mut [u8], x: u8, pixels: u8) -> bool {
             let index = (index + 1) % input.len();
             let octet = input[index] as usize;
             let result = octet ^ (carry as usize);
-            collision = result != octet | (carry as usize);
+            collision |= result != octet | (carry as usize);
             input[index] = result as u8;
         }
     }
@@ -345,7 +872,10 @@ pub fn proccess_graphics_row(input: &mut [u8], x: u8, pixels: u8) -> bool {
 #[cfg(test)]
 mod test {
 
-    use super::{dec_to_bcd, proccess_graphics_row, Context};
+    use super::{
+        dec_to_bcd, proccess_graphics_row, Context, Fault, Quirks, FONT_ADDRESS, MEMORY_SIZE,
+    };
+    use crate::{instructions::Instruction, megachip, parser::Variant};
 
     #[test]
     fn set_i_register_in_context() {
@@ -403,6 +933,47 @@ mod test {
         assert_eq!(context.registers[0xF], 0x0);
     }
 
+    #[test]
+    fn detect_collisions_in_either_byte() {
+        // The sprite straddles two bytes and only overlaps pixels in the first
+        let mut row = [0xFF, 0x00];
+        assert!(proccess_graphics_row(&mut row, 4, 0xFF));
+        assert_eq!(row, [0xF0, 0xF0]);
+    }
+
+    #[test]
+    fn skip_on_key_in_register() {
+        // V0 = 5, SKP V0, SKNP V0
+        let test_data = [0x60, 0x05, 0xE0, 0x9E, 0xE0, 0xA1];
+        let mut context = Context::new(&test_data, 1);
+        context.keyboard_input = Some(5);
+        context.tick();
+        context.tick();
+        assert_eq!(context.program_counter, 0x206);
+
+        let mut context = Context::new(&test_data, 1);
+        context.keyboard_input = Some(3);
+        context.tick();
+        context.tick();
+        assert_eq!(context.program_counter, 0x204);
+        context.tick();
+        assert_eq!(context.program_counter, 0x208);
+    }
+
+    #[test]
+    fn wait_for_key_until_pressed() {
+        // LD V2, K
+        let test_data = [0xF2, 0x0A];
+        let mut context = Context::new(&test_data, 1);
+        context.tick();
+        assert_eq!(context.program_counter, 0x200);
+
+        context.keyboard_input = Some(7);
+        context.tick();
+        assert_eq!(context.program_counter, 0x202);
+        assert_eq!(context.registers[2], 7);
+    }
+
     // Fuzzy testing could be useful for this
     #[test]
     fn test_bcd_conversions() {
@@ -418,4 +989,180 @@ mod test {
         let result = dec_to_bcd(n);
         assert_eq!(result, (0, 0, 1));
     }
+
+    #[test]
+    fn shift_with_quirks() {
+        // V0 = 0x0F, V1 = 0xF0, SHR V0 {, V1}
+        let test_data = [0x60, 0x0F, 0x61, 0xF0, 0x80, 0x16];
+        let mut context = Context::new(&test_data, 1);
+        for _ in 0..3 {
+            context.tick();
+        }
+        assert_eq!(context.registers[0], 0x07);
+        assert_eq!(context.registers[0xF], 0x1);
+
+        let mut context = Context::new(&test_data, 1);
+        context.quirks = Quirks {
+            shift_uses_vy: true,
+            ..Quirks::default()
+        };
+        for _ in 0..3 {
+            context.tick();
+        }
+        assert_eq!(context.registers[0], 0x78);
+        assert_eq!(context.registers[0xF], 0x0);
+    }
+
+    #[test]
+    fn store_registers_with_quirks() {
+        // I = 0x300, LD [I], V2
+        let test_data = [0xA3, 0x00, 0xF2, 0x55];
+        let mut context = Context::new(&test_data, 1);
+        context.quirks.load_store_increments_i = true;
+        context.tick();
+        context.tick();
+
+        assert_eq!(context.i_register, 0x303);
+    }
+
+    #[test]
+    fn set_sprite_location_to_font() {
+        // V3 = 0x0A, LD F, V3
+        let test_data = [0x63, 0x0A, 0xF3, 0x29];
+        let mut context = Context::new(&test_data, 1);
+        context.tick();
+        context.tick();
+
+        assert_eq!(context.i_register, (FONT_ADDRESS + 5 * 0xA) as u32);
+        let sprite = &context.memory_map[context.i_register as usize..][..5];
+        assert_eq!(sprite, [0xF0, 0x90, 0xF0, 0x90, 0x90]);
+    }
+
+    #[test]
+    fn count_timers_down() {
+        let mut context = Context::new(&[], 1);
+        context.delay_timer = 2;
+        context.sound_timer = 1;
+        context.update_timers();
+        context.update_timers();
+
+        assert_eq!(context.delay_timer, 0);
+        assert_eq!(context.sound_timer, 0);
+    }
+
+    #[test]
+    fn store_and_load_flags() {
+        // LD R, V2 ; LD V0, 0 ; LD V1, R
+        let mut context = Context::new(&[0xF2, 0x75, 0x60, 0x00, 0xF1, 0x85], 1);
+        context.registers[..3].copy_from_slice(&[7, 8, 9]);
+        context.load_flags(&[0xFF; 20]);
+        context.tick();
+        assert_eq!(context.flags[..4], [7, 8, 9, 0xFF]);
+
+        context.tick();
+        context.tick();
+        assert_eq!(context.registers[..3], [7, 8, 9]);
+    }
+
+    #[test]
+    fn return_after_call() {
+        // CALL 0x206 ; LD V1, 1 ; JP 0x204 ; LD V0, 1 ; RET
+        let program = [0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x60, 0x01, 0x00, 0xEE];
+        let mut context = Context::new(&program, 1);
+        context.tick();
+        assert_eq!(context.stack_pointer, [0x202]);
+
+        context.tick();
+        context.tick();
+        context.tick();
+        assert_eq!(context.program_counter, 0x204);
+        assert_eq!(context.registers[..2], [1, 1]);
+        assert!(context.stack_pointer.is_empty());
+    }
+
+    // Crashes found by the fuzz targets
+
+    #[test]
+    fn wrap_arithmetic() {
+        // LD V0, 0xFF ; ADD V0, 2 ; LD V1, 3 ; SUB V1, V0 ; SUBN V0, V1
+        let program = [0x60, 0xFF, 0x70, 0x02, 0x61, 0x03, 0x81, 0x05, 0x80, 0x17];
+        let mut context = Context::new(&program, 1);
+        for _ in 0..5 {
+            context.tick();
+        }
+        assert_eq!(context.registers[..2], [0x01, 0x02]);
+    }
+
+    #[test]
+    fn draw_past_the_bottom_and_end_of_memory() {
+        // LD V0, 0xFF ; LD I, 0xFFE ; DRW V0, V0, 15
+        let mut context = Context::new(&[0x60, 0xFF, 0xAF, 0xFE, 0xD0, 0x0F], 1);
+        context.memory_map[0xFFE..].copy_from_slice(&[0x80, 0x80]);
+        for _ in 0..3 {
+            context.tick();
+        }
+        assert_eq!(context.graphics_buffer[31][7], 0x01);
+        assert_eq!(context.graphics_buffer[0][7], 0x01);
+        assert_eq!(context.get_flat_graphics_buffer().iter().sum::<u8>(), 2);
+    }
+
+    #[test]
+    fn store_and_load_past_the_end_of_memory() {
+        // LD I, 0xFFE ; LD [I], VF ; LD VF, [I] ; ADD I, V0 ; LD B, V0
+        let program = [0xAF, 0xFE, 0xFF, 0x55, 0xFF, 0x65, 0xF0, 0x1E, 0xF0, 0x33];
+        let mut context = Context::new(&program, 1);
+        context.quirks.load_store_increments_i = true;
+        context.registers[0] = 0xFF;
+        for _ in 0..5 {
+            context.tick();
+        }
+        assert_eq!(context.memory_map[0xFFE..], [0xFF, 0x00]);
+        assert_eq!(context.i_register, 0x111D);
+    }
+
+    #[test]
+    fn keep_program_counter_in_memory() {
+        // LD V0, 0xFF ; JP V0, 0xFFF
+        let mut context = Context::new(&[0x60, 0xFF, 0xBF, 0xFF], 1);
+        context.tick();
+        context.tick();
+        assert!((context.program_counter as usize) < MEMORY_SIZE);
+
+        context.program_counter = 0xFFF;
+        context.memory_map[0xFFF] = 0x12;
+        assert_eq!(context.tick(), Instruction::Jump(0x200));
+    }
+
+    #[test]
+    fn stop_on_endless_recursion() {
+        // CALL 0x200
+        let mut context = Context::new(&[0x22, 0x00], 1);
+        context.stack_depth = 12;
+        for _ in 0..100 {
+            context.tick();
+        }
+        assert_eq!(context.stack_pointer.len(), 12);
+        assert_eq!(context.fault, Some(Fault::StackOverflow(0x200)));
+        assert_eq!(
+            context.fault.unwrap().to_string(),
+            "stack overflow at 0x200"
+        );
+    }
+
+    #[test]
+    fn load_whole_mega_chip_programs() {
+        let mut program = vec![0; 0x2000];
+        program[0x1FFF] = 0xAB;
+        let mut context = Context::new(&program, 1);
+        assert_eq!(context.memory_map.len(), MEMORY_SIZE);
+
+        context.set_variant(Variant::MegaChip);
+        assert_eq!(context.memory_map.len(), megachip::MEMORY_SIZE);
+        assert_eq!(context.memory_map[0x21FF], 0xAB);
+        assert!(context.mega_chip.is_some());
+
+        context.set_variant(Variant::Chip8);
+        assert_eq!(context.memory_map.len(), MEMORY_SIZE);
+        assert!(context.mega_chip.is_none());
+    }
 }
@@ -0,0 +1,302 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs, io,
+    path::Path,
+};
+
+use serde::Deserialize;
+
+use crate::config::{rom_hash, QuirkSettings, Settings};
+
+// Sample in the format of the CHIP-8 community database
+// (https://github.com/chip-8/chip-8-database), with only the IBM logo. Load
+// the files of that repository with `load` to look up other ROMs.
+const PROGRAMS: &str = include_str!("../assets/database/programs.json");
+const PLATFORMS: &str = include_str!("../assets/database/platforms.json");
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Io(error) => write!(f, "{error}"),
+            DatabaseError::Parse(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Program {
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Rom {
+    pub platforms: Vec<String>,
+    // Instructions per frame
+    pub tickrate: Option<u32>,
+    // Name of the control (`up`, `a`, ...) to keypad key
+    pub keys: BTreeMap<String, u8>,
+    pub colors: Option<Colors>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Colors {
+    // Hex colors for each pixel value, 2 or 4 of them
+    pub pixels: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Platform {
+    pub id: String,
+    pub name: String,
+    pub quirks: PlatformQuirks,
+}
+
+// Quirks as named in the database. `true` means the platform has the quirk.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PlatformQuirks {
+    pub shift: bool,
+    pub memory_increment_by_x: bool,
+    pub memory_leave_i_unchanged: bool,
+    pub wrap: bool,
+    pub jump: bool,
+    pub vblank: bool,
+    pub logic: bool,
+}
+
+impl PlatformQuirks {
+    // `wrap` and `vblank` aren't emulated. Incrementing I by X instead of
+    // X + 1 is treated like leaving it unchanged.
+    pub fn to_quirks(&self) -> QuirkSettings {
+        QuirkSettings {
+            shift_uses_vy: Some(!self.shift),
+            load_store_increments_i: Some(
+                !(self.memory_leave_i_unchanged || self.memory_increment_by_x),
+            ),
+            jump_uses_vx: Some(self.jump),
+            logic_resets_vf: Some(self.logic),
+            ..QuirkSettings::default()
+        }
+    }
+}
+
+pub struct Database {
+    programs: Vec<Program>,
+    platforms: Vec<Platform>,
+    // ROM hash to index in `programs`
+    hashes: HashMap<String, usize>,
+}
+
+// Everything known about a ROM
+pub struct RomInfo<'a> {
+    pub program: &'a Program,
+    pub rom: &'a Rom,
+    // The first platform the ROM runs on
+    pub platform: Option<&'a Platform>,
+}
+
+impl Database {
+    pub fn bundled() -> Database {
+        Database::parse(PROGRAMS, PLATFORMS).expect("the bundled database is valid")
+    }
+
+    // `programs.json` and `platforms.json` in a directory, like the
+    // `database` directory of the community database repository
+    pub fn load(directory: &Path) -> Result<Database, DatabaseError> {
+        let read = |name| fs::read_to_string(directory.join(name)).map_err(DatabaseError::Io);
+        let (programs, platforms) = (read("programs.json")?, read("platforms.json")?);
+        Database::parse(&programs, &platforms).map_err(DatabaseError::Parse)
+    }
+
+    pub fn parse(programs: &str, platforms: &str) -> serde_json::Result<Database> {
+        let programs: Vec<Program> = serde_json::from_str(programs)?;
+        let platforms = serde_json::from_str(platforms)?;
+        let hashes = programs
+            .iter()
+            .enumerate()
+            .flat_map(|(index, program)| {
+                let hashes = program.roms.keys();
+                hashes.map(move |hash| (hash.clone(), index))
+            })
+            .collect();
+
+        Ok(Database {
+            programs,
+            platforms,
+            hashes,
+        })
+    }
+
+    // Platform by id, e.g. `superchip`
+    pub fn platform(&self, id: &str) -> Option<&Platform> {
+        self.platforms.iter().find(|platform| platform.id == id)
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo<'_>> {
+        let hash = rom_hash(rom);
+        let program = &self.programs[*self.hashes.get(&hash)?];
+        let rom = &program.roms[&hash];
+        let platform = rom.platforms.first().and_then(|id| self.platform(id));
+
+        Some(RomInfo {
+            program,
+            rom,
+            platform,
+        })
+    }
+}
+
+impl RomInfo<'_> {
+    // Settings recommended for the ROM, meant to be overridden by the config
+    pub fn settings(&self) -> Settings {
+        Settings {
+            ticks_per_frame: self.rom.tickrate,
+            quirks: self
+                .platform
+                .map(|platform| platform.quirks.to_quirks())
+                .unwrap_or_default(),
+            palette: self
+                .rom
+                .colors
+                .as_ref()
+                .map(|colors| colors.pixels.join(",")),
+            ..Settings::default()
+        }
+    }
+
+    // Controls of the game and their keypad keys, e.g. `("up", 5)`
+    pub fn controls(&self) -> impl Iterator<Item = (&str, u8)> {
+        self.rom
+            .keys
+            .iter()
+            .map(|(control, key)| (control.as_str(), *key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{Database, DatabaseError, PLATFORMS as BUNDLED_PLATFORMS};
+    use crate::{config::rom_hash, palette::Palette, test_data::DATA};
+
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Test Game",
+            "roms": {
+                "a9993e364706816aba3e25717850c26c9cd0d89d": {
+                    "platforms": ["superchip", "xochip"],
+                    "tickrate": 30,
+                    "keys": { "left": 7, "right": 9 },
+                    "colors": { "pixels": ["#000000", "#ff0000"] }
+                }
+            }
+        }
+    ]"##;
+
+    const PLATFORMS: &str = r#"[
+        {
+            "id": "superchip",
+            "name": "SUPER-CHIP 1.1",
+            "quirks": { "shift": true, "memoryLeaveIUnchanged": true, "jump": true }
+        }
+    ]"#;
+
+    #[test]
+    fn identify_bundled_ibm_logo() {
+        let database = Database::bundled();
+        let info = database.lookup(&DATA).unwrap();
+
+        assert_eq!(info.program.title, "IBM Logo");
+        assert_eq!(info.platform.unwrap().id, "originalChip8");
+
+        let quirks = info.settings().quirks;
+        assert_eq!(quirks.shift_uses_vy, Some(true));
+        assert_eq!(quirks.load_store_increments_i, Some(true));
+        assert_eq!(quirks.logic_resets_vf, Some(true));
+        assert_eq!(quirks.stack_in_memory, None);
+    }
+
+    #[test]
+    fn recommend_settings_for_rom() {
+        let database = Database::parse(PROGRAMS, PLATFORMS).unwrap();
+        let info = database.lookup(b"abc").unwrap();
+        let settings = info.settings();
+
+        assert_eq!(settings.ticks_per_frame, Some(30));
+        let quirks = settings.quirks;
+        assert_eq!(quirks.shift_uses_vy, Some(false));
+        assert_eq!(quirks.load_store_increments_i, Some(false));
+        assert_eq!(quirks.jump_uses_vx, Some(true));
+
+        let palette = Palette::parse(&settings.palette.unwrap()).unwrap();
+        assert_eq!(palette.foreground(), [0xFF, 0x00, 0x00, 0xFF]);
+
+        let controls = info.controls().collect::<Vec<(&str, u8)>>();
+        assert_eq!(controls, [("left", 7), ("right", 9)]);
+    }
+
+    #[test]
+    fn load_database_from_directory() {
+        let roms: [(&str, &[u8], &str); 3] = [
+            ("Paddles", &[0x6A, 0x02, 0x6B, 0x0C], "originalChip8"),
+            ("Falling Blocks", &[0xA2, 0xB4, 0x23, 0xE6], "originalChip8"),
+            ("Space Shooter", &[0x00, 0xFF, 0x12, 0x00], "superchip"),
+        ];
+        let programs = roms
+            .iter()
+            .map(|(title, rom, platform)| {
+                let hash = rom_hash(rom);
+                format!(r#"{{"title": "{title}", "roms": {{"{hash}": {{"platforms": ["{platform}"]}}}}}}"#)
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        let directory = tempfile::tempdir().unwrap();
+        fs::write(
+            directory.path().join("programs.json"),
+            format!("[{programs}]"),
+        )
+        .unwrap();
+        fs::write(directory.path().join("platforms.json"), BUNDLED_PLATFORMS).unwrap();
+
+        let database = Database::load(directory.path()).unwrap();
+        for (title, rom, platform) in roms {
+            let info = database.lookup(rom).unwrap();
+            assert_eq!(info.program.title, title);
+            assert_eq!(info.platform.unwrap().id, platform);
+        }
+        assert!(database.lookup(&DATA).is_none());
+    }
+
+    #[test]
+    fn report_missing_or_invalid_database() {
+        let directory = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            Database::load(directory.path()),
+            Err(DatabaseError::Io(_))
+        ));
+
+        fs::write(directory.path().join("programs.json"), "{").unwrap();
+        fs::write(directory.path().join("platforms.json"), "[]").unwrap();
+        assert!(matches!(
+            Database::load(directory.path()),
+            Err(DatabaseError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_roms_have_no_info() {
+        let database = Database::parse(PROGRAMS, PLATFORMS).unwrap();
+        assert!(database.lookup(b"unknown").is_none());
+    }
+}
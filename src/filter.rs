@@ -0,0 +1,129 @@
+#[derive(PartialEq, Eq, Debug)]
+pub struct ParseFilterError;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum FilterMode {
+    // Lit pixels fade out instead of turning off at once, keeping this
+    // fraction (0.0 - 1.0) of their brightness every frame.
+    Decay(f32),
+
+    // A pixel is drawn if it was lit in either of the last two frames
+    Merge,
+}
+
+impl FilterMode {
+    // Accepts `merge`, `decay` or `decay:<strength>`
+    pub fn parse(source: &str) -> Result<FilterMode, ParseFilterError> {
+        match source.split_once(':') {
+            None if source == "merge" => Ok(FilterMode::Merge),
+            None if source == "decay" => Ok(FilterMode::Decay(DEFAULT_DECAY)),
+            Some(("decay", strength)) => strength
+                .parse::<f32>()
+                .ok()
+                .filter(|strength| (0.0..=1.0).contains(strength))
+                .map(FilterMode::Decay)
+                .ok_or(ParseFilterError),
+            _ => Err(ParseFilterError),
+        }
+    }
+}
+
+pub const DEFAULT_DECAY: f32 = 0.6;
+
+/**
+ *  Reduces the flicker caused by games erasing and redrawing sprites every
+ *  frame. Takes the 1-bit graphics buffer of each frame and returns the
+ *  brightness of every pixel, from 0x00 (off) to 0xFF (lit).
+ */
+pub struct PersistenceFilter {
+    pub mode: FilterMode,
+    levels: Vec<u8>,
+}
+
+impl PersistenceFilter {
+    pub fn new(mode: FilterMode) -> PersistenceFilter {
+        PersistenceFilter {
+            mode,
+            levels: vec![],
+        }
+    }
+
+    pub fn apply(&mut self, buffer: &[u8]) -> Vec<u8> {
+        let lit = buffer
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1 == 1));
+
+        // Start over whenever the size of the display changes
+        if self.levels.len() != buffer.len() * 8 {
+            self.levels = vec![0; buffer.len() * 8];
+        }
+
+        lit.zip(self.levels.iter_mut())
+            .map(|(lit, level)| match self.mode {
+                FilterMode::Decay(strength) => {
+                    *level = if lit {
+                        0xFF
+                    } else {
+                        (*level as f32 * strength) as u8
+                    };
+                    *level
+                }
+                FilterMode::Merge => {
+                    // `level` only remembers if the pixel was lit last frame
+                    let shown = lit || *level == 0xFF;
+                    *level = if lit { 0xFF } else { 0x00 };
+                    if shown {
+                        0xFF
+                    } else {
+                        0x00
+                    }
+                }
+            })
+            .collect()
+    }
+
+    pub fn reset(&mut self) {
+        self.levels.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FilterMode, ParseFilterError, PersistenceFilter, DEFAULT_DECAY};
+
+    #[test]
+    fn parse_filter_modes() {
+        assert_eq!(FilterMode::parse("merge"), Ok(FilterMode::Merge));
+        assert_eq!(
+            FilterMode::parse("decay"),
+            Ok(FilterMode::Decay(DEFAULT_DECAY))
+        );
+        assert_eq!(FilterMode::parse("decay:0.25"), Ok(FilterMode::Decay(0.25)));
+        assert_eq!(FilterMode::parse("decay:1.5"), Err(ParseFilterError));
+        assert_eq!(FilterMode::parse("blur"), Err(ParseFilterError));
+    }
+
+    #[test]
+    fn decay_pixels_over_frames() {
+        let mut filter = PersistenceFilter::new(FilterMode::Decay(0.5));
+
+        let levels = filter.apply(&[0b1000_0000]);
+        assert_eq!(levels, [0xFF, 0, 0, 0, 0, 0, 0, 0]);
+
+        let levels = filter.apply(&[0b0000_0001]);
+        assert_eq!(levels, [0x7F, 0, 0, 0, 0, 0, 0, 0xFF]);
+
+        let levels = filter.apply(&[0b0000_0000]);
+        assert_eq!(levels, [0x3F, 0, 0, 0, 0, 0, 0, 0x7F]);
+    }
+
+    #[test]
+    fn merge_last_two_frames() {
+        let mut filter = PersistenceFilter::new(FilterMode::Merge);
+
+        assert_eq!(filter.apply(&[0b1000_0000])[0], 0xFF);
+        // Erased this frame, but lit in the previous one
+        assert_eq!(filter.apply(&[0b0000_0000])[0], 0xFF);
+        assert_eq!(filter.apply(&[0b0000_0000])[0], 0x00);
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::rom_hash, context::FLAG_COUNT};
+
+/**
+ *  RPL user flags saved by Fx75, kept on disk so high scores survive between
+ *  runs. Every ROM gets its own `<sha1>.flags` file holding the raw flag
+ *  bytes.
+ */
+pub struct FlagStore {
+    path: PathBuf,
+    // Flags as they are on disk, to only write when they change
+    saved: [u8; FLAG_COUNT],
+}
+
+impl FlagStore {
+    // `flags` in the `chip-8` directory of the user data directory
+    pub fn default_directory() -> Option<PathBuf> {
+        dirs::data_dir().map(|directory| directory.join("chip-8").join("flags"))
+    }
+
+    // Flags of a ROM that never saved any are all 0
+    pub fn open(directory: &Path, rom: &[u8]) -> io::Result<FlagStore> {
+        let path = directory.join(format!("{}.flags", rom_hash(rom)));
+        let mut saved = [0; FLAG_COUNT];
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let length = bytes.len().min(FLAG_COUNT);
+                saved[..length].copy_from_slice(&bytes[..length]);
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+        Ok(FlagStore { path, saved })
+    }
+
+    pub fn flags(&self) -> &[u8; FLAG_COUNT] {
+        &self.saved
+    }
+
+    // Writes `flags` unless they are already saved. Returns whether the file
+    // was written.
+    pub fn save(&mut self, flags: &[u8; FLAG_COUNT]) -> io::Result<bool> {
+        if flags == &self.saved {
+            return Ok(false);
+        }
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(&self.path, flags)?;
+        self.saved = *flags;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FlagStore;
+    use crate::context::Context;
+
+    #[test]
+    fn persist_flags_between_runs() {
+        let directory = tempfile::tempdir().unwrap();
+        let directory = directory.path().join("flags");
+        let rom = [0xF3, 0x75, 0x12, 0x02];
+
+        let mut store = FlagStore::open(&directory, &rom).unwrap();
+        assert_eq!(store.flags(), &[0; 16]);
+
+        let mut context = Context::new(&rom, 1);
+        context.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+        context.tick();
+        assert!(store.save(&context.flags).unwrap());
+        assert!(!store.save(&context.flags).unwrap());
+
+        let store = FlagStore::open(&directory, &rom).unwrap();
+        let mut context = Context::new(&rom, 1);
+        context.load_flags(store.flags());
+        assert_eq!(context.flags[..5], [1, 2, 3, 4, 0]);
+
+        let other = FlagStore::open(&directory, &[0x12, 0x00]).unwrap();
+        assert_eq!(other.flags(), &[0; 16]);
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(PartialEq, Debug)]
+use std::fmt;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Instruction {
     // 0nnn SYS addr
     Sys(u16),
@@ -112,5 +114,282 @@ pub enum Instruction {
     // Fx65 LD Vx, [I]
     LoadRegRange(u8),
 
+    // Fx75 LD R, Vx
+    // SUPER-CHIP: store V0..Vx in the RPL user flags
+    StoreFlags(u8),
+
+    // Fx85 LD Vx, R
+    // SUPER-CHIP: read V0..Vx from the RPL user flags
+    LoadFlags(u8),
+
+    // 02A0 BGC
+    // CHIP-8X: cycle the background through blue, black, green and red
+    CycleBackground,
+
+    // 5xy1 ADD Vx, Vy
+    // CHIP-8X: add the nibbles separately, each modulo 8
+    AddNibbles(u8, u8),
+
+    // Bxy0 COL Vx, Vy
+    // CHIP-8X: color the zones from the low to the high nibble of Vx
+    // horizontally and of Vx+1 vertically with Vy
+    SetColorZones(u8, u8),
+
+    // Bxyn COL Vx, Vy, nibble
+    // CHIP-8X: color the zones under an 8 by n sprite at Vx, Vx+1 with Vy
+    SetColorArea(u8, u8, u8),
+
+    // ExF2 SKP2 Vx
+    // CHIP-8X: skip if key Vx of the second keypad is down
+    SkipIfKeyPressed2(u8),
+
+    // ExF5 SKNP2 Vx
+    // CHIP-8X: skip if key Vx of the second keypad is up
+    SkipIfKeyNotPressed2(u8),
+
+    // FxF8 OUT Vx
+    // CHIP-8X: send Vx to the output port, the tone of the VP-595
+    OutputTone(u8),
+
+    // FxFB INP Vx
+    // CHIP-8X: wait for a byte on the input port, Vx = byte
+    WaitForInput(u8),
+
+    // 00ED STOP
+    // CHIP-8E: end the program
+    Stop,
+
+    // 0151 WAIT DT
+    // CHIP-8E: wait for the delay timer to reach 0
+    WaitForDelay,
+
+    // 0188 SKIP
+    // CHIP-8E: skip the next instruction
+    SkipNext,
+
+    // 5xy1 SGT Vx, Vy
+    // CHIP-8E: skip if Vx > Vy
+    SkipIfGreater(u8, u8),
+
+    // 5xy2 LD [I], Vx-Vy
+    // CHIP-8E: store Vx..Vy at I, I = I + y - x + 1
+    StoreRange(u8, u8),
+
+    // 5xy3 LD Vx-Vy, [I]
+    // CHIP-8E: read Vx..Vy from I, I = I + y - x + 1
+    LoadRange(u8, u8),
+
+    // BBkk JB byte
+    // CHIP-8E: jump kk bytes back from this instruction
+    JumpBackward(u8),
+
+    // BFkk JF byte
+    // CHIP-8E: jump kk bytes forward from this instruction
+    JumpForward(u8),
+
+    // Fx03 OUT Vx
+    // CHIP-8E: send Vx to the output port
+    Output(u8),
+
+    // Fx1B SKIP Vx
+    // CHIP-8E: skip Vx bytes
+    SkipBytes(u8),
+
+    // Fx4F WAIT DT, Vx
+    // CHIP-8E: delay_timer = Vx, then wait for it to reach 0
+    DelayFor(u8),
+
+    // FxE3 WAIT Vx
+    // CHIP-8E: wait for a byte on the input port, Vx = byte
+    WaitForStrobe(u8),
+
+    // FxE7 INP Vx
+    // CHIP-8E: Vx = the byte on the input port, 0 without one
+    Input(u8),
+
+    // 0010 MEGAOFF
+    // MEGA-CHIP: back to the 64x32 display
+    DisableMegaChip,
+
+    // 0011 MEGAON
+    // MEGA-CHIP: switch to the 256x192 indexed color display
+    EnableMegaChip,
+
+    // 01nn nnnn LDHI I, nnnnnn
+    // MEGA-CHIP: I = nnnnnn, an instruction of 4 bytes
+    SetLongI(u32),
+
+    // 02nn LDPAL nn
+    // MEGA-CHIP: load nn ARGB colors from I into the palette, from index 1
+    LoadPalette(u8),
+
+    // 03nn SPRW nn
+    // MEGA-CHIP: sprite width, 0 for 256
+    SetSpriteWidth(u8),
+
+    // 04nn SPRH nn
+    // MEGA-CHIP: sprite height, 0 for 256
+    SetSpriteHeight(u8),
+
+    // 05nn ALPHA nn
+    // MEGA-CHIP: opacity of the whole screen
+    SetScreenAlpha(u8),
+
+    // 060n DIGISND n
+    // MEGA-CHIP: play the digitized sound at I, in a loop when n is 0
+    PlaySound(u8),
+
+    // 0700 STOPSND
+    // MEGA-CHIP: stop the digitized sound
+    StopSound,
+
+    // 080n BMODE n
+    // MEGA-CHIP: sprite blend mode, 0 normal, 1 25%, 2 50%, 3 add, 4 multiply
+    SetBlendMode(u8),
+
+    // 09nn CCOL nn
+    // MEGA-CHIP: drawing over a pixel of color nn sets VF
+    SetCollisionColor(u8),
+
+    // 00Bn SCU n
+    // MEGA-CHIP: scroll the display up n lines
+    ScrollUp(u8),
+
+    // 00Cn SCD n
+    // MEGA-CHIP: scroll the display down n lines
+    ScrollDown(u8),
+
+    // 00FB SCR
+    // MEGA-CHIP: scroll the display right 4 pixels
+    ScrollRight,
+
+    // 00FC SCL
+    // MEGA-CHIP: scroll the display left 4 pixels
+    ScrollLeft,
+
+    // 00FD EXIT
+    // MEGA-CHIP: end the program
+    Exit,
+
     Data(u16),
 }
+
+impl Instruction {
+    // Bytes the instruction takes in memory
+    pub fn length(&self) -> usize {
+        match self {
+            Instruction::SetLongI(_) => 4,
+            _ => 2,
+        }
+    }
+}
+
+// Mnemonics as in Cowgod's Chip-8 technical reference, with hex numbers
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Sys(address) => write!(f, "SYS {address:#05X}"),
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::Jump(address) => write!(f, "JP {address:#05X}"),
+            Instruction::Call(address) => write!(f, "CALL {address:#05X}"),
+            Instruction::SkipIfEqual(x, value) => write!(f, "SE V{x:X}, {value:#04X}"),
+            Instruction::SkipIfNotEqual(x, value) => write!(f, "SNE V{x:X}, {value:#04X}"),
+            Instruction::SkipIfEqualReg(x, y) => write!(f, "SE V{x:X}, V{y:X}"),
+            Instruction::Set(x, value) => write!(f, "LD V{x:X}, {value:#04X}"),
+            Instruction::Add(x, value) => write!(f, "ADD V{x:X}, {value:#04X}"),
+            Instruction::SetReg(x, y) => write!(f, "LD V{x:X}, V{y:X}"),
+            Instruction::Or(x, y) => write!(f, "OR V{x:X}, V{y:X}"),
+            Instruction::And(x, y) => write!(f, "AND V{x:X}, V{y:X}"),
+            Instruction::Xor(x, y) => write!(f, "XOR V{x:X}, V{y:X}"),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{x:X}, V{y:X}"),
+            Instruction::SubReg(x, y) => write!(f, "SUB V{x:X}, V{y:X}"),
+            Instruction::ShiftRight(x, y) => write!(f, "SHR V{x:X}, V{y:X}"),
+            Instruction::SubN(x, y) => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Instruction::ShiftLeft(x, y) => write!(f, "SHL V{x:X}, V{y:X}"),
+            Instruction::SkipIfNotEqualReg(x, y) => write!(f, "SNE V{x:X}, V{y:X}"),
+            Instruction::SetI(address) => write!(f, "LD I, {address:#05X}"),
+            Instruction::JumpToPlusV0(address) => write!(f, "JP V0, {address:#05X}"),
+            Instruction::SetRandom(x, value) => write!(f, "RND V{x:X}, {value:#04X}"),
+            Instruction::Display(x, y, n) => write!(f, "DRW V{x:X}, V{y:X}, {n}"),
+            Instruction::SkipIfKeyPressed(x) => write!(f, "SKP V{x:X}"),
+            Instruction::SkipIfKeyNotPressed(x) => write!(f, "SKNP V{x:X}"),
+            Instruction::SetDelayTimer(x) => write!(f, "LD V{x:X}, DT"),
+            Instruction::WaitForKey(x) => write!(f, "LD V{x:X}, K"),
+            Instruction::SetDelayTimerReg(x) => write!(f, "LD DT, V{x:X}"),
+            Instruction::SetSoundTimerReg(x) => write!(f, "LD ST, V{x:X}"),
+            Instruction::AddToI(x) => write!(f, "ADD I, V{x:X}"),
+            Instruction::SetSpriteLocation(x) => write!(f, "LD F, V{x:X}"),
+            Instruction::StoreBCD(x) => write!(f, "LD B, V{x:X}"),
+            Instruction::StoreRegRange(x) => write!(f, "LD [I], V{x:X}"),
+            Instruction::LoadRegRange(x) => write!(f, "LD V{x:X}, [I]"),
+            Instruction::StoreFlags(x) => write!(f, "LD R, V{x:X}"),
+            Instruction::LoadFlags(x) => write!(f, "LD V{x:X}, R"),
+            Instruction::CycleBackground => write!(f, "BGC"),
+            Instruction::AddNibbles(x, y) => write!(f, "ADD V{x:X}, V{y:X}"),
+            Instruction::SetColorZones(x, y) => write!(f, "COL V{x:X}, V{y:X}"),
+            Instruction::SetColorArea(x, y, n) => write!(f, "COL V{x:X}, V{y:X}, {n}"),
+            Instruction::SkipIfKeyPressed2(x) => write!(f, "SKP2 V{x:X}"),
+            Instruction::SkipIfKeyNotPressed2(x) => write!(f, "SKNP2 V{x:X}"),
+            Instruction::OutputTone(x) | Instruction::Output(x) => write!(f, "OUT V{x:X}"),
+            Instruction::WaitForInput(x) | Instruction::Input(x) => write!(f, "INP V{x:X}"),
+            Instruction::Stop => write!(f, "STOP"),
+            Instruction::WaitForDelay => write!(f, "WAIT DT"),
+            Instruction::SkipNext => write!(f, "SKIP"),
+            Instruction::SkipIfGreater(x, y) => write!(f, "SGT V{x:X}, V{y:X}"),
+            Instruction::StoreRange(x, y) => write!(f, "LD [I], V{x:X}-V{y:X}"),
+            Instruction::LoadRange(x, y) => write!(f, "LD V{x:X}-V{y:X}, [I]"),
+            Instruction::JumpBackward(offset) => write!(f, "JB {offset:#04X}"),
+            Instruction::JumpForward(offset) => write!(f, "JF {offset:#04X}"),
+            Instruction::SkipBytes(x) => write!(f, "SKIP V{x:X}"),
+            Instruction::DelayFor(x) => write!(f, "WAIT DT, V{x:X}"),
+            Instruction::WaitForStrobe(x) => write!(f, "WAIT V{x:X}"),
+            Instruction::DisableMegaChip => write!(f, "MEGAOFF"),
+            Instruction::EnableMegaChip => write!(f, "MEGAON"),
+            Instruction::SetLongI(address) => write!(f, "LDHI I, {address:#08X}"),
+            Instruction::LoadPalette(count) => write!(f, "LDPAL {count}"),
+            Instruction::SetSpriteWidth(width) => write!(f, "SPRW {width}"),
+            Instruction::SetSpriteHeight(height) => write!(f, "SPRH {height}"),
+            Instruction::SetScreenAlpha(alpha) => write!(f, "ALPHA {alpha:#04X}"),
+            Instruction::PlaySound(mode) => write!(f, "DIGISND {mode}"),
+            Instruction::StopSound => write!(f, "STOPSND"),
+            Instruction::SetBlendMode(mode) => write!(f, "BMODE {mode}"),
+            Instruction::SetCollisionColor(color) => write!(f, "CCOL {color:#04X}"),
+            Instruction::ScrollUp(n) => write!(f, "SCU {n}"),
+            Instruction::ScrollDown(n) => write!(f, "SCD {n}"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Data(word) => write!(f, "DW {word:#06X}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Instruction;
+
+    #[test]
+    fn display_mnemonics() {
+        assert_eq!(Instruction::Jump(0x228).to_string(), "JP 0x228");
+        assert_eq!(Instruction::Set(0x1, 0x8).to_string(), "LD V1, 0x08");
+        assert_eq!(
+            Instruction::Display(0x0, 0x1, 15).to_string(),
+            "DRW V0, V1, 15"
+        );
+        assert_eq!(Instruction::StoreRegRange(0xA).to_string(), "LD [I], VA");
+        assert_eq!(Instruction::Data(0xFF00).to_string(), "DW 0xFF00");
+        assert_eq!(
+            Instruction::SetColorArea(0x2, 0x3, 4).to_string(),
+            "COL V2, V3, 4"
+        );
+        assert_eq!(
+            Instruction::StoreRange(0x1, 0x4).to_string(),
+            "LD [I], V1-V4"
+        );
+        assert_eq!(
+            Instruction::SetLongI(0x012345).to_string(),
+            "LDHI I, 0x012345"
+        );
+    }
+}
@@ -0,0 +1,26 @@
+pub mod alu;
+pub mod analysis;
+pub mod audio;
+pub mod block;
+pub mod capture;
+pub mod cdp1802;
+pub mod cheat;
+pub mod config;
+pub mod context;
+pub mod database;
+pub mod filter;
+pub mod flags;
+pub mod instructions;
+pub mod lint;
+pub mod loader;
+pub mod megachip;
+pub mod memory_view;
+pub mod palette;
+pub mod parser;
+pub mod profile;
+pub mod reference;
+pub mod render;
+pub mod script;
+pub mod test_data;
+pub mod timing;
+pub mod vip;
@@ -0,0 +1,444 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use crate::{
+    analysis::{analyze, ControlFlowGraph},
+    context::{FONT, FONT_ADDRESS, MEMORY_SIZE, PROGRAM_ADDRESS},
+    instructions::Instruction,
+    parser::{encode_instruction, Dialect},
+};
+
+// Deepest call stack of the original COSMAC VIP interpreter
+pub const MAX_STACK_DEPTH: usize = 16;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Quirk {
+    Shift,
+    LoadStore,
+    Jump,
+}
+
+impl fmt::Display for Quirk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quirk::Shift => write!(f, "shift"),
+            Quirk::LoadStore => write!(f, "load/store"),
+            Quirk::Jump => write!(f, "jump"),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Platform {
+    OriginalChip8,
+    ModernChip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    // Id of the platform in the ROM database
+    pub fn id(&self) -> &'static str {
+        match self {
+            Platform::OriginalChip8 => "originalChip8",
+            Platform::ModernChip8 => "modernChip8",
+            Platform::SuperChip => "superchip",
+            Platform::XoChip => "xochip",
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Finding {
+    // A reachable word that isn't a CHIP-8 instruction
+    UnknownOpcode {
+        address: u16,
+        opcode: u16,
+    },
+    // An instruction of a later platform, unknown to plain CHIP-8
+    Extension {
+        address: u16,
+        opcode: u16,
+        platform: Platform,
+    },
+    // `0nnn`, running machine code of the COSMAC VIP
+    MachineCodeCall {
+        address: u16,
+        target: u16,
+    },
+    // An instruction that behaves differently between interpreters
+    QuirkSensitive {
+        address: u16,
+        quirk: Quirk,
+    },
+    // Memory read before anything was written to it
+    UninitializedRead {
+        address: u16,
+        target: u16,
+    },
+    // Calls nested deeper than the stack, `None` for unbounded recursion
+    StackOverflow {
+        depth: Option<usize>,
+    },
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Finding::UnknownOpcode { address, opcode } => {
+                write!(f, "{address:#05X}: unknown opcode {opcode:04X}")
+            }
+            Finding::Extension {
+                address,
+                opcode,
+                platform,
+            } => write!(f, "{address:#05X}: {opcode:04X} needs {}", platform.id()),
+            Finding::MachineCodeCall { address, target } => {
+                write!(f, "{address:#05X}: calls machine code at {target:#05X}")
+            }
+            Finding::QuirkSensitive { address, quirk } => {
+                write!(f, "{address:#05X}: depends on the {quirk} quirk")
+            }
+            Finding::UninitializedRead { address, target } => {
+                write!(
+                    f,
+                    "{address:#05X}: reads uninitialized memory at {target:#05X}"
+                )
+            }
+            Finding::StackOverflow { depth: Some(depth) } => {
+                write!(f, "calls nest {depth} deep, more than {MAX_STACK_DEPTH}")
+            }
+            Finding::StackOverflow { depth: None } => {
+                write!(f, "recursive calls can overflow the stack")
+            }
+        }
+    }
+}
+
+pub struct Report {
+    pub findings: Vec<Finding>,
+    // The most likely platform the ROM was written for
+    pub platform: Platform,
+}
+
+/**
+ *  Checks the code reachable from 0x200 for opcodes that don't decode,
+ *  instructions from later platforms, machine code calls, instructions whose
+ *  behavior depends on quirks, reads of memory that was never written and
+ *  calls nested deeper than the stack. Only what the control flow analysis
+ *  finds is checked, so code behind `Bnnn` isn't.
+ */
+pub fn lint(program: &[u8], dialect: Dialect) -> Report {
+    let graph = analyze(program, dialect);
+    let mut findings = vec![];
+
+    for block in graph.blocks.values() {
+        for &(address, instruction) in &block.instructions {
+            findings.extend(check_instruction(address, instruction));
+        }
+    }
+    findings.extend(uninitialized_reads(&graph, program.len()));
+    match stack_depth(&graph) {
+        Some(depth) if depth <= MAX_STACK_DEPTH => {}
+        depth => findings.push(Finding::StackOverflow { depth }),
+    }
+
+    let platform = suggest_platform(&findings);
+    Report { findings, platform }
+}
+
+fn check_instruction(address: u16, instruction: Instruction) -> Option<Finding> {
+    let quirk = |quirk| Some(Finding::QuirkSensitive { address, quirk });
+    match instruction {
+        Instruction::ShiftRight(_, _) | Instruction::ShiftLeft(_, _) => quirk(Quirk::Shift),
+        Instruction::StoreRegRange(_) | Instruction::LoadRegRange(_) => quirk(Quirk::LoadStore),
+        Instruction::JumpToPlusV0(_) => quirk(Quirk::Jump),
+        Instruction::StoreFlags(_) | Instruction::LoadFlags(_) => Some(Finding::Extension {
+            address,
+            opcode: opcode(instruction),
+            platform: Platform::SuperChip,
+        }),
+        // Sprites 16 pixels wide
+        Instruction::Display(_, _, 0) => Some(Finding::Extension {
+            address,
+            opcode: opcode(instruction),
+            platform: Platform::SuperChip,
+        }),
+        Instruction::Sys(target) => Some(match extension(target) {
+            Some(platform) => Finding::Extension {
+                address,
+                opcode: target,
+                platform,
+            },
+            None => Finding::MachineCodeCall { address, target },
+        }),
+        Instruction::Data(opcode) => Some(match extension(opcode) {
+            Some(platform) => Finding::Extension {
+                address,
+                opcode,
+                platform,
+            },
+            None => Finding::UnknownOpcode { address, opcode },
+        }),
+        _ => None,
+    }
+}
+
+// Opcodes only known to SUPER-CHIP and XO-CHIP
+fn extension(opcode: u16) -> Option<Platform> {
+    let [high, low] = opcode.to_be_bytes();
+    match (high >> 4, high & 0xF, low) {
+        // 00Cn scroll down, 00FB - 00FF scroll, exit and resolution
+        (0x0, 0x0, 0xC0..=0xCF | 0xFB..=0xFF) => Some(Platform::SuperChip),
+        // Fx30 big font
+        (0xF, _, 0x30) => Some(Platform::SuperChip),
+        // 00Dn scroll up
+        (0x0, 0x0, 0xD0..=0xDF) => Some(Platform::XoChip),
+        // 5xy2 / 5xy3 save and load a register range
+        (0x5, _, low) if low & 0xF == 2 || low & 0xF == 3 => Some(Platform::XoChip),
+        // F000 long I, Fx01 plane, F002 audio, Fx3A pitch
+        (0xF, 0x0, 0x00 | 0x02) | (0xF, _, 0x01 | 0x3A) => Some(Platform::XoChip),
+        _ => None,
+    }
+}
+
+// The first 2 bytes of the instruction
+fn opcode(instruction: Instruction) -> u16 {
+    let bytes = encode_instruction(instruction);
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+/**
+ *  Follows I through each block, from `Annn` or `Fx29` until something
+ *  changes it in a way that isn't known statically. Memory is initialized if
+ *  it's part of the program or the font, or if a store with a known I writes
+ *  it anywhere in the ROM.
+ */
+fn uninitialized_reads(graph: &ControlFlowGraph, length: usize) -> Vec<Finding> {
+    let mut initialized = vec![false; MEMORY_SIZE];
+    let program = PROGRAM_ADDRESS as usize..(PROGRAM_ADDRESS as usize + length).min(MEMORY_SIZE);
+    initialized[program].fill(true);
+    initialized[FONT_ADDRESS as usize..][..FONT.len()].fill(true);
+
+    // Memory read by each instruction, from its address
+    let mut reads = BTreeMap::new();
+    for block in graph.blocks.values() {
+        let mut i = None;
+        for &(address, instruction) in &block.instructions {
+            let accessed =
+                |length: usize| i.map(|i: u16| i as usize..(i as usize + length).min(MEMORY_SIZE));
+            match instruction {
+                Instruction::SetI(address) => i = Some(address),
+                Instruction::SetSpriteLocation(_) => i = Some(FONT_ADDRESS),
+                Instruction::StoreBCD(_) => {
+                    if let Some(range) = accessed(3) {
+                        initialized[range].fill(true);
+                    }
+                }
+                Instruction::StoreRegRange(x) => {
+                    if let Some(range) = accessed(x as usize + 1) {
+                        initialized[range].fill(true);
+                    }
+                    i = None;
+                }
+                Instruction::LoadRegRange(x) => {
+                    if let Some(range) = accessed(x as usize + 1) {
+                        reads.insert(address, range);
+                    }
+                    i = None;
+                }
+                Instruction::Display(_, _, n) => {
+                    // Dxy0 draws 16 rows of 2 bytes
+                    let length = if n == 0 { 32 } else { n as usize };
+                    if let Some(range) = accessed(length) {
+                        reads.insert(address, range);
+                    }
+                }
+                Instruction::AddToI(_) => i = None,
+                _ => {}
+            }
+        }
+    }
+
+    reads
+        .into_iter()
+        .filter_map(|(address, range)| {
+            let target = range.into_iter().find(|target| !initialized[*target])?;
+            Some(Finding::UninitializedRead {
+                address,
+                target: target as u16,
+            })
+        })
+        .collect()
+}
+
+// Deepest nesting of calls from 0x200, `None` if calls are recursive
+fn stack_depth(graph: &ControlFlowGraph) -> Option<usize> {
+    fn depth(
+        graph: &ControlFlowGraph,
+        entry: u16,
+        visiting: &mut BTreeSet<u16>,
+        known: &mut BTreeMap<u16, usize>,
+    ) -> Option<usize> {
+        if let Some(depth) = known.get(&entry) {
+            return Some(*depth);
+        }
+        if !visiting.insert(entry) {
+            return None;
+        }
+        let mut deepest = 0;
+        for &callee in graph.calls.get(&entry).into_iter().flatten() {
+            deepest = deepest.max(1 + depth(graph, callee, visiting, known)?);
+        }
+        visiting.remove(&entry);
+        known.insert(entry, deepest);
+        Some(deepest)
+    }
+
+    depth(
+        graph,
+        PROGRAM_ADDRESS,
+        &mut BTreeSet::new(),
+        &mut BTreeMap::new(),
+    )
+}
+
+// The most capable platform any instruction needs. Machine code calls only
+// work on the COSMAC VIP, otherwise the modern CHIP-8 quirks are the safest.
+fn suggest_platform(findings: &[Finding]) -> Platform {
+    let needs = |platform| {
+        findings.iter().any(
+            |finding| matches!(finding, Finding::Extension { platform: p, .. } if *p == platform),
+        )
+    };
+    if needs(Platform::XoChip) {
+        Platform::XoChip
+    } else if needs(Platform::SuperChip) {
+        Platform::SuperChip
+    } else if findings
+        .iter()
+        .any(|finding| matches!(finding, Finding::MachineCodeCall { .. }))
+    {
+        Platform::OriginalChip8
+    } else {
+        Platform::ModernChip8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lint, Finding, Platform, Quirk};
+    use crate::{parser::Dialect, test_data::DATA};
+
+    #[test]
+    fn ibm_logo_is_clean() {
+        let report = lint(&DATA, Dialect::CHIP8);
+        assert!(report.findings.is_empty());
+        assert_eq!(report.platform, Platform::ModernChip8);
+    }
+
+    #[test]
+    fn report_reachable_opcodes() {
+        let program = [
+            0x01, 0x23, // 200: SYS 0x123
+            0x81, 0x26, // 202: SHR V1, V2
+            0xF2, 0x65, // 204: LD V2, [I]
+            0xE1, 0x00, // 206: unknown
+            0xB2, 0x00, // 208: JP V0, 0x200
+            0xE1, 0x00, // 20A: data, never reached
+        ];
+        let report = lint(&program, Dialect::CHIP8);
+
+        assert_eq!(
+            report.findings,
+            [
+                Finding::MachineCodeCall {
+                    address: 0x200,
+                    target: 0x123
+                },
+                Finding::QuirkSensitive {
+                    address: 0x202,
+                    quirk: Quirk::Shift
+                },
+                Finding::QuirkSensitive {
+                    address: 0x204,
+                    quirk: Quirk::LoadStore
+                },
+                Finding::UnknownOpcode {
+                    address: 0x206,
+                    opcode: 0xE100
+                },
+                Finding::QuirkSensitive {
+                    address: 0x208,
+                    quirk: Quirk::Jump
+                },
+            ]
+        );
+        assert_eq!(report.platform, Platform::OriginalChip8);
+    }
+
+    #[test]
+    fn suggest_platform_from_extensions() {
+        // LD R, V3 ; EXIT
+        let report = lint(&[0xF3, 0x75, 0x00, 0xFD], Dialect::CHIP8);
+        assert_eq!(report.platform, Platform::SuperChip);
+
+        // LD I, long ; SAVE V1 - V2
+        let report = lint(
+            &[0xF0, 0x00, 0x03, 0x00, 0x51, 0x22, 0x12, 0x06],
+            Dialect::CHIP8,
+        );
+        assert_eq!(report.platform, Platform::XoChip);
+    }
+
+    #[test]
+    fn find_uninitialized_reads() {
+        let program = [
+            0xA3, 0x00, // 200: LD I, 0x300
+            0xF1, 0x55, // 202: LD [I], V1
+            0xA3, 0x00, // 204: LD I, 0x300
+            0xD0, 0x02, // 206: DRW V0, V0, 2
+            0xA4, 0x00, // 208: LD I, 0x400
+            0xD0, 0x01, // 20A: DRW V0, V0, 1
+            0x12, 0x0C, // 20C: JP 0x20C
+        ];
+        let report = lint(&program, Dialect::CHIP8);
+
+        let reads = report
+            .findings
+            .into_iter()
+            .filter(|finding| matches!(finding, Finding::UninitializedRead { .. }))
+            .collect::<Vec<Finding>>();
+        assert_eq!(
+            reads,
+            [Finding::UninitializedRead {
+                address: 0x20A,
+                target: 0x400
+            }]
+        );
+    }
+
+    #[test]
+    fn detect_deep_and_recursive_calls() {
+        // 17 nested calls, each subroutine calling the next one
+        let mut program = vec![];
+        for index in 1..=17u16 {
+            program.extend((0x2000 | (0x200 + 2 * index)).to_be_bytes());
+        }
+        program.extend([0x00, 0xEE]);
+        let report = lint(&program, Dialect::CHIP8);
+        assert_eq!(
+            report.findings,
+            [Finding::StackOverflow { depth: Some(17) }]
+        );
+
+        // CALL 0x204 ; JP 0x202 ; CALL 0x204 ; RET
+        let report = lint(
+            &[0x22, 0x04, 0x12, 0x02, 0x22, 0x04, 0x00, 0xEE],
+            Dialect::CHIP8,
+        );
+        assert_eq!(report.findings, [Finding::StackOverflow { depth: None }]);
+    }
+}
@@ -0,0 +1,447 @@
+use std::{fmt, fs, io, path::Path};
+
+use serde::Deserialize;
+
+use crate::{
+    config::{QuirkSettings, Settings},
+    parser::Variant,
+};
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    // Line number of the invalid hex text, starting at 1
+    InvalidHexText(usize),
+    InvalidIntelHex(usize),
+    InvalidCartridge,
+    // Octo cartridges hold source code, which has to be assembled first. The
+    // cartridge keeps its options for running the assembled program.
+    UnassembledSource(Box<Cartridge>),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "{error}"),
+            LoadError::InvalidHexText(line) => write!(f, "invalid hex text on line {line}"),
+            LoadError::InvalidIntelHex(line) => {
+                write!(f, "invalid Intel HEX record on line {line}")
+            }
+            LoadError::InvalidCartridge => write!(f, "invalid Octo cartridge"),
+            LoadError::UnassembledSource(_) => {
+                write!(
+                    f,
+                    "Octo cartridges contain source code, assemble it with Octo first and pass the binary with --program"
+                )
+            }
+        }
+    }
+}
+
+// A program and whatever its file says about how to run it
+#[derive(PartialEq, Default, Debug)]
+pub struct RomFile {
+    pub program: Vec<u8>,
+    // Platform id as used in the ROM database, e.g. `superchip`
+    pub platform: Option<String>,
+    pub settings: Settings,
+}
+
+// Source code and options embedded in an Octo cartridge
+#[derive(PartialEq, Default, Debug)]
+pub struct Cartridge {
+    pub source: String,
+    pub platform: Option<String>,
+    pub settings: Settings,
+}
+
+impl Cartridge {
+    // The program assembled from `source`, run with the cartridge's options
+    pub fn with_program(self, program: Vec<u8>) -> RomFile {
+        RomFile {
+            program,
+            platform: self.platform,
+            settings: self.settings,
+        }
+    }
+}
+
+pub fn load_rom(path: &Path) -> Result<RomFile, LoadError> {
+    let bytes = fs::read(path).map_err(LoadError::Io)?;
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    parse_rom(&bytes, extension.as_deref())
+}
+
+/**
+ *  Detects the format from the content, falling back to a raw binary:
+ *  - Octo cartridge GIFs
+ *  - Intel HEX, lines starting with `:`
+ *  - Hex text, like `00E0 A22A` or the listing in `docs/sample_program_explanation.md`
+ *  - Raw binaries (`.ch8`, `.sc8`, `.xo8`), the extension is the platform,
+ *    or the variant for `.c8x`, `.c8e` and `.mc8`
+ */
+pub fn parse_rom(bytes: &[u8], extension: Option<&str>) -> Result<RomFile, LoadError> {
+    if bytes.starts_with(b"GIF8") {
+        let cartridge = parse_cartridge(bytes)?;
+        return Err(LoadError::UnassembledSource(Box::new(cartridge)));
+    }
+
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        let text = text.trim_start();
+        if text.starts_with(':') {
+            return parse_intel_hex(text).map(rom_file);
+        }
+        if is_hex_text(text) {
+            return parse_hex_text(text).map(rom_file);
+        }
+    }
+
+    let platform = match extension {
+        Some("sc8") => Some("superchip".to_owned()),
+        Some("xo8") => Some("xochip".to_owned()),
+        _ => None,
+    };
+    let variant = match extension {
+        Some("c8x") => Some(Variant::Chip8X),
+        Some("c8e") => Some(Variant::Chip8E),
+        Some("mc8") => Some(Variant::MegaChip),
+        _ => None,
+    };
+    Ok(RomFile {
+        program: bytes.to_vec(),
+        platform,
+        settings: Settings {
+            variant,
+            ..Settings::default()
+        },
+    })
+}
+
+fn rom_file(program: Vec<u8>) -> RomFile {
+    RomFile {
+        program,
+        ..RomFile::default()
+    }
+}
+
+// Removes comments: everything after `->`, `//`, `;` or `#`, and lines
+// starting with `--`
+fn strip_comment(line: &str) -> &str {
+    let line = line.trim();
+    if line.starts_with("--") {
+        return "";
+    }
+    let end = ["->", "//", ";", "#"]
+        .iter()
+        .filter_map(|comment| line.find(comment))
+        .min()
+        .unwrap_or(line.len());
+    &line[..end]
+}
+
+fn is_hex_text(text: &str) -> bool {
+    let mut words = text
+        .lines()
+        .flat_map(|line| strip_comment(line).split_whitespace())
+        .peekable();
+    words.peek().is_some() && words.all(|word| parse_hex_word(word).is_some())
+}
+
+// `00E0`, `0x00E0` or `0038003F`, any even number of digits
+fn parse_hex_word(word: &str) -> Option<Vec<u8>> {
+    let digits = word.strip_prefix("0x").unwrap_or(word);
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(digits.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+pub fn parse_hex_text(text: &str) -> Result<Vec<u8>, LoadError> {
+    let mut program = vec![];
+    for (index, line) in text.lines().enumerate() {
+        for word in strip_comment(line).split_whitespace() {
+            let bytes = parse_hex_word(word).ok_or(LoadError::InvalidHexText(index + 1))?;
+            program.extend(bytes);
+        }
+    }
+    Ok(program)
+}
+
+// Data records are placed by address, starting at the lowest one. Gaps are
+// filled with zeros.
+pub fn parse_intel_hex(text: &str) -> Result<Vec<u8>, LoadError> {
+    let mut records: Vec<(usize, Vec<u8>)> = vec![];
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = LoadError::InvalidIntelHex(index + 1);
+        let bytes = line
+            .strip_prefix(':')
+            .and_then(parse_hex_word)
+            .ok_or(error)?;
+
+        let checksum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if bytes.len() < 5 || bytes.len() != 5 + bytes[0] as usize || checksum != 0 {
+            return Err(LoadError::InvalidIntelHex(index + 1));
+        }
+
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+        match bytes[3] {
+            0x00 => records.push((address, bytes[4..bytes.len() - 1].to_vec())),
+            0x01 => break,
+            // Extended addresses can't fit in CHIP-8 memory
+            _ => return Err(LoadError::InvalidIntelHex(index + 1)),
+        }
+    }
+
+    let start = records
+        .iter()
+        .map(|(address, _)| *address)
+        .min()
+        .unwrap_or(0);
+    let mut program = vec![];
+    for (address, data) in records {
+        let offset = address - start;
+        if program.len() < offset + data.len() {
+            program.resize(offset + data.len(), 0);
+        }
+        program[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    Ok(program)
+}
+
+#[derive(Deserialize)]
+struct CartridgePayload {
+    program: String,
+    #[serde(default)]
+    options: OctoOptions,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct OctoOptions {
+    tickrate: Option<u32>,
+    background_color: Option<String>,
+    fill_color: Option<String>,
+    fill_color2: Option<String>,
+    blend_color: Option<String>,
+    shift_quirks: bool,
+    load_store_quirks: bool,
+    jump_quirks: bool,
+    logic_quirks: bool,
+    max_size: Option<u32>,
+}
+
+/**
+ *  Octo cartridges hide their payload in the pixels of the GIF: every pixel
+ *  holds a nibble in the low 4 bits of its color index, high nibble first.
+ *  The payload is a 32-bit big endian length followed by that many bytes of
+ *  JSON with the `program` source and the Octo `options`.
+ */
+pub fn parse_cartridge(bytes: &[u8]) -> Result<Cartridge, LoadError> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options
+        .read_info(bytes)
+        .map_err(|_| LoadError::InvalidCartridge)?;
+
+    let mut nibbles = vec![];
+    while let Some(frame) = decoder
+        .read_next_frame()
+        .map_err(|_| LoadError::InvalidCartridge)?
+    {
+        nibbles.extend(frame.buffer.iter().map(|index| index & 0xF));
+    }
+    let payload = nibbles
+        .chunks_exact(2)
+        .map(|pair| (pair[0] << 4) | pair[1])
+        .collect::<Vec<u8>>();
+
+    let length = payload
+        .get(0..4)
+        .map(|length| u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize)
+        .ok_or(LoadError::InvalidCartridge)?;
+    let json = payload
+        .get(4..4 + length)
+        .ok_or(LoadError::InvalidCartridge)?;
+    let payload: CartridgePayload =
+        serde_json::from_slice(json).map_err(|_| LoadError::InvalidCartridge)?;
+
+    let options = payload.options;
+    let palette = match (options.background_color, options.fill_color) {
+        (Some(background), Some(fill)) => {
+            let mut colors = vec![background, fill];
+            if let (Some(fill2), Some(blend)) = (options.fill_color2, options.blend_color) {
+                colors.extend([fill2, blend]);
+            }
+            Some(colors.join(","))
+        }
+        _ => None,
+    };
+    // Octo picks the platform by the memory available to the program
+    let platform = match options.max_size {
+        Some(3216) => Some("originalChip8"),
+        Some(3583) => Some("superchip"),
+        Some(65024) => Some("xochip"),
+        _ => None,
+    };
+
+    Ok(Cartridge {
+        source: payload.program,
+        platform: platform.map(str::to_owned),
+        settings: Settings {
+            palette,
+            ticks_per_frame: options.tickrate,
+            quirks: QuirkSettings {
+                shift_uses_vy: Some(!options.shift_quirks),
+                load_store_increments_i: Some(!options.load_store_quirks),
+                jump_uses_vx: Some(options.jump_quirks),
+                logic_resets_vf: Some(options.logic_quirks),
+                ..QuirkSettings::default()
+            },
+            ..Settings::default()
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_cartridge, parse_rom, LoadError};
+    use crate::parser::Variant;
+    use crate::test_data::DATA;
+
+    #[test]
+    fn load_raw_binaries() {
+        let rom = parse_rom(&DATA, Some("ch8")).unwrap();
+        assert_eq!(rom.program, DATA);
+        assert_eq!(rom.platform, None);
+
+        let rom = parse_rom(&DATA, Some("sc8")).unwrap();
+        assert_eq!(rom.platform.as_deref(), Some("superchip"));
+
+        let rom = parse_rom(&DATA, Some("c8x")).unwrap();
+        assert_eq!(rom.settings.variant, Some(Variant::Chip8X));
+
+        let rom = parse_rom(&DATA, Some("mc8")).unwrap();
+        assert_eq!(rom.settings.variant, Some(Variant::MegaChip));
+    }
+
+    #[test]
+    fn load_sample_program_listing() {
+        let listing = include_str!("../docs/sample_program_explanation.md");
+        let rom = parse_rom(listing.as_bytes(), Some("md")).unwrap();
+
+        assert_eq!(rom.program, DATA);
+    }
+
+    #[test]
+    fn load_hex_text() {
+        let text = "0x00E0 A22A ; clear and set I\n\n600C6108 // two at once\n";
+        let rom = parse_rom(text.as_bytes(), None).unwrap();
+        assert_eq!(
+            rom.program,
+            [0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08]
+        );
+    }
+
+    #[test]
+    fn load_intel_hex() {
+        let text = "\
+            :0402000000E0A22A4E\n\
+            :02020600D01F07\n\
+            :00000001FF\n";
+        let rom = parse_rom(text.as_bytes(), Some("hex")).unwrap();
+        assert_eq!(
+            rom.program,
+            [0x00, 0xE0, 0xA2, 0x2A, 0x00, 0x00, 0xD0, 0x1F]
+        );
+
+        let text = ":0402000000E0A22A4F\n";
+        assert!(matches!(
+            parse_rom(text.as_bytes(), None),
+            Err(LoadError::InvalidIntelHex(1))
+        ));
+    }
+
+    // Builds a cartridge with `json` as payload
+    fn cartridge(json: &str) -> Vec<u8> {
+        let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+        payload.extend(json.as_bytes());
+        // A label color in the high nibble doesn't change the data
+        let pixels = payload
+            .iter()
+            .flat_map(|byte| [0x10 | (byte >> 4), 0x20 | (byte & 0xF)])
+            .collect::<Vec<u8>>();
+
+        let palette = (0..=255)
+            .flat_map(|index| [index, index, index])
+            .collect::<Vec<u8>>();
+        let mut gif = vec![];
+        {
+            let width = pixels.len() as u16;
+            let mut encoder = gif::Encoder::new(&mut gif, width, 1, &palette).unwrap();
+            let frame = gif::Frame {
+                width,
+                height: 1,
+                buffer: pixels.into(),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&frame).unwrap();
+        }
+        gif
+    }
+
+    #[test]
+    fn read_octo_cartridge() {
+        let gif = cartridge(
+            r##"{
+                "program": ": main\n  loop again",
+                "options": {
+                    "tickrate": 20,
+                    "fillColor": "#FFCC00",
+                    "backgroundColor": "#996600",
+                    "shiftQuirks": true,
+                    "jumpQuirks": true,
+                    "maxSize": 3583
+                }
+            }"##,
+        );
+        let cartridge = parse_cartridge(&gif).unwrap();
+
+        assert_eq!(cartridge.source, ": main\n  loop again");
+        assert_eq!(cartridge.platform.as_deref(), Some("superchip"));
+        assert_eq!(cartridge.settings.ticks_per_frame, Some(20));
+        assert_eq!(
+            cartridge.settings.palette.as_deref(),
+            Some("#996600,#FFCC00")
+        );
+        let quirks = cartridge.settings.quirks;
+        assert_eq!(quirks.shift_uses_vy, Some(false));
+        assert_eq!(quirks.jump_uses_vx, Some(true));
+
+        let Err(LoadError::UnassembledSource(cartridge)) = parse_rom(&gif, Some("gif")) else {
+            panic!("cartridges need assembling");
+        };
+        let rom = cartridge.with_program(vec![0x12, 0x00]);
+        assert_eq!(rom.program, [0x12, 0x00]);
+        assert_eq!(rom.platform.as_deref(), Some("superchip"));
+        assert_eq!(rom.settings.ticks_per_frame, Some(20));
+    }
+
+    #[test]
+    fn reject_invalid_cartridge() {
+        assert!(matches!(
+            parse_cartridge(&cartridge("{}")),
+            Err(LoadError::InvalidCartridge)
+        ));
+    }
+}
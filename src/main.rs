@@ -1,85 +1,1185 @@
-use std::{thread, time::Duration};
+use std::{
+    collections::VecDeque,
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use context::Context;
+use chip_8::{
+    analysis::{analyze, ControlFlowGraph},
+    audio::{pcm_wav, square_wave},
+    capture::{save_png, Recorder},
+    cheat::{Cheat, CheatList, Comparison, Search},
+    config::{Config, Settings},
+    context::{Context, MEMORY_SIZE},
+    database::Database,
+    filter::{FilterMode, PersistenceFilter},
+    flags::FlagStore,
+    lint::lint,
+    loader::{load_rom, LoadError, RomFile},
+    megachip::{self, DigitizedSound},
+    memory_view::{
+        sprite_at_i, stack_frames, Highlight, MemoryView, RECENT_FRAMES, ROW_BYTES, VISIBLE_ROWS,
+    },
+    palette::{Palette, PALETTE_NAMES},
+    parser::{Dialect, Variant},
+    profile::{Profiler, HEATMAP_SIZE},
+    render::{
+        convert_color_zones, convert_faded, pad_frame, render_frame, scale_frame, DEFAULT_SCALE,
+    },
+    script::{Script, ScriptError},
+    test_data,
+    timing::VipTiming,
+    vip::Vip,
+};
 use macroquad::{
-    color::{GRAY, WHITE},
+    audio::{load_sound_from_bytes, play_sound, stop_sound, PlaySoundParams, Sound},
+    color::{Color, BLACK, WHITE},
+    input::{is_key_down, is_key_pressed, KeyCode},
     math::vec2,
+    shapes::{draw_rectangle, draw_rectangle_lines},
+    text::{draw_text, measure_text},
     texture::{draw_texture_ex, DrawTextureParams, Texture2D},
-    time::{self, get_frame_time},
-    window::{clear_background, next_frame, screen_height, screen_width},
+    window::{clear_background, next_frame, screen_height, screen_width, Conf},
 };
 
-mod context;
-mod instructions;
-mod parser;
-mod test_data;
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+// Size of a byte of memory in the heatmap overlay
+const HEATMAP_SCALE: f32 = 3.0;
+
+const PANEL_FONT_SIZE: u16 = 16;
+// Size of a pixel in the sprite view of the memory panel
+const SPRITE_VIEW_SCALE: f32 = 8.0;
+const PC_COLOR: Color = Color::new(0.8, 0.7, 0.0, 1.0);
+const I_COLOR: Color = Color::new(0.0, 0.6, 0.8, 1.0);
+const WRITTEN_COLOR: Color = Color::new(0.8, 0.1, 0.1, 1.0);
+// Matches of a cheat search listed in the memory panel
+const VISIBLE_MATCHES: usize = 8;
+
+// Space around the display when the window opens
+const WINDOW_MARGIN: i32 = 40;
+
+const DEFAULT_TICKS_PER_FRAME: u32 = 1;
+const DEFAULT_FRAME_DURATION_MS: u64 = 60;
+const DEFAULT_AUDIO_FREQUENCY: f32 = 440.0;
+const DEFAULT_AUDIO_VOLUME: f32 = 0.5;
+const AUDIO_SAMPLE_RATE: u32 = 44100;
 
-struct Rect {
-    pub x: f32,
-    pub y: f32,
-    pub width: f32,
-    pub height: f32,
+// Options followed by a value. The first argument that isn't an option or a
+// value is the path of the ROM.
+const VALUE_OPTIONS: [&str; 19] = [
+    "--palette",
+    "--filter",
+    "--record",
+    "--frames",
+    "--screenshot",
+    "--scale",
+    "--speed",
+    "--config",
+    "--flags",
+    "--cheats",
+    "--script",
+    "--graph",
+    "--profile",
+    "--vip",
+    "--vip-monitor",
+    "--variant",
+    "--program",
+    "--input",
+    "--database",
+];
+
+struct Frontend {
+    palette: Palette,
+    filter: Option<PersistenceFilter>,
+    // Size of the frames shown and captured, 256x192 on MEGA-CHIP
+    width: usize,
+    height: usize,
+    // Size of a pixel of the frames on screen and in captures
+    scale: usize,
+    ticks_per_frame: u32,
+    // Replaces `ticks_per_frame` when the VIP timing model is on
+    timing: Option<VipTiming>,
+    frame_duration: Duration,
+    settings: Settings,
+    // Game controls from the ROM database, e.g. `("up", 5)`
+    controls: Vec<(String, u8)>,
+    // Where the RPL user flags of the ROM are saved
+    flags: Option<FlagStore>,
+    cheats: CheatList,
+    script: Option<Script>,
+    profiler: Option<Profiler>,
+    // Runs the ROM on the original interpreter instead, `context` mirrors it
+    vip: Option<Vip>,
+    // Bytes still to send to the CHIP-8X / CHIP-8E input port, from `--input`
+    port_input: VecDeque<u8>,
+}
+
+// Keys typing the hex digits 0-F
+const HEX_KEYS: [KeyCode; 16] = [
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+];
+
+// Cheat search of the memory panel, and the match to freeze
+#[derive(Default)]
+struct CheatSearch {
+    search: Option<Search>,
+    selected: usize,
+    // Hex digits of the value to search for, while it's being typed
+    value: Option<Vec<u8>>,
 }
 
-const WIDTH: u16 = 64;
-const HEIGHT: u16 = 32;
+fn main() {
+    let rom = match rom_path() {
+        Some(path) => open_rom(&path),
+        None => RomFile {
+            program: test_data::DATA.to_vec(),
+            ..RomFile::default()
+        },
+    };
+    let data = rom.program;
+    let config = load_config();
+    let database = database_option();
+    let info = database.lookup(&data);
+
+    let mut settings = config.settings.clone();
+    if let Some(platform) = rom.platform.as_deref().and_then(|id| database.platform(id)) {
+        settings.quirks = settings.quirks.merge(&platform.quirks.to_quirks());
+    }
+    if let Some(info) = &info {
+        settings = settings.merge(&info.settings());
+    }
+    settings = settings.merge(&rom.settings);
+    if let Some(overrides) = config.rom_overrides(&data) {
+        settings = settings.merge(overrides);
+    }
+    settings = settings.merge(&settings_from_args());
+
+    let mut context: Context = Context::new(&data, unix_time());
+    context.quirks = settings.quirks.apply(context.quirks);
+    if let Some(depth) = settings.stack_depth {
+        context.stack_depth = depth;
+    }
+    if let Some(variant) = settings.variant {
+        context.set_variant(variant);
+    }
+    if let Some(font) = &settings.font {
+        match font.as_slice().try_into() {
+            Ok(font) => context.load_font(font),
+            Err(_) => {
+                eprintln!("The font must have 80 bytes, 5 for each hex digit");
+                process::exit(1);
+            }
+        }
+    }
+
+    // Decoded with the opcodes of the variant the ROM runs as
+    if let Some(format) = option_value("--graph") {
+        print_graph(&data, context.dialect, &format);
+        return;
+    }
+    if env::args().any(|arg| arg == "--lint") {
+        print_lint(&data, context.dialect);
+        return;
+    }
 
-const VIEWPORT_WIDTH: f32 = 64.0 * 10.0;
-const VIEWPORT_HEIGHT: f32 = 32.0 * 10.0;
+    let flags = open_flags(&data);
+    if let Some(flags) = &flags {
+        context.load_flags(flags.flags());
+    }
 
-#[macroquad::main("Chip-8 Emulator")]
-async fn main() {
-    let data = test_data::DATA;
-    let mut context: Context = Context::new(&data, time::get_time() as u64);
+    // MEGA-CHIP pixels are a quarter of the size of CHIP-8 ones
+    let scale = settings.scale.unwrap_or(DEFAULT_SCALE);
+    let (width, height, scale) = match context.mega_chip {
+        Some(_) => (megachip::WIDTH, megachip::HEIGHT, (scale / 4).max(1)),
+        None => (WIDTH, HEIGHT, scale),
+    };
+    let mut frontend = Frontend {
+        palette: palette_setting(&settings),
+        filter: filter_option(),
+        width,
+        height,
+        scale,
+        ticks_per_frame: settings.ticks_per_frame.unwrap_or(DEFAULT_TICKS_PER_FRAME),
+        timing: settings.vip_timing.unwrap_or(false).then(VipTiming::new),
+        frame_duration: Duration::from_millis(
+            settings
+                .frame_duration_ms
+                .unwrap_or(DEFAULT_FRAME_DURATION_MS),
+        ),
+        settings,
+        controls: info.as_ref().map_or(vec![], |info| {
+            info.controls()
+                .map(|(control, key)| (control.to_owned(), key))
+                .collect()
+        }),
+        flags,
+        cheats: cheats_option(),
+        script: None,
+        profiler: option_value("--profile").map(|_| Profiler::new()),
+        vip: vip_option(&data),
+        port_input: input_option(),
+    };
+    frontend.script = script_option(&mut context, &frontend);
 
-    let graphics_buffer = convert_graphics_buffer(&context.get_flat_graphics_buffer());
-    let texture = Texture2D::from_rgba8(WIDTH, HEIGHT, &graphics_buffer);
+    let mut window_title = "Chip-8 Emulator".to_owned();
+    if let Some(info) = &info {
+        println!("{}", info.program.title);
+        if let Some(description) = &info.program.description {
+            println!("{description}");
+        }
+        if let Some(platform) = info.platform {
+            println!("Platform: {}", platform.name);
+        }
+        if !frontend.controls.is_empty() {
+            println!("Controls: {}", controls_text(&frontend));
+        }
+        window_title = format!("{} - {window_title}", info.program.title);
+    }
+
+    if env::args().any(|arg| arg == "--headless") {
+        run_headless(context, frontend);
+    } else {
+        let conf = Conf {
+            window_title,
+            window_width: (frontend.width * frontend.scale) as i32 + 2 * WINDOW_MARGIN,
+            window_height: (frontend.height * frontend.scale) as i32 + 2 * WINDOW_MARGIN,
+            ..Default::default()
+        };
+        macroquad::Window::from_config(conf, run_window(context, frontend));
+    }
+}
+
+async fn run_window(mut context: Context, mut frontend: Frontend) {
+    let [r, g, b, a] = frontend.palette.border;
+    let border = Color::from_rgba(r, g, b, a);
+    let viewport_width = (frontend.width * frontend.scale) as f32;
+    let viewport_height = (frontend.height * frontend.scale) as f32;
+
+    let mut frame = render_display(&context, &mut frontend);
+    let texture = Texture2D::from_rgba8(frontend.width as u16, frontend.height as u16, &frame);
     texture.set_filter(macroquad::texture::FilterMode::Nearest);
 
+    let keys = bind_keys("keypad", |key| frontend.settings.key_name(key));
+    let second_keys = bind_keys("second keypad", |key| {
+        frontend.settings.second_key_name(key)
+    });
+
+    let frequency = frontend
+        .settings
+        .audio_frequency
+        .unwrap_or(DEFAULT_AUDIO_FREQUENCY);
+    let volume = frontend
+        .settings
+        .audio_volume
+        .unwrap_or(DEFAULT_AUDIO_VOLUME);
+    let beep = load_sound_from_bytes(&square_wave(frequency, AUDIO_SAMPLE_RATE))
+        .await
+        .ok();
+    let mut beeping = false;
+    // MEGA-CHIP digitized sound playing, if it could be loaded
+    let mut digitized: Option<(DigitizedSound, Option<Sound>)> = None;
+    let controls = controls_text(&frontend);
+
+    let mut recorder =
+        option_value("--record").map(|path| start_recording(Path::new(&path), &frontend));
+
+    let size = HEATMAP_SIZE as u16;
+    let heatmap = Texture2D::from_rgba8(size, size, &vec![0; HEATMAP_SIZE * HEATMAP_SIZE * 4]);
+    heatmap.set_filter(macroquad::texture::FilterMode::Nearest);
+    let mut show_heatmap = false;
+
+    let mut memory_view = MemoryView::new(&context);
+    let mut show_memory = false;
+    let mut cheat_search = CheatSearch::default();
+    let mut paused = false;
+    // Labels for the stack view
+    let graph = analyze(&context.data, context.dialect);
+    let mut last_frame = Instant::now();
+    // Time spent unpaused, the clock of recordings
+    let mut clock = Duration::ZERO;
+
     loop {
-        clear_background(GRAY);
-        context.tick();
+        clear_background(border);
 
-        let graphics_buffer = convert_graphics_buffer(&context.get_flat_graphics_buffer());
+        context.keyboard_input = key_down(&keys);
+        context.second_keypad = key_down(&second_keys);
 
-        texture.update_from_bytes(
-            texture.width() as u32,
-            texture.height() as u32,
-            &graphics_buffer,
-        );
+        if !paused {
+            frame = run_frame(&mut context, &mut frontend);
+            memory_view.update(&context);
+
+            // A fault pauses with the memory panel open to show where
+            if let Some(fault) = context.fault {
+                eprintln!("Stopped: {fault}");
+                paused = true;
+                show_memory = true;
+            }
+        }
+
+        texture.update_from_bytes(texture.width() as u32, texture.height() as u32, &frame);
 
         draw_texture_ex(
             &texture,
-            (screen_width() / 2.0) - (VIEWPORT_WIDTH / 2.0),
-            (screen_height() / 2.0) - (VIEWPORT_HEIGHT / 2.0),
+            (screen_width() / 2.0) - (viewport_width / 2.0),
+            (screen_height() / 2.0) - (viewport_height / 2.0),
             WHITE,
             DrawTextureParams {
-                dest_size: Some(vec2(VIEWPORT_WIDTH, VIEWPORT_HEIGHT)),
+                dest_size: Some(vec2(viewport_width, viewport_height)),
                 ..Default::default()
             },
         );
 
-        thread::sleep(Duration::from_millis(60));
+        if !controls.is_empty() {
+            draw_text(&controls, 10.0, screen_height() - 12.0, 20.0, WHITE);
+        }
+
+        if let (true, Some(profiler)) = (show_heatmap, &frontend.profiler) {
+            heatmap.update_from_bytes(size as u32, size as u32, &profiler.heatmap());
+            draw_texture_ex(
+                &heatmap,
+                0.0,
+                0.0,
+                WHITE,
+                DrawTextureParams {
+                    dest_size: Some(vec2(HEATMAP_SCALE, HEATMAP_SCALE) * size as f32),
+                    ..Default::default()
+                },
+            );
+        }
+
+        if show_memory {
+            draw_memory_panel(&context, &memory_view, &graph, &cheat_search, paused);
+        }
+
+        if let Some(beep) = &beep {
+            let sounding = context.sound_timer > 0 && !paused;
+            if sounding && !beeping {
+                play_sound(
+                    beep,
+                    PlaySoundParams {
+                        looped: true,
+                        volume,
+                    },
+                );
+            } else if !sounding && beeping {
+                stop_sound(beep);
+            }
+            beeping = sounding;
+        }
+
+        let sound = context
+            .mega_chip
+            .as_ref()
+            .and_then(|mega| mega.sound.as_ref())
+            .filter(|_| !paused);
+        // A new sound starts playing, the one before stops
+        if sound != digitized.as_ref().map(|(sound, _)| sound) {
+            if let Some((_, Some(playing))) = digitized.take() {
+                stop_sound(&playing);
+            }
+            if let Some(sound) = sound {
+                let wav = pcm_wav(&sound.samples, sound.sample_rate as u32);
+                let loaded = load_sound_from_bytes(&wav).await.ok();
+                if let Some(loaded) = &loaded {
+                    let looped = sound.looped;
+                    play_sound(loaded, PlaySoundParams { looped, volume });
+                }
+                digitized = Some((sound.clone(), loaded));
+            }
+        }
+
+        // The sleep and the wait for vsync both take time, so recordings
+        // use the measured time frames were shown at
+        let now = Instant::now();
+        if !paused {
+            clock += now - last_frame;
+        }
+        last_frame = now;
+
+        // F12 takes a screenshot, F9 starts and stops recording a GIF
+        if is_key_pressed(KeyCode::F12) {
+            let path = format!("chip-8-{}.png", unix_time());
+            save_screenshot(Path::new(&path), &frame, &frontend);
+        }
+        if is_key_pressed(KeyCode::F9) {
+            recorder = match recorder.take() {
+                Some(recorder) => {
+                    stop_recording(recorder, clock);
+                    None
+                }
+                None => {
+                    let path = format!("chip-8-{}.gif", unix_time());
+                    Some(start_recording(Path::new(&path), &frontend))
+                }
+            };
+        }
+        if let Some(recorder) = recorder.as_mut().filter(|_| !paused) {
+            record_frame(recorder, &frame, clock, &frontend);
+        }
+
+        // F5 pauses, F8 shows the memory panel
+        if is_key_pressed(KeyCode::F5) {
+            paused = !paused;
+        }
+        if is_key_pressed(KeyCode::F8) {
+            show_memory = !show_memory;
+        }
+        if show_memory {
+            // Digits typed for a search value don't edit memory
+            let editing = paused && cheat_search.value.is_none();
+            edit_memory(&mut context, &mut memory_view, editing);
+        }
+        if show_memory && paused {
+            search_memory(&context, &mut cheat_search, &mut frontend.cheats);
+        }
+
+        // F6 shows the memory heatmap, profiling from then on, F7 saves the
+        // annotated disassembly
+        if is_key_pressed(KeyCode::F6) {
+            show_heatmap = !show_heatmap;
+            frontend.profiler.get_or_insert_with(Profiler::new);
+        }
+        if is_key_pressed(KeyCode::F7) {
+            if let Some(profiler) = &frontend.profiler {
+                let path = option_value("--profile")
+                    .unwrap_or_else(|| format!("chip-8-{}-profile.txt", unix_time()));
+                save_profile(Path::new(&path), profiler, &context);
+            }
+        }
+
+        thread::sleep(frontend.frame_duration);
         next_frame().await;
     }
 }
 
-fn convert_graphics_buffer(buffer: &[u8]) -> Vec<u8> {
-    let result = buffer
+// Arrows and page keys move the cursor, hex digits edit memory while paused
+fn edit_memory(context: &mut Context, view: &mut MemoryView, paused: bool) {
+    let row = ROW_BYTES as i32;
+    let moves = [
+        (KeyCode::Left, -1),
+        (KeyCode::Right, 1),
+        (KeyCode::Up, -row),
+        (KeyCode::Down, row),
+        (KeyCode::PageUp, -16 * row),
+        (KeyCode::PageDown, 16 * row),
+    ];
+    for (key, offset) in moves {
+        if is_key_pressed(key) {
+            view.move_cursor(offset);
+        }
+    }
+    if !paused {
+        return;
+    }
+
+    for (digit, key) in HEX_KEYS.into_iter().enumerate() {
+        if is_key_pressed(key) {
+            view.type_digit(context, digit as u8);
+        }
+    }
+}
+
+// N snapshots registers and memory for a new search, `=` `/` `.` `,` keep the
+// values unchanged, changed, increased or decreased since the last snapshot,
+// V and two hex digits keep the ones equal to the typed value, Tab selects the
+// next match and Enter freezes it at its current value
+fn search_memory(context: &Context, cheat_search: &mut CheatSearch, cheats: &mut CheatList) {
+    if let Some(digits) = cheat_search.value.as_mut() {
+        for (digit, key) in HEX_KEYS.into_iter().enumerate() {
+            if is_key_pressed(key) {
+                digits.push(digit as u8);
+            }
+        }
+        if let [high, low, ..] = digits[..] {
+            let search = cheat_search
+                .search
+                .get_or_insert_with(|| Search::new(context));
+            search.refine(context, Comparison::Equal((high << 4) | low));
+            cheat_search.selected = 0;
+            cheat_search.value = None;
+        } else if is_key_pressed(KeyCode::Escape) {
+            cheat_search.value = None;
+        }
+        return;
+    }
+
+    if is_key_pressed(KeyCode::N) {
+        *cheat_search = CheatSearch {
+            search: Some(Search::new(context)),
+            ..CheatSearch::default()
+        };
+    }
+    if is_key_pressed(KeyCode::V) {
+        cheat_search.value = Some(vec![]);
+    }
+    let Some(search) = cheat_search.search.as_mut() else {
+        return;
+    };
+
+    let comparisons = [
+        (KeyCode::Equal, Comparison::Unchanged),
+        (KeyCode::Slash, Comparison::Changed),
+        (KeyCode::Period, Comparison::Increased),
+        (KeyCode::Comma, Comparison::Decreased),
+    ];
+    for (key, comparison) in comparisons {
+        if is_key_pressed(key) {
+            search.refine(context, comparison);
+            cheat_search.selected = 0;
+        }
+    }
+    if is_key_pressed(KeyCode::Tab) && !search.is_empty() {
+        cheat_search.selected = (cheat_search.selected + 1) % search.len();
+    }
+    if is_key_pressed(KeyCode::Enter) {
+        if let Some(target) = search.candidates().nth(cheat_search.selected) {
+            let cheat = Cheat::freeze(target, context);
+            eprintln!("Froze {target} at {:#04X}", target.read(context));
+            cheats.cheats.retain(|frozen| frozen.name != cheat.name);
+            cheats.cheats.push(cheat);
+        }
+    }
+}
+
+// Hex dump of the memory around the cursor, with the sprite at I and the
+// stack next to it
+fn draw_memory_panel(
+    context: &Context,
+    view: &MemoryView,
+    graph: &ControlFlowGraph,
+    cheat_search: &CheatSearch,
+    paused: bool,
+) {
+    let font_size = PANEL_FONT_SIZE as f32;
+    let cell = measure_text("00 ", None, PANEL_FONT_SIZE, 1.0).width;
+    let address_width = measure_text("0x000  ", None, PANEL_FONT_SIZE, 1.0).width;
+    let line_height = font_size + 2.0;
+    let (left, top) = (10.0, 10.0);
+    let sprite_left = left + address_width + cell * ROW_BYTES as f32 + 20.0;
+    let stack_width = measure_text("0x000  sub_000+0x000", None, PANEL_FONT_SIZE, 1.0).width;
+
+    draw_rectangle(
+        0.0,
+        0.0,
+        sprite_left + stack_width.max(SPRITE_VIEW_SCALE * 8.0) + 20.0,
+        screen_height(),
+        Color::new(0.0, 0.0, 0.0, 0.85),
+    );
+    let title = format!(
+        "PC {:#05X}  I {:#05X}{}",
+        context.program_counter,
+        context.i_register,
+        if paused { "  PAUSED" } else { "" }
+    );
+    draw_text(&title, left, top + font_size, font_size, WHITE);
+
+    for (row, address) in view.rows().enumerate() {
+        let y = top + line_height * (row + 2) as f32;
+        draw_text(&format!("{address:#05X}"), left, y, font_size, WHITE);
+        for column in 0..ROW_BYTES {
+            let address = address + column as u16;
+            let x = left + address_width + cell * column as f32;
+            let background = match view.highlight(context, address) {
+                Highlight::ProgramCounter => Some(PC_COLOR),
+                Highlight::I => Some(I_COLOR),
+                Highlight::Written(age) => {
+                    let fade = 1.0 - age as f32 / RECENT_FRAMES as f32;
+                    Some(Color {
+                        a: fade,
+                        ..WRITTEN_COLOR
+                    })
+                }
+                Highlight::None => None,
+            };
+            let (width, height) = (cell - 4.0, line_height);
+            if let Some(color) = background {
+                draw_rectangle(x - 2.0, y - font_size + 2.0, width, height, color);
+            }
+            if address == view.cursor {
+                draw_rectangle_lines(x - 2.0, y - font_size + 2.0, width, height, 2.0, WHITE);
+            }
+            let byte = context.memory_map[address as usize];
+            draw_text(&format!("{byte:02X}"), x, y, font_size, WHITE);
+        }
+    }
+
+    // Memory at I, one row of 8 pixels per byte
+    draw_text(
+        "Sprite at I",
+        sprite_left,
+        top + font_size,
+        font_size,
+        WHITE,
+    );
+    let sprite_top = top + line_height * 2.0 - font_size;
+    for (row, address) in sprite_at_i(context).enumerate() {
+        let byte = context.memory_map[address];
+        for bit in 0..8 {
+            let lit = (byte >> (7 - bit)) & 1 == 1;
+            draw_rectangle(
+                sprite_left + bit as f32 * SPRITE_VIEW_SCALE,
+                sprite_top + row as f32 * SPRITE_VIEW_SCALE,
+                SPRITE_VIEW_SCALE,
+                SPRITE_VIEW_SCALE,
+                if lit { WHITE } else { BLACK },
+            );
+        }
+    }
+
+    // Return addresses below the sprite, innermost first
+    let stack_top = sprite_top + SPRITE_VIEW_SCALE * 16.0 + line_height;
+    let title = format!(
+        "Stack {}/{}",
+        context.stack_pointer.len(),
+        context.stack_depth
+    );
+    draw_text(&title, sprite_left, stack_top, font_size, WHITE);
+    for (row, frame) in stack_frames(context, graph).iter().enumerate() {
+        let y = stack_top + line_height * (row + 1) as f32;
+        draw_text(frame, sprite_left, y, font_size, WHITE);
+    }
+    if let Some(fault) = context.fault {
+        draw_text(
+            &fault.to_string(),
+            left,
+            top + line_height * (VISIBLE_ROWS + 3) as f32,
+            font_size,
+            WRITTEN_COLOR,
+        );
+    }
+
+    // Matches of the cheat search, from the selected one on
+    let search_top = top + line_height * (VISIBLE_ROWS + 5) as f32;
+    if let Some(digits) = &cheat_search.value {
+        let typed = digits
+            .iter()
+            .map(|digit| format!("{digit:X}"))
+            .collect::<String>();
+        let title = format!("Search for value: {typed:_<2}");
+        draw_text(&title, left, search_top, font_size, WHITE);
+    } else if let Some(search) = &cheat_search.search {
+        let title = format!("Search: {} matches", search.len());
+        draw_text(&title, left, search_top, font_size, WHITE);
+        let matches = search
+            .candidates()
+            .skip(cheat_search.selected)
+            .take(VISIBLE_MATCHES);
+        for (row, target) in matches.enumerate() {
+            let y = search_top + line_height * (row + 1) as f32;
+            let marker = if row == 0 { ">" } else { " " };
+            let line = format!("{marker} {target} = {:#04X}", target.read(context));
+            draw_text(&line, left, y, font_size, WHITE);
+        }
+    }
+}
+
+// Game controls with the keyboard keys bound to them, e.g. `up: W  down: S`
+fn controls_text(frontend: &Frontend) -> String {
+    frontend
+        .controls
         .iter()
-        .flat_map(|byte| {
-            let mut colors: Vec<u8> = vec![];
-            let mut i = 8;
-            while i > 0 {
-                let bit = (byte & (1 << (i - 1))) >> (i - 1);
-                for _ in 0..3 {
-                    colors.push(!(0xFF >> (8 * bit)) as u8);
+        .map(|(control, key)| format!("{control}: {}", frontend.settings.key_name(*key)))
+        .collect::<Vec<String>>()
+        .join("  ")
+}
+
+// Runs a fixed number of frames without opening a window, for captures
+fn run_headless(mut context: Context, mut frontend: Frontend) {
+    let frames = option_value("--frames").map_or(60, |value| parse_option("--frames", &value));
+    let mut recorder =
+        option_value("--record").map(|path| start_recording(Path::new(&path), &frontend));
+
+    let mut frame = render_display(&context, &mut frontend);
+    let mut clock = Duration::ZERO;
+    for _ in 0..frames {
+        frame = run_frame(&mut context, &mut frontend);
+        if let Some(recorder) = recorder.as_mut() {
+            record_frame(recorder, &frame, clock, &frontend);
+        }
+        clock += frontend.frame_duration;
+        if let Some(fault) = context.fault {
+            eprintln!("Stopped: {fault}");
+            break;
+        }
+    }
+
+    if let Some(recorder) = recorder {
+        stop_recording(recorder, clock);
+    }
+    if let Some(path) = option_value("--screenshot") {
+        save_screenshot(Path::new(&path), &frame, &frontend);
+    }
+    if let (Some(profiler), Some(path)) = (&frontend.profiler, option_value("--profile")) {
+        save_profile(Path::new(&path), profiler, &context);
+    }
+}
+
+// Emulates one frame and returns its RGBA pixels
+fn run_frame(context: &mut Context, frontend: &mut Frontend) -> Vec<u8> {
+    frontend.cheats.apply(context);
+    if let Some(vip) = frontend.vip.as_mut() {
+        run_vip_frame(vip, context);
+    } else {
+        run_ticks(context, frontend);
+        // The display interrupt
+        context.update_timers();
+    }
+    if let Some(script) = frontend.script.as_mut() {
+        if let Err(error) = script.end_frame(context) {
+            stop_script(frontend, error);
+        }
+    }
+    if let Some(flags) = frontend.flags.as_mut() {
+        if let Err(error) = flags.save(&context.flags) {
+            eprintln!("Couldn't save the flags: {error}");
+            frontend.flags = None;
+        }
+    }
+
+    render_display(context, frontend)
+}
+
+// RGBA pixels of the display, `frontend.width` by `frontend.height`
+fn render_display(context: &Context, frontend: &mut Frontend) -> Vec<u8> {
+    if let Some(mega) = context.mega_chip.as_ref().filter(|mega| mega.enabled) {
+        return convert_faded(&mega.frame, mega.alpha);
+    }
+    let buffer = context.get_flat_graphics_buffer();
+    let frame = if context.variant == Variant::Chip8X {
+        convert_color_zones(&buffer, context.background, &context.zone_colors)
+    } else {
+        render_frame(&buffer, frontend.filter.as_mut(), &frontend.palette)
+    };
+    if frontend.width == WIDTH {
+        return frame;
+    }
+    // The 64x32 display is as wide as the MEGA-CHIP one, in the middle
+    let scale = frontend.width / WIDTH;
+    let rows = (frontend.height - HEIGHT * scale) / 2;
+    let background = frontend.palette.background();
+    pad_frame(
+        &scale_frame(&frame, WIDTH, scale),
+        frontend.width,
+        rows,
+        background,
+    )
+}
+
+// Runs the instructions of one frame, a fixed number or as many as fit in
+// the frame's VIP cycles
+fn run_ticks(context: &mut Context, frontend: &mut Frontend) {
+    match frontend.timing.take() {
+        Some(mut timing) => {
+            timing.start_frame();
+            while timing.can_run() && context.fault.is_none() {
+                timing.charge(context);
+                run_tick(context, frontend);
+            }
+            frontend.timing = Some(timing);
+        }
+        None => {
+            for _ in 0..frontend.ticks_per_frame {
+                if context.fault.is_some() {
+                    break;
                 }
-                colors.push(0xFF);
-                i -= 1
+                run_tick(context, frontend);
+            }
+        }
+    }
+}
+
+// The VIP runs the program, the context only mirrors its display, memory and
+// sound for the rest of the frontend
+fn run_vip_frame(vip: &mut Vip, context: &mut Context) {
+    vip.keyboard_input = context.keyboard_input;
+    vip.run_frame();
+    context.graphics_buffer.clone_from(&vip.graphics_buffer);
+    context.memory_map[..MEMORY_SIZE].copy_from_slice(vip.memory());
+    context.sound_timer = vip.sound_on() as u8;
+}
+
+fn run_tick(context: &mut Context, frontend: &mut Frontend) {
+    // The next byte is sent once the program took the last one
+    if context.port_input.is_none() {
+        context.port_input = frontend.port_input.pop_front();
+    }
+    if let Some(profiler) = frontend.profiler.as_mut() {
+        profiler.record(context);
+    }
+    match frontend.script.as_mut() {
+        Some(script) => {
+            if let Err(error) = script.tick(context) {
+                stop_script(frontend, error);
+            }
+        }
+        None => {
+            context.tick();
+        }
+    }
+}
+
+fn print_graph(rom: &[u8], dialect: Dialect, format: &str) {
+    let graph = analyze(rom, dialect);
+    match format {
+        "dot" => print!("{}", graph.to_dot()),
+        "json" => println!("{}", graph.to_json()),
+        _ => {
+            eprintln!("Invalid value '{format}' for --graph, use dot or json");
+            process::exit(1);
+        }
+    }
+}
+
+fn print_lint(rom: &[u8], dialect: Dialect) {
+    let report = lint(rom, dialect);
+    for finding in &report.findings {
+        println!("{finding}");
+    }
+    println!("Suggested platform: {}", report.platform.id());
+}
+
+fn database_option() -> Database {
+    let Some(path) = option_value("--database") else {
+        return Database::bundled();
+    };
+    match Database::load(Path::new(&path)) {
+        Ok(database) => database,
+        Err(error) => {
+            eprintln!("Couldn't load database {path}: {error}");
+            process::exit(1);
+        }
+    }
+}
+
+fn script_option(context: &mut Context, frontend: &Frontend) -> Option<Script> {
+    let path = option_value("--script")?;
+    // Script screenshots are of the 64x32 display
+    let scale = frontend.settings.scale.unwrap_or(DEFAULT_SCALE);
+    let script = Script::load(Path::new(&path), context, &frontend.palette, scale);
+    match script {
+        Ok(script) => Some(script),
+        Err(error) => {
+            eprintln!("Couldn't run script {path}: {error}");
+            process::exit(1);
+        }
+    }
+}
+
+// Bytes of the file given by `--input`
+fn input_option() -> VecDeque<u8> {
+    let Some(path) = option_value("--input") else {
+        return VecDeque::new();
+    };
+    fs::read(&path)
+        .unwrap_or_else(|error| {
+            eprintln!("Couldn't read input {path}: {error}");
+            process::exit(1);
+        })
+        .into()
+}
+
+// The emulator keeps running without the script
+fn stop_script(frontend: &mut Frontend, error: ScriptError) {
+    eprintln!("Script error: {error}");
+    frontend.script = None;
+}
+
+// The VIP with the interpreter image given by `--vip`, and the monitor ROM
+// given by `--vip-monitor`
+fn vip_option(program: &[u8]) -> Option<Vip> {
+    let interpreter = option_value("--vip")?;
+    let read = |path: String| {
+        fs::read(&path).unwrap_or_else(|error| {
+            eprintln!("Couldn't read {path}: {error}");
+            process::exit(1);
+        })
+    };
+    let interpreter = read(interpreter);
+    let monitor = option_value("--vip-monitor").map(read);
+    let vip = Vip::new(&interpreter, monitor.as_deref(), program).unwrap_or_else(|error| {
+        eprintln!("Couldn't start the VIP: {error}");
+        process::exit(1);
+    });
+    Some(vip)
+}
+
+fn cheats_option() -> CheatList {
+    let Some(path) = option_value("--cheats") else {
+        return CheatList::default();
+    };
+    CheatList::load(Path::new(&path)).unwrap_or_else(|error| {
+        eprintln!("Couldn't load cheats {path}: {error}");
+        process::exit(1);
+    })
+}
+
+// RPL user flags saved for the ROM, in the directory given by `--flags` or
+// the user data directory
+fn open_flags(rom: &[u8]) -> Option<FlagStore> {
+    let directory = option_value("--flags")
+        .map(PathBuf::from)
+        .or_else(FlagStore::default_directory)?;
+    FlagStore::open(&directory, rom)
+        .map_err(|error| eprintln!("Couldn't read the flags: {error}"))
+        .ok()
+}
+
+fn save_profile(path: &Path, profiler: &Profiler, context: &Context) {
+    match fs::write(path, profiler.annotated_disassembly(context)) {
+        Ok(()) => println!("Saved profile to {}", path.display()),
+        Err(error) => eprintln!("Couldn't save profile to {}: {error}", path.display()),
+    }
+}
+
+fn save_screenshot(path: &Path, frame: &[u8], frontend: &Frontend) {
+    let scale = frontend.scale;
+    let scaled = scale_frame(frame, frontend.width, scale);
+    let (width, height) = (frontend.width * scale, frontend.height * scale);
+    match save_png(path, &scaled, width, height) {
+        Ok(()) => println!("Saved screenshot to {}", path.display()),
+        Err(error) => eprintln!("Couldn't save screenshot to {}: {error}", path.display()),
+    }
+}
+
+fn start_recording(path: &Path, frontend: &Frontend) -> Recorder {
+    let (width, height) = (
+        frontend.width * frontend.scale,
+        frontend.height * frontend.scale,
+    );
+    let recorder = Recorder::create(path, width, height).unwrap_or_else(|error| {
+        eprintln!("Couldn't start recording to {}: {error}", path.display());
+        process::exit(1);
+    });
+    println!("Recording to {}", path.display());
+    recorder
+}
+
+// Records a frame shown at `time`
+fn record_frame(recorder: &mut Recorder, frame: &[u8], time: Duration, frontend: &Frontend) {
+    let scaled = scale_frame(frame, frontend.width, frontend.scale);
+    if let Err(error) = recorder.push_frame(&scaled, time) {
+        eprintln!("Couldn't record frame: {error}");
+    }
+}
+
+// Ends the recording with the last frame shown until `time`
+fn stop_recording(recorder: Recorder, time: Duration) {
+    let frames = recorder.frames();
+    match recorder.finish(time) {
+        Ok(()) => println!("Recorded {frames} frames"),
+        Err(error) => eprintln!("Couldn't finish recording: {error}"),
+    }
+}
+
+// Octo cartridges hold source code, `--program` gives the binary assembled
+// from it to run with the cartridge's options
+fn open_rom(path: &str) -> RomFile {
+    match load_rom(Path::new(path)) {
+        Ok(rom) => rom,
+        Err(LoadError::UnassembledSource(cartridge)) if option_value("--program").is_some() => {
+            let program_path = option_value("--program").unwrap_or_default();
+            let program = fs::read(&program_path).unwrap_or_else(|error| {
+                eprintln!("Couldn't read program {program_path}: {error}");
+                process::exit(1);
+            });
+            cartridge.with_program(program)
+        }
+        Err(error) => {
+            eprintln!("Couldn't load ROM {path}: {error}");
+            process::exit(1);
+        }
+    }
+}
+
+// Uses `--config` or the config file in the user config directory
+fn load_config() -> Config {
+    let path = match option_value("--config") {
+        Some(path) => Some(path.into()),
+        None => Config::default_path(),
+    };
+    let Some(path) = path else {
+        return Config::default();
+    };
+
+    Config::load(&path).unwrap_or_else(|error| {
+        eprintln!("Couldn't load config {}: {error}", path.display());
+        process::exit(1);
+    })
+}
+
+// Command line flags that override the config file
+fn settings_from_args() -> Settings {
+    Settings {
+        palette: option_value("--palette"),
+        scale: option_value("--scale").map(|value| parse_option("--scale", &value)),
+        ticks_per_frame: option_value("--speed").map(|value| parse_option("--speed", &value)),
+        variant: option_value("--variant").map(|value| {
+            Variant::parse(&value).unwrap_or_else(|| {
+                eprintln!("Invalid variant '{value}', use chip8, chip8x, chip8e or megachip");
+                process::exit(1);
+            })
+        }),
+        vip_timing: env::args().any(|arg| arg == "--vip-timing").then_some(true),
+        ..Settings::default()
+    }
+}
+
+fn palette_setting(settings: &Settings) -> Palette {
+    match &settings.palette {
+        Some(value) => Palette::parse(value).unwrap_or_else(|_| {
+            eprintln!(
+                "Invalid palette '{value}'. Use one of {} or a list of hex colors like #000000,#FFFFFF",
+                PALETTE_NAMES.join(", ")
+            );
+            process::exit(1);
+        }),
+        None => Palette::default(),
+    }
+}
+
+fn filter_option() -> Option<PersistenceFilter> {
+    option_value("--filter").map(|value| {
+        let mode = FilterMode::parse(&value).unwrap_or_else(|_| {
+            eprintln!("Invalid filter '{value}'. Use merge, decay or decay:<0.0 - 1.0>");
+            process::exit(1);
+        });
+        PersistenceFilter::new(mode)
+    })
+}
+
+// Value following `name` in the command line arguments
+fn option_value(name: &str) -> Option<String> {
+    env::args().skip_while(|arg| arg != name).nth(1)
+}
+
+fn parse_option<T: FromStr>(name: &str, value: &str) -> T {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("Invalid value '{value}' for {name}");
+        process::exit(1);
+    })
+}
+
+fn rom_path() -> Option<String> {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    args.iter()
+        .enumerate()
+        .find(|(index, arg)| {
+            let is_value = *index > 0 && VALUE_OPTIONS.contains(&args[index - 1].as_str());
+            !arg.starts_with("--") && !is_value
+        })
+        .map(|(_, arg)| arg.clone())
+}
+
+// Keyboard keys for each key of a keypad, `None` for names that aren't keys
+fn bind_keys<'a>(keypad: &str, name: impl Fn(u8) -> &'a str) -> Vec<Option<KeyCode>> {
+    (0..16)
+        .map(|key| {
+            let name = name(key);
+            let code = key_code(name);
+            if code.is_none() {
+                eprintln!("Unknown key '{name}' for {keypad} key {key:X}");
             }
-            colors
+            code
         })
-        .collect::<Vec<u8>>();
-    result
+        .collect()
+}
+
+// The first keypad key held down
+fn key_down(keys: &[Option<KeyCode>]) -> Option<u8> {
+    (0..16).find(|key| keys[*key as usize].is_some_and(is_key_down))
+}
+
+// Key names used in the `keys` section of the config
+fn key_code(name: &str) -> Option<KeyCode> {
+    let code = match name.to_ascii_uppercase().as_str() {
+        "0" => KeyCode::Key0,
+        "1" => KeyCode::Key1,
+        "2" => KeyCode::Key2,
+        "3" => KeyCode::Key3,
+        "4" => KeyCode::Key4,
+        "5" => KeyCode::Key5,
+        "6" => KeyCode::Key6,
+        "7" => KeyCode::Key7,
+        "8" => KeyCode::Key8,
+        "9" => KeyCode::Key9,
+        "A" => KeyCode::A,
+        "B" => KeyCode::B,
+        "C" => KeyCode::C,
+        "D" => KeyCode::D,
+        "E" => KeyCode::E,
+        "F" => KeyCode::F,
+        "G" => KeyCode::G,
+        "H" => KeyCode::H,
+        "I" => KeyCode::I,
+        "J" => KeyCode::J,
+        "K" => KeyCode::K,
+        "L" => KeyCode::L,
+        "M" => KeyCode::M,
+        "N" => KeyCode::N,
+        "O" => KeyCode::O,
+        "P" => KeyCode::P,
+        "Q" => KeyCode::Q,
+        "R" => KeyCode::R,
+        "S" => KeyCode::S,
+        "T" => KeyCode::T,
+        "U" => KeyCode::U,
+        "V" => KeyCode::V,
+        "W" => KeyCode::W,
+        "X" => KeyCode::X,
+        "Y" => KeyCode::Y,
+        "Z" => KeyCode::Z,
+        "SPACE" => KeyCode::Space,
+        "ENTER" => KeyCode::Enter,
+        "TAB" => KeyCode::Tab,
+        "BACKSPACE" => KeyCode::Backspace,
+        "UP" => KeyCode::Up,
+        "DOWN" => KeyCode::Down,
+        "LEFT" => KeyCode::Left,
+        "RIGHT" => KeyCode::Right,
+        "KP0" => KeyCode::Kp0,
+        "KP1" => KeyCode::Kp1,
+        "KP2" => KeyCode::Kp2,
+        "KP3" => KeyCode::Kp3,
+        "KP4" => KeyCode::Kp4,
+        "KP5" => KeyCode::Kp5,
+        "KP6" => KeyCode::Kp6,
+        "KP7" => KeyCode::Kp7,
+        "KP8" => KeyCode::Kp8,
+        "KP9" => KeyCode::Kp9,
+        "KP." => KeyCode::KpDecimal,
+        "KP/" => KeyCode::KpDivide,
+        "KP*" => KeyCode::KpMultiply,
+        "KP-" => KeyCode::KpSubtract,
+        "KP+" => KeyCode::KpAdd,
+        "KPENTER" => KeyCode::KpEnter,
+        _ => return None,
+    };
+    Some(code)
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
 }
@@ -0,0 +1,330 @@
+use std::rc::Rc;
+
+// Size of the MEGA-CHIP display
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 192;
+// I has 24 bits on MEGA-CHIP, room for 16MB of sprites and sounds
+pub const MEMORY_SIZE: usize = 0x100_0000;
+// Digitized sounds start with a 16-bit sample rate and a 24-bit length
+const SOUND_HEADER: usize = 5;
+const BLACK: [u8; 4] = [0, 0, 0, 0xFF];
+
+// How sprite colors are combined with the colors under them, set by `080n`
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+pub enum BlendMode {
+    // The sprite color over the screen, by the alpha of its palette entry
+    #[default]
+    Normal,
+    // A quarter of the sprite color and three quarters of the screen
+    Quarter,
+    Half,
+    // Channels added together, up to white
+    Add,
+    // Channels multiplied together, darkening the screen
+    Multiply,
+}
+
+impl BlendMode {
+    // Modes 0 - 4 of `080n`, other values draw normally
+    pub fn from_mode(mode: u8) -> BlendMode {
+        match mode {
+            1 => BlendMode::Quarter,
+            2 => BlendMode::Half,
+            3 => BlendMode::Add,
+            4 => BlendMode::Multiply,
+            _ => BlendMode::Normal,
+        }
+    }
+
+    // Combines an RGBA sprite color with the opaque color under it
+    pub fn blend(&self, source: [u8; 4], destination: [u8; 4]) -> [u8; 4] {
+        let mix = |weight: u16| {
+            let mut color = BLACK;
+            for channel in 0..3 {
+                let (source, destination) = (source[channel] as u16, destination[channel] as u16);
+                color[channel] = ((source * weight + destination * (0xFF - weight)) / 0xFF) as u8;
+            }
+            color
+        };
+        match self {
+            BlendMode::Normal => mix(source[3] as u16),
+            BlendMode::Quarter => mix(0x40),
+            BlendMode::Half => mix(0x80),
+            BlendMode::Add | BlendMode::Multiply => {
+                let mut color = BLACK;
+                for channel in 0..3 {
+                    let (source, destination) =
+                        (source[channel] as u16, destination[channel] as u16);
+                    color[channel] = match self {
+                        BlendMode::Add => (source + destination).min(0xFF),
+                        _ => source * destination / 0xFF,
+                    } as u8;
+                }
+                color
+            }
+        }
+    }
+}
+
+// 8-bit unsigned mono samples, as played by `060n`. Cloning shares the
+// samples.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct DigitizedSound {
+    pub sample_rate: u16,
+    pub samples: Rc<[u8]>,
+    pub looped: bool,
+}
+
+impl DigitizedSound {
+    // Reads a sound from the start of `memory`, samples past its end are left
+    // out. `None` when even the header doesn't fit.
+    pub fn read(memory: &[u8], looped: bool) -> Option<DigitizedSound> {
+        let header = memory.get(..SOUND_HEADER)?;
+        let sample_rate = u16::from_be_bytes([header[0], header[1]]);
+        let length = u32::from_be_bytes([0, header[2], header[3], header[4]]) as usize;
+        let samples = &memory[SOUND_HEADER..];
+        Some(DigitizedSound {
+            sample_rate,
+            samples: samples[..length.min(samples.len())].into(),
+            looped,
+        })
+    }
+}
+
+/**
+ *  MEGA-CHIP display and sound. Sprites are drawn into `pixels` as palette
+ *  indices, for collisions, and into `colors` with their palette colors
+ *  blended over what's already there. `00E0` shows them by copying `colors`
+ *  to `frame` before clearing both for the next frame.
+ */
+pub struct MegaChip {
+    // Set by `0011`, until then the 64x32 display is used
+    pub enabled: bool,
+    // Palette indices drawn since the last `00E0`, row by row. Sprite pixels
+    // of index 0 are transparent.
+    pub pixels: Vec<u8>,
+    // Opaque RGBA colors drawn since the last `00E0`, row by row
+    pub colors: Vec<[u8; 4]>,
+    // Opaque RGBA colors on screen
+    pub frame: Vec<[u8; 4]>,
+    // RGBA colors, grays until `02nn` loads a palette
+    pub palette: [[u8; 4]; 256],
+    // Sprite size in pixels, 1 - 256
+    pub sprite_width: usize,
+    pub sprite_height: usize,
+    // Opacity of the whole screen over black
+    pub alpha: u8,
+    pub blend_mode: BlendMode,
+    // Drawing over a pixel of this index sets VF, nothing does until `09nn`
+    pub collision_color: Option<u8>,
+    // Sound started by `060n`, until `070n` stops it. Sounds that aren't
+    // looped play once but stay here.
+    pub sound: Option<DigitizedSound>,
+}
+
+impl MegaChip {
+    pub fn new() -> MegaChip {
+        MegaChip {
+            enabled: false,
+            pixels: vec![0; WIDTH * HEIGHT],
+            colors: vec![BLACK; WIDTH * HEIGHT],
+            frame: vec![BLACK; WIDTH * HEIGHT],
+            palette: std::array::from_fn(|index| {
+                let level = index as u8;
+                [level, level, level, 0xFF]
+            }),
+            sprite_width: 8,
+            sprite_height: 8,
+            alpha: 0xFF,
+            blend_mode: BlendMode::Normal,
+            collision_color: None,
+            sound: None,
+        }
+    }
+
+    // Loads ARGB colors into the palette from index 1, index 0 stays black
+    pub fn load_palette(&mut self, colors: &[u8]) {
+        for (index, color) in colors.chunks_exact(4).take(255).enumerate() {
+            self.palette[index + 1] = [color[1], color[2], color[3], color[0]];
+        }
+    }
+
+    // Sprite sizes of `03nn` and `04nn`, where 0 means 256
+    pub fn set_sprite_size(&mut self, width: Option<u8>, height: Option<u8>) {
+        let size = |value: u8| if value == 0 { 256 } else { value as usize };
+        if let Some(width) = width {
+            self.sprite_width = size(width);
+        }
+        if let Some(height) = height {
+            self.sprite_height = size(height);
+        }
+    }
+
+    /**
+     *  Draws a sprite of palette indices, row by row, with its top left
+     *  corner at `x`, `y`, blending its colors with the blend mode. Pixels
+     *  off the display are cut. Returns whether a pixel of the collision
+     *  color was drawn over.
+     */
+    pub fn draw(&mut self, x: u8, y: u8, sprite: &[u8]) -> bool {
+        let mut collision = false;
+        for (index, color) in sprite.iter().enumerate() {
+            let column = x as usize + index % self.sprite_width;
+            let row = y as usize + index / self.sprite_width;
+            if *color == 0 || column >= WIDTH || row >= HEIGHT {
+                continue;
+            }
+            let index = row * WIDTH + column;
+            collision |= self.collision_color == Some(self.pixels[index]);
+            self.pixels[index] = *color;
+            let source = self.palette[*color as usize];
+            self.colors[index] = self.blend_mode.blend(source, self.colors[index]);
+        }
+        collision
+    }
+
+    // Moves the drawn pixels, positive amounts go right and down. Pixels
+    // scrolled in are transparent and black.
+    pub fn scroll(&mut self, columns: isize, rows: isize) {
+        self.pixels = scrolled(&self.pixels, columns, rows, 0);
+        self.colors = scrolled(&self.colors, columns, rows, BLACK);
+    }
+
+    // Shows what was drawn and starts the next frame empty
+    pub fn present(&mut self) {
+        self.frame.copy_from_slice(&self.colors);
+        self.pixels.fill(0);
+        self.colors.fill(BLACK);
+    }
+}
+
+fn scrolled<T: Copy>(pixels: &[T], columns: isize, rows: isize, empty: T) -> Vec<T> {
+    let mut result = vec![empty; WIDTH * HEIGHT];
+    for row in 0..HEIGHT {
+        for column in 0..WIDTH {
+            let from_row = row as isize - rows;
+            let from_column = column as isize - columns;
+            if (0..HEIGHT as isize).contains(&from_row)
+                && (0..WIDTH as isize).contains(&from_column)
+            {
+                result[row * WIDTH + column] =
+                    pixels[from_row as usize * WIDTH + from_column as usize];
+            }
+        }
+    }
+    result
+}
+
+impl Default for MegaChip {
+    fn default() -> MegaChip {
+        MegaChip::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BlendMode, DigitizedSound, MegaChip, BLACK, HEIGHT, WIDTH};
+
+    #[test]
+    fn draw_and_present() {
+        let mut mega = MegaChip::new();
+        mega.set_sprite_size(Some(2), Some(2));
+        mega.collision_color = Some(7);
+        assert!(!mega.draw(1, 1, &[7, 0, 0, 7]));
+        assert!(mega.draw(2, 2, &[5, 0, 0, 0]));
+
+        assert_eq!(mega.pixels[WIDTH + 1..][..2], [7, 0]);
+        assert_eq!(mega.pixels[2 * WIDTH + 1..][..2], [0, 5]);
+        assert!(mega.frame.iter().all(|color| *color == BLACK));
+
+        mega.present();
+        assert_eq!(mega.frame[WIDTH + 1], [7, 7, 7, 0xFF]);
+        assert_eq!(mega.frame[WIDTH + 2], BLACK);
+        assert!(mega.pixels.iter().all(|index| *index == 0));
+        assert!(mega.colors.iter().all(|color| *color == BLACK));
+    }
+
+    // Draws palette color 2 over color 1 with `mode`, returns the result
+    fn blend(mode: u8) -> [u8; 4] {
+        let mut mega = MegaChip::new();
+        mega.palette[1] = [0x20, 0x40, 0xFF, 0xFF];
+        mega.palette[2] = [0x80, 0xFF, 0x00, 0xC0];
+        mega.set_sprite_size(Some(1), Some(1));
+        mega.draw(0, 0, &[1]);
+        mega.blend_mode = BlendMode::from_mode(mode);
+        mega.draw(0, 0, &[2]);
+        mega.colors[0]
+    }
+
+    #[test]
+    fn blend_normal_by_palette_alpha() {
+        assert_eq!(blend(0), [0x68, 0xCF, 0x3F, 0xFF]);
+        assert_eq!(blend(9), blend(0));
+    }
+
+    #[test]
+    fn blend_a_quarter() {
+        assert_eq!(blend(1), [0x38, 0x6F, 0xBF, 0xFF]);
+    }
+
+    #[test]
+    fn blend_half() {
+        assert_eq!(blend(2), [0x50, 0x9F, 0x7F, 0xFF]);
+    }
+
+    #[test]
+    fn blend_additive() {
+        assert_eq!(blend(3), [0xA0, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn blend_multiply() {
+        assert_eq!(blend(4), [0x10, 0x40, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn cut_sprites_at_the_edges() {
+        let mut mega = MegaChip::new();
+        mega.set_sprite_size(Some(2), Some(0));
+        assert_eq!(mega.sprite_height, 256);
+        mega.draw(255, 191, &[1, 2, 3, 4]);
+
+        assert_eq!(mega.pixels[WIDTH * HEIGHT - 1], 1);
+        assert_eq!(mega.pixels.iter().filter(|index| **index != 0).count(), 1);
+    }
+
+    #[test]
+    fn scroll_pixels() {
+        let mut mega = MegaChip::new();
+        mega.pixels[0] = 9;
+        mega.colors[0] = [9, 9, 9, 0xFF];
+        mega.scroll(4, 2);
+        assert_eq!(mega.pixels[2 * WIDTH + 4], 9);
+        assert_eq!(mega.colors[2 * WIDTH + 4], [9, 9, 9, 0xFF]);
+        mega.scroll(-8, 0);
+        assert!(mega.pixels.iter().all(|index| *index == 0));
+        assert!(mega.colors.iter().all(|color| *color == BLACK));
+    }
+
+    #[test]
+    fn load_argb_palette() {
+        let mut mega = MegaChip::new();
+        mega.load_palette(&[0xFF, 0x10, 0x20, 0x30, 0x80, 0x40, 0x50, 0x60]);
+        assert_eq!(mega.palette[0], [0, 0, 0, 0xFF]);
+        assert_eq!(mega.palette[1], [0x10, 0x20, 0x30, 0xFF]);
+        assert_eq!(mega.palette[2], [0x40, 0x50, 0x60, 0x80]);
+        assert_eq!(mega.palette[3], [3, 3, 3, 0xFF]);
+    }
+
+    #[test]
+    fn read_digitized_sound() {
+        let memory = [0x1F, 0x40, 0x00, 0x00, 0x03, 0x80, 0x90, 0xA0, 0xB0];
+        let sound = DigitizedSound::read(&memory, true).unwrap();
+        assert_eq!(sound.sample_rate, 8000);
+        assert_eq!(*sound.samples, [0x80, 0x90, 0xA0]);
+
+        let sound = DigitizedSound::read(&memory[..7], false).unwrap();
+        assert_eq!(*sound.samples, [0x80, 0x90]);
+        assert_eq!(DigitizedSound::read(&memory[..4], false), None);
+    }
+}
@@ -0,0 +1,274 @@
+use std::ops::Range;
+
+use crate::{
+    analysis::ControlFlowGraph,
+    context::{Context, MEMORY_SIZE},
+    instructions::Instruction,
+};
+
+pub const ROW_BYTES: usize = 16;
+pub const VISIBLE_ROWS: usize = 16;
+// Frames a written byte stays highlighted
+pub const RECENT_FRAMES: u8 = 30;
+// Rows shown by the sprite view when the next instruction isn't a `Dxyn`
+pub const DEFAULT_SPRITE_HEIGHT: usize = 15;
+
+// Address of the first row when the last row of memory is at the bottom
+const LAST_TOP: u16 = (MEMORY_SIZE - ROW_BYTES * VISIBLE_ROWS) as u16;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Highlight {
+    ProgramCounter,
+    I,
+    // Written this many frames ago
+    Written(u8),
+    None,
+}
+
+/**
+ *  State of the memory hex viewer: the selected byte, the rows on screen and
+ *  the bytes written in the last `RECENT_FRAMES` frames. Bytes are edited by
+ *  typing two hex digits at the cursor. Only the first 4 KiB are shown, also
+ *  on MEGA-CHIP with its 16 MB of memory.
+ */
+pub struct MemoryView {
+    pub cursor: u16,
+    // Address of the first row on screen
+    pub top: u16,
+    ages: Vec<u8>,
+    previous: Vec<u8>,
+    // High nibble typed at the cursor, waiting for the low one
+    pending: Option<u8>,
+}
+
+impl MemoryView {
+    pub fn new(context: &Context) -> MemoryView {
+        let mut view = MemoryView {
+            cursor: 0,
+            top: 0,
+            ages: vec![0; MEMORY_SIZE],
+            previous: context.memory_map[..MEMORY_SIZE].to_vec(),
+            pending: None,
+        };
+        // Start with the program at the top
+        view.move_to(context.program_counter);
+        view.top = (view.cursor - view.cursor % ROW_BYTES as u16).min(LAST_TOP);
+        view
+    }
+
+    // Must be called once per frame to track the bytes the program writes
+    pub fn update(&mut self, context: &Context) {
+        let memory = &context.memory_map[..MEMORY_SIZE];
+        for (address, byte) in memory.iter().enumerate() {
+            if *byte != self.previous[address] {
+                self.ages[address] = RECENT_FRAMES;
+            } else {
+                self.ages[address] = self.ages[address].saturating_sub(1);
+            }
+        }
+        self.previous.copy_from_slice(memory);
+    }
+
+    pub fn highlight(&self, context: &Context, address: u16) -> Highlight {
+        let pc = context.program_counter;
+        let age = self.ages[address as usize];
+        if address == pc || address == pc.wrapping_add(1) {
+            Highlight::ProgramCounter
+        } else if address as u32 == context.i_register {
+            Highlight::I
+        } else if age > 0 {
+            Highlight::Written(RECENT_FRAMES - age)
+        } else {
+            Highlight::None
+        }
+    }
+
+    // Addresses of the rows on screen
+    pub fn rows(&self) -> impl Iterator<Item = u16> {
+        (self.top..MEMORY_SIZE as u16)
+            .step_by(ROW_BYTES)
+            .take(VISIBLE_ROWS)
+    }
+
+    // Moves the cursor by `offset` bytes and scrolls to keep it on screen
+    pub fn move_cursor(&mut self, offset: i32) {
+        let address = (self.cursor as i32 + offset).clamp(0, MEMORY_SIZE as i32 - 1);
+        self.move_to(address as u16);
+    }
+
+    pub fn move_to(&mut self, address: u16) {
+        self.cursor = address.min(MEMORY_SIZE as u16 - 1);
+        self.pending = None;
+
+        let row = self.cursor - self.cursor % ROW_BYTES as u16;
+        let screen = (ROW_BYTES * VISIBLE_ROWS) as u16;
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + screen {
+            self.top = (row + ROW_BYTES as u16 - screen).min(LAST_TOP);
+        }
+    }
+
+    // Types a hex digit at the cursor. The second digit completes the byte
+    // and moves to the next one.
+    pub fn type_digit(&mut self, context: &mut Context, digit: u8) {
+        let address = self.cursor as usize;
+        match self.pending.take() {
+            None => {
+                context.memory_map[address] = (digit << 4) | (context.memory_map[address] & 0xF);
+                self.pending = Some(digit);
+            }
+            Some(high) => {
+                context.memory_map[address] = (high << 4) | digit;
+                self.move_cursor(1);
+            }
+        }
+        // Edits aren't writes of the program
+        self.previous[address] = context.memory_map[address];
+    }
+}
+
+// Memory at I as drawn by the `Dxyn` at the program counter, or
+// `DEFAULT_SPRITE_HEIGHT` rows when the next instruction doesn't draw
+pub fn sprite_at_i(context: &Context) -> Range<usize> {
+    let height = match context.fetch() {
+        Instruction::Display(_, _, n) => n as usize,
+        _ => DEFAULT_SPRITE_HEIGHT,
+    };
+    let start = context.i_register as usize;
+    start.min(MEMORY_SIZE)..(start + height).min(MEMORY_SIZE)
+}
+
+// Return addresses on the stack, innermost first, with the subroutine they
+// return into, e.g. `0x206  start+0x6`
+pub fn stack_frames(context: &Context, graph: &ControlFlowGraph) -> Vec<String> {
+    context
+        .stack_pointer
+        .iter()
+        .rev()
+        .map(|&address| format!("{address:#05X}  {}", graph.symbolize(address)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{sprite_at_i, stack_frames, Highlight, MemoryView, RECENT_FRAMES, VISIBLE_ROWS};
+    use crate::{
+        analysis::analyze,
+        context::Context,
+        parser::{Dialect, Variant},
+        test_data::DATA,
+    };
+
+    #[test]
+    fn highlight_pc_i_and_writes() {
+        // LD I, 0x300 ; LD [I], V0 ; JP 0x204
+        let mut context = Context::new(&[0xA3, 0x00, 0xF0, 0x55, 0x12, 0x04], 1);
+        context.registers[0] = 0xAB;
+        let mut view = MemoryView::new(&context);
+
+        context.tick();
+        assert_eq!(view.highlight(&context, 0x202), Highlight::ProgramCounter);
+        assert_eq!(view.highlight(&context, 0x203), Highlight::ProgramCounter);
+        assert_eq!(view.highlight(&context, 0x300), Highlight::I);
+
+        context.tick();
+        context.i_register = 0;
+        view.update(&context);
+        assert_eq!(view.highlight(&context, 0x300), Highlight::Written(0));
+        view.update(&context);
+        assert_eq!(view.highlight(&context, 0x300), Highlight::Written(1));
+        for _ in 0..RECENT_FRAMES {
+            view.update(&context);
+        }
+        assert_eq!(view.highlight(&context, 0x300), Highlight::None);
+    }
+
+    #[test]
+    fn track_writes_with_mega_chip_memory() {
+        // LD I, 0x300 ; LD [I], V0
+        let mut context = Context::new(&[0xA3, 0x00, 0xF0, 0x55], 1);
+        context.set_variant(Variant::MegaChip);
+        context.registers[0] = 0xAB;
+        let mut view = MemoryView::new(&context);
+
+        context.tick();
+        context.tick();
+        context.i_register = 0;
+        context.memory_map[0x10000] = 0xFF;
+        view.update(&context);
+        assert_eq!(view.highlight(&context, 0x300), Highlight::Written(0));
+    }
+
+    #[test]
+    fn scroll_with_cursor() {
+        let context = Context::new(&DATA, 1);
+        let mut view = MemoryView::new(&context);
+        assert_eq!(view.cursor, 0x200);
+        assert_eq!(view.top, 0x200);
+
+        view.move_cursor(-1);
+        assert_eq!(view.top, 0x1F0);
+        view.move_cursor(16 * VISIBLE_ROWS as i32);
+        assert_eq!(view.cursor, 0x2FF);
+        assert_eq!(view.top, 0x200);
+        assert_eq!(view.rows().count(), VISIBLE_ROWS);
+
+        view.move_cursor(0x2000);
+        assert_eq!(view.cursor, 0xFFF);
+        assert_eq!(view.rows().last(), Some(0xFF0));
+        view.move_cursor(-0x2000);
+        assert_eq!((view.cursor, view.top), (0, 0));
+    }
+
+    #[test]
+    fn edit_bytes_with_hex_digits() {
+        let mut context = Context::new(&DATA, 1);
+        let mut view = MemoryView::new(&context);
+        view.move_to(0x300);
+
+        view.type_digit(&mut context, 0x1);
+        assert_eq!(context.memory_map[0x300], 0x10);
+        view.type_digit(&mut context, 0x2);
+        view.type_digit(&mut context, 0x3);
+        view.type_digit(&mut context, 0x4);
+        assert_eq!(context.memory_map[0x300..0x302], [0x12, 0x34]);
+        assert_eq!(view.cursor, 0x302);
+
+        view.update(&context);
+        assert_eq!(view.highlight(&context, 0x301), Highlight::None);
+    }
+
+    #[test]
+    fn show_sprite_drawn_next() {
+        let mut context = Context::new(&DATA, 1);
+        for _ in 0..4 {
+            context.tick();
+        }
+        // DRW V0, V1, 15 with I at 0x22A
+        assert_eq!(sprite_at_i(&context), 0x22A..0x239);
+        context.tick();
+        assert_eq!(sprite_at_i(&context).len(), 15);
+    }
+
+    #[test]
+    fn symbolize_stack() {
+        let program = [
+            0x22, 0x04, // 200: CALL 0x204
+            0x12, 0x02, // 202: JP 0x202
+            0x60, 0x01, // 204: LD V0, 1
+            0x22, 0x0A, // 206: CALL 0x20A
+            0x00, 0xEE, // 208: RET
+            0x00, 0xEE, // 20A: RET
+        ];
+        let graph = analyze(&program, Dialect::CHIP8);
+        let mut context = Context::new(&program, 1);
+        for _ in 0..3 {
+            context.tick();
+        }
+        assert_eq!(
+            stack_frames(&context, &graph),
+            ["0x208  sub_204+0x4", "0x202  start+0x2"]
+        );
+    }
+}
@@ -0,0 +1,136 @@
+pub type Rgba = [u8; 4];
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct ParsePaletteError;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Palette {
+    // Indexed by the value of the pixel. Plain CHIP-8 only uses the first two
+    // (off and on), XO-CHIP combines two bitplanes into an index for all four.
+    pub colors: [Rgba; 4],
+    // Drawn around the display
+    pub border: Rgba,
+}
+
+pub const PALETTE_NAMES: [&str; 6] = ["classic", "green", "amber", "lcd", "octo", "high-contrast"];
+
+impl Palette {
+    pub fn named(name: &str) -> Option<Palette> {
+        let (colors, border) = match name {
+            "classic" => ([0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555], 0x828282),
+            "green" => ([0x0A1A0A, 0x33FF66, 0x1F9940, 0x66FF99], 0x050D05),
+            "amber" => ([0x1A0F00, 0xFFB000, 0x995F00, 0xFFD066], 0x0D0800),
+            "lcd" => ([0x9BBC0F, 0x0F380F, 0x8BAC0F, 0x306230], 0x8BAC0F),
+            "octo" => ([0x996600, 0xFFCC00, 0xFF6600, 0x662200], 0x996600),
+            "high-contrast" => ([0x000000, 0xFFFFFF, 0xFFFF00, 0x00FFFF], 0x000000),
+            _ => return None,
+        };
+
+        Some(Palette {
+            colors: colors.map(rgb_to_rgba),
+            border: rgb_to_rgba(border),
+        })
+    }
+
+    // Accepts either one of the `PALETTE_NAMES` or a custom palette given as a
+    // comma separated list of 2 or 4 hex colors, e.g. `#000000,#33FF66`.
+    // Custom palettes use the background color as the border.
+    pub fn parse(source: &str) -> Result<Palette, ParsePaletteError> {
+        if let Some(palette) = Palette::named(source) {
+            return Ok(palette);
+        }
+
+        let custom = source
+            .split(',')
+            .map(|color| parse_hex_color(color.trim()))
+            .collect::<Result<Vec<Rgba>, ParsePaletteError>>()?;
+
+        let colors = match custom[..] {
+            // Without bitplane colors, the second plane is drawn like the first one
+            [background, foreground] => [background, foreground, foreground, foreground],
+            [background, foreground, second, both] => [background, foreground, second, both],
+            _ => return Err(ParsePaletteError),
+        };
+
+        Ok(Palette {
+            colors,
+            border: colors[0],
+        })
+    }
+
+    pub fn background(&self) -> Rgba {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Rgba {
+        self.colors[1]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::named("classic").unwrap()
+    }
+}
+
+fn rgb_to_rgba(rgb: u32) -> Rgba {
+    let [_, r, g, b] = rgb.to_be_bytes();
+    [r, g, b, 0xFF]
+}
+
+// Parses `#RRGGBB`, the leading `#` is optional
+pub fn parse_hex_color(source: &str) -> Result<Rgba, ParsePaletteError> {
+    let hex = source.strip_prefix('#').unwrap_or(source);
+    if hex.len() != 6 {
+        return Err(ParsePaletteError);
+    }
+    u32::from_str_radix(hex, 16)
+        .map(rgb_to_rgba)
+        .map_err(|_| ParsePaletteError)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_hex_color, Palette, ParsePaletteError, PALETTE_NAMES};
+
+    #[test]
+    fn all_palette_names_exist() {
+        for name in PALETTE_NAMES {
+            assert!(Palette::named(name).is_some(), "{name}");
+        }
+        assert_eq!(Palette::named("sepia"), None);
+    }
+
+    #[test]
+    fn parse_hex_colors() {
+        assert_eq!(parse_hex_color("#33FF66"), Ok([0x33, 0xFF, 0x66, 0xFF]));
+        assert_eq!(parse_hex_color("0a1a0a"), Ok([0x0A, 0x1A, 0x0A, 0xFF]));
+        assert_eq!(parse_hex_color("#FFF"), Err(ParsePaletteError));
+        assert_eq!(parse_hex_color("#GG0000"), Err(ParsePaletteError));
+    }
+
+    #[test]
+    fn parse_custom_palettes() {
+        let palette = Palette::parse("#102030, #FFFFFF").unwrap();
+        assert_eq!(palette.background(), [0x10, 0x20, 0x30, 0xFF]);
+        assert_eq!(palette.border, palette.background());
+        assert_eq!(palette.colors[3], palette.foreground());
+
+        let palette = Palette::parse("#000000,#111111,#222222,#333333").unwrap();
+        assert_eq!(palette.colors[3], [0x33, 0x33, 0x33, 0xFF]);
+
+        assert_eq!(Palette::parse("#000000"), Err(ParsePaletteError));
+        assert_eq!(
+            Palette::parse("#000000,#111111,#222222"),
+            Err(ParsePaletteError)
+        );
+    }
+
+    #[test]
+    fn parse_named_palette() {
+        assert_eq!(
+            Palette::parse("amber"),
+            Ok(Palette::named("amber").unwrap())
+        );
+    }
+}
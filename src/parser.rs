@@ -1,48 +1,378 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
 use crate::instructions::Instruction;
 
-#[derive(PartialEq, Eq, Debug)]
-pub struct ParseInstructionError;
-
-pub fn parse_instruction(source: [u8; 2]) -> Instruction {
-    let [high, low] = source;
-    match high >> 4 {
-        0x0 if low == 0xE0 => Instruction::ClearScreen,
-        0x0 if low == 0xEE => Instruction::Return,
-        0x0 => Instruction::Sys(u16::from_be_bytes(source)),
-        0x1 => Instruction::Jump(u16::from_be_bytes([high ^ (1 << 4), low])),
-        0x2 => Instruction::Call(u16::from_be_bytes([high ^ (2 << 4), low])),
-        0x3 => Instruction::SkipIfEqual(high & 0xF, low),
-        0x4 => Instruction::SkipIfNotEqual(high & 0xF, low),
-        0x5 => Instruction::SkipIfEqualReg(high & 0xF, low >> 4),
-        0x6 => Instruction::Set(high & 0xF, low),
-        0x7 => Instruction::Add(high & 0xF, low),
-        0x8 if low << 4 == 0x0 => Instruction::SetReg(high & 0xF, low >> 4),
-        0x8 if low << 4 == 0x10 => Instruction::Or(high & 0xF, low >> 4),
-        0x8 if low << 4 == 0x20 => Instruction::And(high & 0xF, low >> 4),
-        0x8 if low << 4 == 0x30 => Instruction::Xor(high & 0xF, low >> 4),
-        0x8 if low << 4 == 0x40 => Instruction::AddReg(high & 0xF, low >> 4),
-        0x8 if low << 4 == 0x50 => Instruction::SubReg(high & 0xF, low >> 4),
-        0x8 if low << 4 == 0x60 => Instruction::ShiftRight(high & 0xF, low >> 4),
-        0x8 if low << 4 == 0x70 => Instruction::SubN(high & 0xF, low >> 4),
-        0x8 if low << 4 == 0xE0 => Instruction::ShiftLeft(high & 0xF, low >> 4),
-        0x9 if low << 4 == 0x00 => Instruction::SkipIfNotEqualReg(high & 0xF, low >> 4),
-        0xA => Instruction::SetI(u16::from_be_bytes([high ^ (0xA << 4), low])),
-        0xB => Instruction::JumpToPlusV0(u16::from_be_bytes([high ^ (0xB << 4), low])),
-        0xC => Instruction::SetRandom(high & 0xF, low),
-        0xD => Instruction::Display(high & 0xF, low >> 4, low & 0xF),
-        0xE if low == 0x9E => Instruction::SkipIfKeyPressed(high & 0xF),
-        0xE if low == 0xA1 => Instruction::SkipIfKeyNotPressed(high & 0xF),
-        0xF if low == 0x07 => Instruction::SetDelayTimer(high & 0xF),
-        0xF if low == 0x0A => Instruction::WaitForKey(high & 0xF),
-        0xF if low == 0x15 => Instruction::SetDelayTimerReg(high & 0xF),
-        0xF if low == 0x18 => Instruction::SetSoundTimerReg(high & 0xF),
-        0xF if low == 0x1E => Instruction::AddToI(high & 0xF),
-        0xF if low == 0x29 => Instruction::SetSpriteLocation(high & 0xF),
-        0xF if low == 0x33 => Instruction::StoreBCD(high & 0xF),
-        0xF if low == 0x55 => Instruction::StoreRegRange(high & 0xF),
-        0xF if low == 0x65 => Instruction::LoadRegRange(high & 0xF),
-        _ => Instruction::Data(u16::from_be_bytes(source)),
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ParseInstructionError {
+    // No enabled opcode family has this opcode
+    UnknownOpcode(u16),
+    // The bytes end before the instruction does
+    Truncated,
+}
+
+impl fmt::Display for ParseInstructionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseInstructionError::UnknownOpcode(opcode) => {
+                write!(f, "unknown opcode {opcode:04X}")
+            }
+            ParseInstructionError::Truncated => write!(f, "the instruction is cut short"),
+        }
+    }
+}
+
+// Dialects of the COSMAC VIP interpreter with opcodes of their own
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Variant {
+    #[default]
+    Chip8,
+    Chip8X,
+    Chip8E,
+    MegaChip,
+}
+
+impl Variant {
+    pub fn parse(name: &str) -> Option<Variant> {
+        match name {
+            "chip8" => Some(Variant::Chip8),
+            "chip8x" => Some(Variant::Chip8X),
+            "chip8e" => Some(Variant::Chip8E),
+            "megachip" => Some(Variant::MegaChip),
+            _ => None,
+        }
+    }
+
+    // Opcodes the variant decodes
+    pub fn dialect(&self) -> Dialect {
+        let extension = match self {
+            Variant::Chip8 => return Dialect::CHIP8,
+            Variant::Chip8X => OpcodeFamily::Chip8X,
+            Variant::Chip8E => OpcodeFamily::Chip8E,
+            Variant::MegaChip => OpcodeFamily::MegaChip,
+        };
+        Dialect::CHIP8.with(extension)
+    }
+}
+
+// Groups of opcodes platforms add to CHIP-8 or change in it
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum OpcodeFamily {
+    // The instructions of the original interpreter
+    Chip8,
+    // Fx75 / Fx85, the SUPER-CHIP RPL user flags
+    Flags,
+    Chip8X,
+    Chip8E,
+    MegaChip,
+}
+
+impl OpcodeFamily {
+    // Every family, those that change opcodes of others first
+    pub const ALL: [OpcodeFamily; 5] = [
+        OpcodeFamily::MegaChip,
+        OpcodeFamily::Chip8E,
+        OpcodeFamily::Chip8X,
+        OpcodeFamily::Flags,
+        OpcodeFamily::Chip8,
+    ];
+
+    fn opcodes(&self) -> &'static [Opcode] {
+        match self {
+            OpcodeFamily::Chip8 => CHIP8,
+            OpcodeFamily::Flags => FLAGS,
+            OpcodeFamily::Chip8X => CHIP8X,
+            OpcodeFamily::Chip8E => CHIP8E,
+            OpcodeFamily::MegaChip => MEGACHIP,
+        }
+    }
+}
+
+/**
+ *  A platform's opcodes as the set of opcode families it enables. When two
+ *  families decode the same opcode, the one earlier in `OpcodeFamily::ALL`
+ *  wins, so extensions can replace CHIP-8 instructions.
+ */
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+pub struct Dialect {
+    // One bit per family, by position in `OpcodeFamily::ALL`
+    families: u8,
+}
+
+impl Dialect {
+    pub const CHIP8: Dialect = Dialect::new(&[OpcodeFamily::Chip8, OpcodeFamily::Flags]);
+
+    pub const fn new(families: &[OpcodeFamily]) -> Dialect {
+        let mut dialect = Dialect { families: 0 };
+        let mut index = 0;
+        while index < families.len() {
+            dialect = dialect.with(families[index]);
+            index += 1;
+        }
+        dialect
+    }
+
+    pub const fn with(self, family: OpcodeFamily) -> Dialect {
+        Dialect {
+            families: self.families | 1 << family as u8,
+        }
+    }
+
+    pub const fn contains(&self, family: OpcodeFamily) -> bool {
+        self.families & 1 << family as u8 != 0
+    }
+}
+
+// Operands of an opcode by their usual names
+#[derive(Clone, Copy)]
+struct Operands {
+    opcode: u16,
+    x: u8,
+    y: u8,
+    n: u8,
+    kk: u8,
+    nnn: u16,
+    // The word after the opcode, for instructions of 4 bytes
+    next: u16,
+}
+
+// Opcodes whose bits under `mask` are `value` decode with `decode`
+struct Opcode {
+    mask: u16,
+    value: u16,
+    decode: fn(Operands) -> Instruction,
+}
+
+const fn opcode(mask: u16, value: u16, decode: fn(Operands) -> Instruction) -> Opcode {
+    Opcode {
+        mask,
+        value,
+        decode,
+    }
+}
+
+static CHIP8: &[Opcode] = &[
+    opcode(0xFFFF, 0x00E0, |_| Instruction::ClearScreen),
+    opcode(0xFFFF, 0x00EE, |_| Instruction::Return),
+    opcode(0xF000, 0x0000, |o| Instruction::Sys(o.nnn)),
+    opcode(0xF000, 0x1000, |o| Instruction::Jump(o.nnn)),
+    opcode(0xF000, 0x2000, |o| Instruction::Call(o.nnn)),
+    opcode(0xF000, 0x3000, |o| Instruction::SkipIfEqual(o.x, o.kk)),
+    opcode(0xF000, 0x4000, |o| Instruction::SkipIfNotEqual(o.x, o.kk)),
+    opcode(0xF00F, 0x5000, |o| Instruction::SkipIfEqualReg(o.x, o.y)),
+    opcode(0xF000, 0x6000, |o| Instruction::Set(o.x, o.kk)),
+    opcode(0xF000, 0x7000, |o| Instruction::Add(o.x, o.kk)),
+    opcode(0xF00F, 0x8000, |o| Instruction::SetReg(o.x, o.y)),
+    opcode(0xF00F, 0x8001, |o| Instruction::Or(o.x, o.y)),
+    opcode(0xF00F, 0x8002, |o| Instruction::And(o.x, o.y)),
+    opcode(0xF00F, 0x8003, |o| Instruction::Xor(o.x, o.y)),
+    opcode(0xF00F, 0x8004, |o| Instruction::AddReg(o.x, o.y)),
+    opcode(0xF00F, 0x8005, |o| Instruction::SubReg(o.x, o.y)),
+    opcode(0xF00F, 0x8006, |o| Instruction::ShiftRight(o.x, o.y)),
+    opcode(0xF00F, 0x8007, |o| Instruction::SubN(o.x, o.y)),
+    opcode(0xF00F, 0x800E, |o| Instruction::ShiftLeft(o.x, o.y)),
+    opcode(0xF00F, 0x9000, |o| Instruction::SkipIfNotEqualReg(o.x, o.y)),
+    opcode(0xF000, 0xA000, |o| Instruction::SetI(o.nnn)),
+    opcode(0xF000, 0xB000, |o| Instruction::JumpToPlusV0(o.nnn)),
+    opcode(0xF000, 0xC000, |o| Instruction::SetRandom(o.x, o.kk)),
+    opcode(0xF000, 0xD000, |o| Instruction::Display(o.x, o.y, o.n)),
+    opcode(0xF0FF, 0xE09E, |o| Instruction::SkipIfKeyPressed(o.x)),
+    opcode(0xF0FF, 0xE0A1, |o| Instruction::SkipIfKeyNotPressed(o.x)),
+    opcode(0xF0FF, 0xF007, |o| Instruction::SetDelayTimer(o.x)),
+    opcode(0xF0FF, 0xF00A, |o| Instruction::WaitForKey(o.x)),
+    opcode(0xF0FF, 0xF015, |o| Instruction::SetDelayTimerReg(o.x)),
+    opcode(0xF0FF, 0xF018, |o| Instruction::SetSoundTimerReg(o.x)),
+    opcode(0xF0FF, 0xF01E, |o| Instruction::AddToI(o.x)),
+    opcode(0xF0FF, 0xF029, |o| Instruction::SetSpriteLocation(o.x)),
+    opcode(0xF0FF, 0xF033, |o| Instruction::StoreBCD(o.x)),
+    opcode(0xF0FF, 0xF055, |o| Instruction::StoreRegRange(o.x)),
+    opcode(0xF0FF, 0xF065, |o| Instruction::LoadRegRange(o.x)),
+];
+
+static FLAGS: &[Opcode] = &[
+    opcode(0xF0FF, 0xF075, |o| Instruction::StoreFlags(o.x)),
+    opcode(0xF0FF, 0xF085, |o| Instruction::LoadFlags(o.x)),
+];
+
+static CHIP8X: &[Opcode] = &[
+    opcode(0xFFFF, 0x02A0, |_| Instruction::CycleBackground),
+    opcode(0xF00F, 0x5001, |o| Instruction::AddNibbles(o.x, o.y)),
+    // Bnnn is gone
+    opcode(0xF00F, 0xB000, |o| Instruction::SetColorZones(o.x, o.y)),
+    opcode(0xF000, 0xB000, |o| Instruction::SetColorArea(o.x, o.y, o.n)),
+    opcode(0xF0FF, 0xE0F2, |o| Instruction::SkipIfKeyPressed2(o.x)),
+    opcode(0xF0FF, 0xE0F5, |o| Instruction::SkipIfKeyNotPressed2(o.x)),
+    opcode(0xF0FF, 0xF0F8, |o| Instruction::OutputTone(o.x)),
+    opcode(0xF0FF, 0xF0FB, |o| Instruction::WaitForInput(o.x)),
+];
+
+static CHIP8E: &[Opcode] = &[
+    opcode(0xFFFF, 0x00ED, |_| Instruction::Stop),
+    opcode(0xFFFF, 0x0151, |_| Instruction::WaitForDelay),
+    opcode(0xFFFF, 0x0188, |_| Instruction::SkipNext),
+    opcode(0xF00F, 0x5001, |o| Instruction::SkipIfGreater(o.x, o.y)),
+    opcode(0xF00F, 0x5002, |o| Instruction::StoreRange(o.x, o.y)),
+    opcode(0xF00F, 0x5003, |o| Instruction::LoadRange(o.x, o.y)),
+    opcode(0xFF00, 0xBB00, |o| Instruction::JumpBackward(o.kk)),
+    opcode(0xFF00, 0xBF00, |o| Instruction::JumpForward(o.kk)),
+    opcode(0xF0FF, 0xF003, |o| Instruction::Output(o.x)),
+    opcode(0xF0FF, 0xF01B, |o| Instruction::SkipBytes(o.x)),
+    opcode(0xF0FF, 0xF04F, |o| Instruction::DelayFor(o.x)),
+    opcode(0xF0FF, 0xF0E3, |o| Instruction::WaitForStrobe(o.x)),
+    opcode(0xF0FF, 0xF0E7, |o| Instruction::Input(o.x)),
+];
+
+static MEGACHIP: &[Opcode] = &[
+    opcode(0xFFFF, 0x0010, |_| Instruction::DisableMegaChip),
+    opcode(0xFFFF, 0x0011, |_| Instruction::EnableMegaChip),
+    opcode(0xFFF0, 0x00B0, |o| Instruction::ScrollUp(o.n)),
+    opcode(0xFFF0, 0x00C0, |o| Instruction::ScrollDown(o.n)),
+    opcode(0xFFFF, 0x00FB, |_| Instruction::ScrollRight),
+    opcode(0xFFFF, 0x00FC, |_| Instruction::ScrollLeft),
+    opcode(0xFFFF, 0x00FD, |_| Instruction::Exit),
+    opcode(0xFF00, 0x0100, |o| {
+        Instruction::SetLongI((o.kk as u32) << 16 | o.next as u32)
+    }),
+    opcode(0xFF00, 0x0200, |o| Instruction::LoadPalette(o.kk)),
+    opcode(0xFF00, 0x0300, |o| Instruction::SetSpriteWidth(o.kk)),
+    opcode(0xFF00, 0x0400, |o| Instruction::SetSpriteHeight(o.kk)),
+    opcode(0xFF00, 0x0500, |o| Instruction::SetScreenAlpha(o.kk)),
+    opcode(0xFFF0, 0x0600, |o| Instruction::PlaySound(o.n)),
+    opcode(0xFFFF, 0x0700, |_| Instruction::StopSound),
+    opcode(0xFFF0, 0x0800, |o| Instruction::SetBlendMode(o.n)),
+    opcode(0xFF00, 0x0900, |o| Instruction::SetCollisionColor(o.kk)),
+];
+
+/**
+ *  Decodes the instruction at the start of `bytes` with the opcodes of
+ *  `dialect`. Most instructions take 2 bytes, `Instruction::length` tells
+ *  how many this one took.
+ */
+pub fn decode_instruction(
+    bytes: &[u8],
+    dialect: Dialect,
+) -> Result<Instruction, ParseInstructionError> {
+    let [high, low] = *bytes
+        .first_chunk::<2>()
+        .ok_or(ParseInstructionError::Truncated)?;
+    let next = bytes
+        .get(2..4)
+        .map_or(0, |next| u16::from_be_bytes([next[0], next[1]]));
+    let opcode = u16::from_be_bytes([high, low]);
+    let operands = Operands {
+        opcode,
+        x: high & 0xF,
+        y: low >> 4,
+        n: low & 0xF,
+        kk: low,
+        nnn: opcode & 0xFFF,
+        next,
+    };
+
+    let instruction = OpcodeFamily::ALL
+        .iter()
+        .filter(|family| dialect.contains(**family))
+        .flat_map(|family| family.opcodes())
+        .find(|entry| operands.opcode & entry.mask == entry.value)
+        .map(|entry| (entry.decode)(operands))
+        .ok_or(ParseInstructionError::UnknownOpcode(opcode))?;
+    if instruction.length() > bytes.len() {
+        return Err(ParseInstructionError::Truncated);
     }
+    Ok(instruction)
+}
+
+// Bytes of an instruction, the inverse of `decode_instruction` with a dialect
+// the instruction belongs to. Operands are cut to the bits the opcode has room
+// for.
+pub fn encode_instruction(instruction: Instruction) -> Vec<u8> {
+    let nnn = |prefix: u16, address: u16| prefix << 12 | address & 0xFFF;
+    let xkk = |prefix: u16, x: u8, kk: u8| prefix << 12 | ((x & 0xF) as u16) << 8 | kk as u16;
+    let xyn = |prefix: u16, x: u8, y: u8, n: u8| xkk(prefix, x, (y & 0xF) << 4 | n & 0xF);
+    let opcode = match instruction {
+        Instruction::ClearScreen => 0x00E0,
+        Instruction::Return => 0x00EE,
+        Instruction::Sys(address) => nnn(0x0, address),
+        Instruction::Jump(address) => nnn(0x1, address),
+        Instruction::Call(address) => nnn(0x2, address),
+        Instruction::SkipIfEqual(x, value) => xkk(0x3, x, value),
+        Instruction::SkipIfNotEqual(x, value) => xkk(0x4, x, value),
+        Instruction::SkipIfEqualReg(x, y) => xyn(0x5, x, y, 0x0),
+        Instruction::Set(x, value) => xkk(0x6, x, value),
+        Instruction::Add(x, value) => xkk(0x7, x, value),
+        Instruction::SetReg(x, y) => xyn(0x8, x, y, 0x0),
+        Instruction::Or(x, y) => xyn(0x8, x, y, 0x1),
+        Instruction::And(x, y) => xyn(0x8, x, y, 0x2),
+        Instruction::Xor(x, y) => xyn(0x8, x, y, 0x3),
+        Instruction::AddReg(x, y) => xyn(0x8, x, y, 0x4),
+        Instruction::SubReg(x, y) => xyn(0x8, x, y, 0x5),
+        Instruction::ShiftRight(x, y) => xyn(0x8, x, y, 0x6),
+        Instruction::SubN(x, y) => xyn(0x8, x, y, 0x7),
+        Instruction::ShiftLeft(x, y) => xyn(0x8, x, y, 0xE),
+        Instruction::SkipIfNotEqualReg(x, y) => xyn(0x9, x, y, 0x0),
+        Instruction::SetI(address) => nnn(0xA, address),
+        Instruction::JumpToPlusV0(address) => nnn(0xB, address),
+        Instruction::SetRandom(x, value) => xkk(0xC, x, value),
+        Instruction::Display(x, y, n) => xyn(0xD, x, y, n),
+        Instruction::SkipIfKeyPressed(x) => xkk(0xE, x, 0x9E),
+        Instruction::SkipIfKeyNotPressed(x) => xkk(0xE, x, 0xA1),
+        Instruction::SetDelayTimer(x) => xkk(0xF, x, 0x07),
+        Instruction::WaitForKey(x) => xkk(0xF, x, 0x0A),
+        Instruction::SetDelayTimerReg(x) => xkk(0xF, x, 0x15),
+        Instruction::SetSoundTimerReg(x) => xkk(0xF, x, 0x18),
+        Instruction::AddToI(x) => xkk(0xF, x, 0x1E),
+        Instruction::SetSpriteLocation(x) => xkk(0xF, x, 0x29),
+        Instruction::StoreBCD(x) => xkk(0xF, x, 0x33),
+        Instruction::StoreRegRange(x) => xkk(0xF, x, 0x55),
+        Instruction::LoadRegRange(x) => xkk(0xF, x, 0x65),
+        Instruction::StoreFlags(x) => xkk(0xF, x, 0x75),
+        Instruction::LoadFlags(x) => xkk(0xF, x, 0x85),
+        Instruction::CycleBackground => 0x02A0,
+        Instruction::AddNibbles(x, y) => xyn(0x5, x, y, 0x1),
+        Instruction::SetColorZones(x, y) => xyn(0xB, x, y, 0x0),
+        Instruction::SetColorArea(x, y, n) => xyn(0xB, x, y, n),
+        Instruction::SkipIfKeyPressed2(x) => xkk(0xE, x, 0xF2),
+        Instruction::SkipIfKeyNotPressed2(x) => xkk(0xE, x, 0xF5),
+        Instruction::OutputTone(x) => xkk(0xF, x, 0xF8),
+        Instruction::WaitForInput(x) => xkk(0xF, x, 0xFB),
+        Instruction::Stop => 0x00ED,
+        Instruction::WaitForDelay => 0x0151,
+        Instruction::SkipNext => 0x0188,
+        Instruction::SkipIfGreater(x, y) => xyn(0x5, x, y, 0x1),
+        Instruction::StoreRange(x, y) => xyn(0x5, x, y, 0x2),
+        Instruction::LoadRange(x, y) => xyn(0x5, x, y, 0x3),
+        Instruction::JumpBackward(offset) => xkk(0xB, 0xB, offset),
+        Instruction::JumpForward(offset) => xkk(0xB, 0xF, offset),
+        Instruction::Output(x) => xkk(0xF, x, 0x03),
+        Instruction::SkipBytes(x) => xkk(0xF, x, 0x1B),
+        Instruction::DelayFor(x) => xkk(0xF, x, 0x4F),
+        Instruction::WaitForStrobe(x) => xkk(0xF, x, 0xE3),
+        Instruction::Input(x) => xkk(0xF, x, 0xE7),
+        Instruction::DisableMegaChip => 0x0010,
+        Instruction::EnableMegaChip => 0x0011,
+        Instruction::SetLongI(address) => {
+            let high = xkk(0x0, 0x1, (address >> 16) as u8);
+            let low = address as u16;
+            return [high.to_be_bytes(), low.to_be_bytes()].concat();
+        }
+        Instruction::LoadPalette(count) => xkk(0x0, 0x2, count),
+        Instruction::SetSpriteWidth(width) => xkk(0x0, 0x3, width),
+        Instruction::SetSpriteHeight(height) => xkk(0x0, 0x4, height),
+        Instruction::SetScreenAlpha(alpha) => xkk(0x0, 0x5, alpha),
+        Instruction::PlaySound(mode) => xkk(0x0, 0x6, mode & 0xF),
+        Instruction::StopSound => 0x0700,
+        Instruction::SetBlendMode(mode) => xkk(0x0, 0x8, mode & 0xF),
+        Instruction::SetCollisionColor(color) => xkk(0x0, 0x9, color),
+        Instruction::ScrollUp(n) => xkk(0x0, 0x0, 0xB0 | n & 0xF),
+        Instruction::ScrollDown(n) => xkk(0x0, 0x0, 0xC0 | n & 0xF),
+        Instruction::ScrollRight => 0x00FB,
+        Instruction::ScrollLeft => 0x00FC,
+        Instruction::Exit => 0x00FD,
+        Instruction::Data(word) => word,
+    };
+    opcode.to_be_bytes().to_vec()
 }
 
 #[cfg(test)]
@@ -50,7 +380,10 @@ mod test {
     use std::io::Read;
 
     use crate::instructions::Instruction;
-    use crate::parser::parse_instruction;
+    use crate::parser::{
+        decode_instruction, encode_instruction, Dialect, OpcodeFamily, ParseInstructionError,
+        Variant,
+    };
     use crate::test_data::DATA;
 
     #[test]
@@ -59,7 +392,7 @@ mod test {
         let _ = DATA.take(8).read_to_end(&mut instructions_bytes);
         let instructions = instructions_bytes
             .chunks(2)
-            .map(|chunk| parse_instruction([chunk[0], chunk[1]]))
+            .map(|chunk| decode_instruction(chunk, Variant::Chip8.dialect()).unwrap())
             .collect::<Vec<Instruction>>();
         assert_eq!(
             instructions,
@@ -76,15 +409,15 @@ mod test {
     fn parse_instructions_file() {
         let instructions = DATA
             .chunks(2)
-            .map(|chunk| parse_instruction([chunk[0], chunk[1]]))
-            .collect::<Vec<Instruction>>();
+            .map(|chunk| decode_instruction(chunk, Variant::Chip8.dialect()))
+            .collect::<Vec<_>>();
         dbg!(instructions);
     }
 
     fn assert_instruction(input: [u8; 2], output: Instruction) {
-        let instruction = parse_instruction(input);
+        let instruction = decode_instruction(&input, Variant::Chip8.dialect());
 
-        assert_eq!(instruction, output);
+        assert_eq!(instruction, Ok(output));
     }
 
     #[test]
@@ -261,4 +594,909 @@ mod test {
     fn read_load_register_range_instruction() {
         assert_instruction([0xFD, 0x65], Instruction::LoadRegRange(0xD))
     }
+
+    #[test]
+    fn read_store_flags_instruction() {
+        assert_instruction([0xF7, 0x75], Instruction::StoreFlags(0x7))
+    }
+
+    #[test]
+    fn read_load_flags_instruction() {
+        assert_instruction([0xF3, 0x85], Instruction::LoadFlags(0x3))
+    }
+
+    #[test]
+    fn read_sys_instruction_ending_like_clear_screen() {
+        assert_instruction([0x01, 0xE0], Instruction::Sys(0x1E0))
+    }
+
+    #[test]
+    fn read_unknown_skip_if_equal_register_variant() {
+        assert_eq!(
+            decode_instruction(&[0x51, 0x22], Variant::Chip8.dialect()),
+            Err(ParseInstructionError::UnknownOpcode(0x5122))
+        )
+    }
+
+    #[test]
+    fn read_variant_instructions() {
+        use ParseInstructionError::UnknownOpcode;
+        let decode =
+            |source: [u8; 2], variant: Variant| decode_instruction(&source, variant.dialect());
+        let cases = [
+            (
+                [0x02, 0xA0],
+                Ok(Instruction::Sys(0x2A0)),
+                Ok(Instruction::CycleBackground),
+            ),
+            (
+                [0xB1, 0x23],
+                Ok(Instruction::JumpToPlusV0(0x123)),
+                Ok(Instruction::SetColorArea(1, 2, 3)),
+            ),
+            (
+                [0x51, 0x21],
+                Err(UnknownOpcode(0x5121)),
+                Ok(Instruction::AddNibbles(1, 2)),
+            ),
+            (
+                [0xE4, 0xF2],
+                Err(UnknownOpcode(0xE4F2)),
+                Ok(Instruction::SkipIfKeyPressed2(4)),
+            ),
+        ];
+        for (source, chip8, chip8x) in cases {
+            assert_eq!(decode(source, Variant::Chip8), chip8);
+            assert_eq!(decode(source, Variant::Chip8X), chip8x);
+        }
+
+        let cases = [
+            (
+                [0x00, 0xED],
+                Ok(Instruction::Sys(0x0ED)),
+                Ok(Instruction::Stop),
+            ),
+            (
+                [0x51, 0x21],
+                Err(UnknownOpcode(0x5121)),
+                Ok(Instruction::SkipIfGreater(1, 2)),
+            ),
+            (
+                [0x53, 0x62],
+                Err(UnknownOpcode(0x5362)),
+                Ok(Instruction::StoreRange(3, 6)),
+            ),
+            (
+                [0xBB, 0x10],
+                Ok(Instruction::JumpToPlusV0(0xB10)),
+                Ok(Instruction::JumpBackward(0x10)),
+            ),
+            (
+                [0xB1, 0x10],
+                Ok(Instruction::JumpToPlusV0(0x110)),
+                Ok(Instruction::JumpToPlusV0(0x110)),
+            ),
+            (
+                [0xF2, 0x4F],
+                Err(UnknownOpcode(0xF24F)),
+                Ok(Instruction::DelayFor(2)),
+            ),
+        ];
+        for (source, chip8, chip8e) in cases {
+            assert_eq!(decode(source, Variant::Chip8), chip8);
+            assert_eq!(decode(source, Variant::Chip8E), chip8e);
+        }
+
+        let cases = [
+            (
+                [0x00, 0x11],
+                Ok(Instruction::Sys(0x011)),
+                Ok(Instruction::EnableMegaChip),
+            ),
+            (
+                [0x03, 0x00],
+                Ok(Instruction::Sys(0x300)),
+                Ok(Instruction::SetSpriteWidth(0)),
+            ),
+            (
+                [0x00, 0xC4],
+                Ok(Instruction::Sys(0x0C4)),
+                Ok(Instruction::ScrollDown(4)),
+            ),
+            (
+                [0x00, 0xFD],
+                Ok(Instruction::Sys(0x0FD)),
+                Ok(Instruction::Exit),
+            ),
+            (
+                [0x06, 0x12],
+                Ok(Instruction::Sys(0x612)),
+                Ok(Instruction::Sys(0x612)),
+            ),
+            (
+                [0x00, 0xE0],
+                Ok(Instruction::ClearScreen),
+                Ok(Instruction::ClearScreen),
+            ),
+        ];
+        for (source, chip8, megachip) in cases {
+            assert_eq!(decode(source, Variant::Chip8), chip8);
+            assert_eq!(decode(source, Variant::MegaChip), megachip);
+        }
+    }
+
+    #[test]
+    fn encode_every_opcode_back() {
+        for variant in [
+            Variant::Chip8,
+            Variant::Chip8X,
+            Variant::Chip8E,
+            Variant::MegaChip,
+        ] {
+            for word in 0..=u16::MAX {
+                let [high, low] = word.to_be_bytes();
+                let bytes = [high, low, 0xAB, 0xCD];
+                match decode_instruction(&bytes, variant.dialect()) {
+                    Ok(instruction) => assert_eq!(
+                        encode_instruction(instruction),
+                        bytes[..instruction.length()],
+                        "{instruction:?}"
+                    ),
+                    Err(error) => {
+                        assert_eq!(error, ParseInstructionError::UnknownOpcode(word))
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn encode_operands_in_range() {
+        assert_eq!(encode_instruction(Instruction::Jump(0x1234)), [0x12, 0x34]);
+        assert_eq!(
+            encode_instruction(Instruction::Display(0x1F, 2, 0x13)),
+            [0xDF, 0x23]
+        );
+    }
+
+    #[test]
+    fn decode_long_instructions() {
+        let dialect = Variant::MegaChip.dialect();
+        assert_eq!(
+            decode_instruction(&[0x01, 0x23, 0x45, 0x67], dialect),
+            Ok(Instruction::SetLongI(0x234567))
+        );
+        assert_eq!(
+            decode_instruction(&[0x01, 0x23], dialect),
+            Err(ParseInstructionError::Truncated)
+        );
+        assert_eq!(
+            decode_instruction(&[0x01, 0x23], Variant::Chip8.dialect()),
+            Ok(Instruction::Sys(0x123))
+        );
+        assert_eq!(
+            decode_instruction(&[0x60], dialect),
+            Err(ParseInstructionError::Truncated)
+        );
+    }
+
+    #[test]
+    fn decode_only_enabled_families() {
+        let bytes = [0xF3, 0x75];
+        let plain = Dialect::new(&[OpcodeFamily::Chip8]);
+        assert_eq!(
+            decode_instruction(&bytes, plain),
+            Err(ParseInstructionError::UnknownOpcode(0xF375))
+        );
+        assert_eq!(
+            decode_instruction(&bytes, plain.with(OpcodeFamily::Flags)),
+            Ok(Instruction::StoreFlags(3))
+        );
+        assert!(Dialect::CHIP8.contains(OpcodeFamily::Flags));
+        assert!(!Dialect::CHIP8.contains(OpcodeFamily::MegaChip));
+    }
+
+    #[test]
+    fn display_decode_errors() {
+        assert_eq!(
+            ParseInstructionError::UnknownOpcode(0x5121).to_string(),
+            "unknown opcode 5121"
+        );
+        assert_eq!(
+            ParseInstructionError::Truncated.to_string(),
+            "the instruction is cut short"
+        );
+    }
+}
+
+// Effects of running each instruction once, from 0x200
+#[cfg(test)]
+mod execution_test {
+    use crate::context::{
+        Context, Fault, DEFAULT_STACK_DEPTH, DEFAULT_ZONE_COLOR, FONT_ADDRESS, STACK_ADDRESS,
+    };
+    use crate::instructions::Instruction;
+    use crate::parser::Variant;
+
+    // Context about to run `opcode` at 0x200
+    fn load(opcode: [u8; 2]) -> Context {
+        Context::new(&opcode, 1)
+    }
+
+    // Context of `variant` about to run `opcode` at 0x200
+    fn load_variant(opcode: [u8; 2], variant: Variant) -> Context {
+        let mut context = load(opcode);
+        context.set_variant(variant);
+        context
+    }
+
+    // Runs the instruction and checks that it was decoded as `instruction`
+    fn run(context: &mut Context, instruction: Instruction) {
+        assert_eq!(context.tick(), instruction);
+    }
+
+    #[test]
+    fn execute_clear_screen() {
+        let mut context = load([0x00, 0xE0]);
+        context.graphics_buffer[3][4] = 0xFF;
+        run(&mut context, Instruction::ClearScreen);
+        assert!(context
+            .get_flat_graphics_buffer()
+            .iter()
+            .all(|byte| *byte == 0));
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_return() {
+        let mut context = load([0x00, 0xEE]);
+        context.stack_pointer = vec![0x300, 0x402];
+        run(&mut context, Instruction::Return);
+        assert_eq!(context.program_counter, 0x402);
+        assert_eq!(context.stack_pointer, [0x300]);
+    }
+
+    #[test]
+    fn execute_return_on_empty_stack() {
+        let mut context = load([0x00, 0xEE]);
+        run(&mut context, Instruction::Return);
+        assert_eq!(context.fault, Some(Fault::StackUnderflow(0x200)));
+        assert_eq!(context.program_counter, 0x200);
+    }
+
+    #[test]
+    fn execute_return_with_stack_in_memory() {
+        // The program changed the return address
+        let mut context = load([0x00, 0xEE]);
+        context.quirks.stack_in_memory = true;
+        context.stack_pointer = vec![0x402];
+        context.memory_map[STACK_ADDRESS as usize..][..2].copy_from_slice(&[0x05, 0x10]);
+        run(&mut context, Instruction::Return);
+        assert_eq!(context.program_counter, 0x510);
+        assert!(context.stack_pointer.is_empty());
+    }
+
+    #[test]
+    fn execute_sys() {
+        let mut context = load([0x03, 0x00]);
+        run(&mut context, Instruction::Sys(0x300));
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_jump() {
+        let mut context = load([0x13, 0x46]);
+        run(&mut context, Instruction::Jump(0x346));
+        assert_eq!(context.program_counter, 0x346);
+    }
+
+    #[test]
+    fn execute_call() {
+        let mut context = load([0x23, 0x46]);
+        run(&mut context, Instruction::Call(0x346));
+        assert_eq!(context.program_counter, 0x346);
+        assert_eq!(context.stack_pointer, [0x202]);
+    }
+
+    #[test]
+    fn execute_call_on_full_stack() {
+        let mut context = load([0x23, 0x46]);
+        context.stack_pointer = vec![0x300; DEFAULT_STACK_DEPTH];
+        run(&mut context, Instruction::Call(0x346));
+        assert_eq!(context.fault, Some(Fault::StackOverflow(0x200)));
+        assert_eq!(context.stack_pointer.len(), DEFAULT_STACK_DEPTH);
+        assert_eq!(context.program_counter, 0x200);
+
+        // Nothing runs after a fault
+        run(&mut context, Instruction::Call(0x346));
+        assert_eq!(context.program_counter, 0x200);
+    }
+
+    #[test]
+    fn execute_call_with_stack_in_memory() {
+        let mut context = load([0x23, 0x46]);
+        context.quirks.stack_in_memory = true;
+        context.stack_pointer = vec![0x300];
+        run(&mut context, Instruction::Call(0x346));
+        let slot = STACK_ADDRESS as usize + 2;
+        assert_eq!(context.memory_map[slot..slot + 2], [0x02, 0x02]);
+        assert_eq!(context.stack_pointer, [0x300, 0x202]);
+    }
+    #[test]
+    fn execute_skip_if_equal() {
+        let mut context = load([0x35, 0x42]);
+        context.registers[5] = 0x42;
+        run(&mut context, Instruction::SkipIfEqual(5, 0x42));
+        assert_eq!(context.program_counter, 0x204);
+
+        let mut context = load([0x35, 0x42]);
+        run(&mut context, Instruction::SkipIfEqual(5, 0x42));
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_skip_if_not_equal() {
+        let mut context = load([0x45, 0x42]);
+        run(&mut context, Instruction::SkipIfNotEqual(5, 0x42));
+        assert_eq!(context.program_counter, 0x204);
+
+        let mut context = load([0x45, 0x42]);
+        context.registers[5] = 0x42;
+        run(&mut context, Instruction::SkipIfNotEqual(5, 0x42));
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_skip_if_equal_register() {
+        let mut context = load([0x51, 0x20]);
+        context.registers[1..3].copy_from_slice(&[7, 7]);
+        run(&mut context, Instruction::SkipIfEqualReg(1, 2));
+        assert_eq!(context.program_counter, 0x204);
+
+        let mut context = load([0x51, 0x20]);
+        context.registers[1] = 7;
+        run(&mut context, Instruction::SkipIfEqualReg(1, 2));
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_set() {
+        let mut context = load([0x6A, 0x42]);
+        run(&mut context, Instruction::Set(0xA, 0x42));
+        assert_eq!(context.registers[0xA], 0x42);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_add() {
+        let mut context = load([0x7A, 0x42]);
+        context.registers[0xA] = 0xC0;
+        run(&mut context, Instruction::Add(0xA, 0x42));
+        assert_eq!(context.registers[0xA], 0x02);
+        // No carry flag
+        assert_eq!(context.registers[0xF], 0);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    // Runs 8xyN with Vx = 0x3C and Vy = 0xA5, returns Vx and VF
+    fn alu(low: u8, instruction: Instruction) -> (u8, u8) {
+        let mut context = load([0x81, low]);
+        context.registers[1] = 0x3C;
+        context.registers[2] = 0xA5;
+        context.registers[0xF] = 0xFF;
+        run(&mut context, instruction);
+        assert_eq!(context.program_counter, 0x202);
+        (context.registers[1], context.registers[0xF])
+    }
+
+    #[test]
+    fn execute_set_register() {
+        assert_eq!(alu(0x20, Instruction::SetReg(1, 2)), (0xA5, 0xFF));
+    }
+
+    #[test]
+    fn execute_or() {
+        assert_eq!(alu(0x21, Instruction::Or(1, 2)), (0xBD, 0xFF));
+    }
+
+    #[test]
+    fn execute_and() {
+        assert_eq!(alu(0x22, Instruction::And(1, 2)), (0x24, 0xFF));
+    }
+
+    #[test]
+    fn execute_xor() {
+        assert_eq!(alu(0x23, Instruction::Xor(1, 2)), (0x99, 0xFF));
+    }
+
+    #[test]
+    fn execute_add_register() {
+        assert_eq!(alu(0x24, Instruction::AddReg(1, 2)), (0xE1, 0));
+    }
+
+    #[test]
+    fn execute_subtract_register() {
+        assert_eq!(alu(0x25, Instruction::SubReg(1, 2)), (0x97, 0));
+    }
+
+    #[test]
+    fn execute_shift_right() {
+        assert_eq!(alu(0x26, Instruction::ShiftRight(1, 2)), (0x1E, 0));
+    }
+
+    #[test]
+    fn execute_subtract_negated() {
+        assert_eq!(alu(0x27, Instruction::SubN(1, 2)), (0x69, 1));
+    }
+
+    #[test]
+    fn execute_shift_left() {
+        assert_eq!(alu(0x2E, Instruction::ShiftLeft(1, 2)), (0x78, 0));
+    }
+
+    #[test]
+    fn execute_skip_if_not_equal_register() {
+        let mut context = load([0x91, 0x20]);
+        context.registers[1] = 7;
+        run(&mut context, Instruction::SkipIfNotEqualReg(1, 2));
+        assert_eq!(context.program_counter, 0x204);
+
+        let mut context = load([0x91, 0x20]);
+        run(&mut context, Instruction::SkipIfNotEqualReg(1, 2));
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_set_i() {
+        let mut context = load([0xA3, 0x46]);
+        run(&mut context, Instruction::SetI(0x346));
+        assert_eq!(context.i_register, 0x346);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_jump_plus_v0() {
+        let mut context = load([0xB3, 0x00]);
+        context.registers[0] = 0x46;
+        context.registers[3] = 0x10;
+        run(&mut context, Instruction::JumpToPlusV0(0x300));
+        assert_eq!(context.program_counter, 0x346);
+
+        let mut context = load([0xB3, 0x00]);
+        context.quirks.jump_uses_vx = true;
+        context.registers[3] = 0x10;
+        run(&mut context, Instruction::JumpToPlusV0(0x300));
+        assert_eq!(context.program_counter, 0x310);
+    }
+
+    #[test]
+    fn execute_set_random() {
+        let mut context = load([0xC4, 0x0F]);
+        context.registers[4] = 0xF0;
+        run(&mut context, Instruction::SetRandom(4, 0x0F));
+        assert_eq!(context.registers[4] & 0xF0, 0);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_display() {
+        let mut context = load([0xD1, 0x22]);
+        context.registers[1] = 4;
+        context.registers[2] = 3;
+        context.i_register = 0x300;
+        context.memory_map[0x300..0x302].copy_from_slice(&[0xFF, 0x81]);
+        run(&mut context, Instruction::Display(1, 2, 2));
+        assert_eq!(context.graphics_buffer[3][..2], [0x0F, 0xF0]);
+        assert_eq!(context.graphics_buffer[4][..2], [0x08, 0x10]);
+        assert_eq!(context.registers[0xF], 0);
+        assert_eq!(context.program_counter, 0x202);
+
+        // Drawing again erases it
+        context.program_counter = 0x200;
+        run(&mut context, Instruction::Display(1, 2, 2));
+        assert_eq!(context.get_flat_graphics_buffer().iter().sum::<u8>(), 0);
+        assert_eq!(context.registers[0xF], 1);
+    }
+
+    #[test]
+    fn execute_skip_if_key_pressed() {
+        let mut context = load([0xE3, 0x9E]);
+        context.registers[3] = 0xA;
+        context.keyboard_input = Some(0xA);
+        run(&mut context, Instruction::SkipIfKeyPressed(3));
+        assert_eq!(context.program_counter, 0x204);
+
+        let mut context = load([0xE3, 0x9E]);
+        context.keyboard_input = Some(0xA);
+        run(&mut context, Instruction::SkipIfKeyPressed(3));
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_skip_if_key_not_pressed() {
+        let mut context = load([0xE3, 0xA1]);
+        context.registers[3] = 0xA;
+        run(&mut context, Instruction::SkipIfKeyNotPressed(3));
+        assert_eq!(context.program_counter, 0x204);
+
+        let mut context = load([0xE3, 0xA1]);
+        context.registers[3] = 0xA;
+        context.keyboard_input = Some(0xA);
+        run(&mut context, Instruction::SkipIfKeyNotPressed(3));
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_set_delay_timer() {
+        let mut context = load([0xF6, 0x07]);
+        context.delay_timer = 0x42;
+        run(&mut context, Instruction::SetDelayTimer(6));
+        assert_eq!(context.registers[6], 0x42);
+        assert_eq!(context.delay_timer, 0x42);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_wait_for_key() {
+        let mut context = load([0xF6, 0x0A]);
+        run(&mut context, Instruction::WaitForKey(6));
+        assert_eq!(context.program_counter, 0x200);
+
+        context.keyboard_input = Some(0xB);
+        run(&mut context, Instruction::WaitForKey(6));
+        assert_eq!(context.registers[6], 0xB);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_set_delay_timer_register() {
+        let mut context = load([0xF6, 0x15]);
+        context.registers[6] = 0x42;
+        run(&mut context, Instruction::SetDelayTimerReg(6));
+        assert_eq!(context.delay_timer, 0x42);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_set_sound_timer_register() {
+        let mut context = load([0xF6, 0x18]);
+        context.registers[6] = 0x42;
+        run(&mut context, Instruction::SetSoundTimerReg(6));
+        assert_eq!(context.sound_timer, 0x42);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_add_to_i() {
+        let mut context = load([0xF6, 0x1E]);
+        context.registers[6] = 0x42;
+        context.i_register = 0x300;
+        run(&mut context, Instruction::AddToI(6));
+        assert_eq!(context.i_register, 0x342);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_set_sprite_location() {
+        let mut context = load([0xF6, 0x29]);
+        context.registers[6] = 0x17;
+        run(&mut context, Instruction::SetSpriteLocation(6));
+        assert_eq!(context.i_register, (FONT_ADDRESS + 5 * 7) as u32);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_store_bcd() {
+        let mut context = load([0xF6, 0x33]);
+        context.registers[6] = 254;
+        context.i_register = 0x300;
+        run(&mut context, Instruction::StoreBCD(6));
+        assert_eq!(context.memory_map[0x300..0x304], [2, 5, 4, 0]);
+        assert_eq!(context.i_register, 0x300);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_store_register_range() {
+        let mut context = load([0xF2, 0x55]);
+        context.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+        context.i_register = 0x300;
+        run(&mut context, Instruction::StoreRegRange(2));
+        assert_eq!(context.memory_map[0x300..0x304], [1, 2, 3, 0]);
+        assert_eq!(context.i_register, 0x300);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_load_register_range() {
+        let mut context = load([0xF2, 0x65]);
+        context.memory_map[0x300..0x304].copy_from_slice(&[1, 2, 3, 4]);
+        context.i_register = 0x300;
+        run(&mut context, Instruction::LoadRegRange(2));
+        assert_eq!(context.registers[..4], [1, 2, 3, 0]);
+        assert_eq!(context.i_register, 0x300);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_store_flags() {
+        let mut context = load([0xF1, 0x75]);
+        context.registers[..3].copy_from_slice(&[1, 2, 3]);
+        run(&mut context, Instruction::StoreFlags(1));
+        assert_eq!(context.flags[..3], [1, 2, 0]);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_load_flags() {
+        let mut context = load([0xF1, 0x85]);
+        context.load_flags(&[1, 2, 3]);
+        run(&mut context, Instruction::LoadFlags(1));
+        assert_eq!(context.registers[..3], [1, 2, 0]);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_data() {
+        let mut context = load([0xFF, 0xFF]);
+        let registers = context.registers;
+        run(&mut context, Instruction::Data(0xFFFF));
+        assert_eq!(context.registers, registers);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_cycle_background() {
+        let mut context = load_variant([0x02, 0xA0], Variant::Chip8X);
+        context.background = 3;
+        run(&mut context, Instruction::CycleBackground);
+        assert_eq!(context.background, 0);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_add_nibbles() {
+        let mut context = load_variant([0x51, 0x21], Variant::Chip8X);
+        context.registers[0x1] = 0x75;
+        context.registers[0x2] = 0x36;
+        run(&mut context, Instruction::AddNibbles(1, 2));
+        assert_eq!(context.registers[0x1], 0x23);
+    }
+
+    #[test]
+    fn execute_set_color_zones() {
+        // Columns 1 - 3 and rows 0 - 2
+        let mut context = load_variant([0xB1, 0x30], Variant::Chip8X);
+        context.registers[0x1] = 0x31;
+        context.registers[0x2] = 0x20;
+        context.registers[0x3] = 5;
+        run(&mut context, Instruction::SetColorZones(1, 3));
+        assert_eq!(context.zone_colors[0][1..5], [5, 5, 5, DEFAULT_ZONE_COLOR]);
+        assert_eq!(context.zone_colors[2][3], 5);
+        assert_eq!(context.zone_colors[3][1], DEFAULT_ZONE_COLOR);
+    }
+
+    #[test]
+    fn execute_set_color_area() {
+        // An 8 by 4 sprite at 10, 6 covers columns 1 - 2 and rows 1 - 2
+        let mut context = load_variant([0xB1, 0x34], Variant::Chip8X);
+        context.registers[0x1] = 10;
+        context.registers[0x2] = 6;
+        context.registers[0x3] = 4;
+        run(&mut context, Instruction::SetColorArea(1, 3, 4));
+        assert_eq!(
+            context.zone_colors[1][0..4],
+            [DEFAULT_ZONE_COLOR, 4, 4, DEFAULT_ZONE_COLOR]
+        );
+        assert_eq!(context.zone_colors[2][2], 4);
+        assert_eq!(context.zone_colors[0][1], DEFAULT_ZONE_COLOR);
+        assert_eq!(context.zone_colors[3][1], DEFAULT_ZONE_COLOR);
+    }
+
+    #[test]
+    fn execute_skip_if_key_pressed_on_second_keypad() {
+        let mut context = load_variant([0xE1, 0xF2], Variant::Chip8X);
+        context.registers[0x1] = 0xA;
+        context.keyboard_input = Some(0xA);
+        run(&mut context, Instruction::SkipIfKeyPressed2(1));
+        assert_eq!(context.program_counter, 0x202);
+
+        let mut context = load_variant([0xE1, 0xF2], Variant::Chip8X);
+        context.registers[0x1] = 0xA;
+        context.second_keypad = Some(0xA);
+        run(&mut context, Instruction::SkipIfKeyPressed2(1));
+        assert_eq!(context.program_counter, 0x204);
+    }
+
+    #[test]
+    fn execute_output_and_wait_for_input() {
+        let mut context = load_variant([0xF1, 0xF8], Variant::Chip8X);
+        context.registers[0x1] = 0x42;
+        run(&mut context, Instruction::OutputTone(1));
+        assert_eq!(context.port_output, 0x42);
+
+        let mut context = load_variant([0xF1, 0xFB], Variant::Chip8X);
+        run(&mut context, Instruction::WaitForInput(1));
+        assert_eq!(context.program_counter, 0x200);
+        context.port_input = Some(0x17);
+        run(&mut context, Instruction::WaitForInput(1));
+        assert_eq!(context.registers[0x1], 0x17);
+        assert_eq!(context.port_input, None);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_stop() {
+        let mut context = load_variant([0x00, 0xED], Variant::Chip8E);
+        run(&mut context, Instruction::Stop);
+        assert_eq!(context.fault, Some(Fault::Stopped(0x200)));
+        assert_eq!(context.program_counter, 0x200);
+    }
+
+    #[test]
+    fn execute_wait_for_delay() {
+        let mut context = load_variant([0x01, 0x51], Variant::Chip8E);
+        context.delay_timer = 1;
+        run(&mut context, Instruction::WaitForDelay);
+        assert_eq!(context.program_counter, 0x200);
+        context.update_timers();
+        run(&mut context, Instruction::WaitForDelay);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_delay_for() {
+        let mut context = load_variant([0xF1, 0x4F], Variant::Chip8E);
+        context.registers[0x1] = 2;
+        run(&mut context, Instruction::DelayFor(1));
+        assert_eq!(context.delay_timer, 2);
+        context.update_timers();
+        run(&mut context, Instruction::DelayFor(1));
+        assert_eq!((context.delay_timer, context.program_counter), (1, 0x200));
+        context.update_timers();
+        run(&mut context, Instruction::DelayFor(1));
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_skip_next_and_skip_bytes() {
+        let mut context = load_variant([0x01, 0x88], Variant::Chip8E);
+        run(&mut context, Instruction::SkipNext);
+        assert_eq!(context.program_counter, 0x204);
+
+        let mut context = load_variant([0xF1, 0x1B], Variant::Chip8E);
+        context.registers[0x1] = 5;
+        run(&mut context, Instruction::SkipBytes(1));
+        assert_eq!(context.program_counter, 0x207);
+    }
+
+    #[test]
+    fn execute_skip_if_greater() {
+        for (vx, vy, pc) in [(3, 2, 0x204), (2, 2, 0x202), (1, 2, 0x202)] {
+            let mut context = load_variant([0x51, 0x21], Variant::Chip8E);
+            context.registers[0x1] = vx;
+            context.registers[0x2] = vy;
+            run(&mut context, Instruction::SkipIfGreater(1, 2));
+            assert_eq!(context.program_counter, pc);
+        }
+    }
+
+    #[test]
+    fn execute_store_and_load_range() {
+        let mut context = load_variant([0x52, 0x42], Variant::Chip8E);
+        context.registers[2..5].copy_from_slice(&[7, 8, 9]);
+        context.i_register = 0x300;
+        run(&mut context, Instruction::StoreRange(2, 4));
+        assert_eq!(context.memory_map[0x300..0x304], [7, 8, 9, 0]);
+        assert_eq!(context.i_register, 0x303);
+
+        let mut context = load_variant([0x56, 0x73], Variant::Chip8E);
+        context.memory_map[0x300..0x302].copy_from_slice(&[1, 2]);
+        context.i_register = 0x300;
+        run(&mut context, Instruction::LoadRange(6, 7));
+        assert_eq!(context.registers[5..9], [0, 1, 2, 0]);
+        assert_eq!(context.i_register, 0x302);
+    }
+
+    #[test]
+    fn execute_relative_jumps() {
+        let mut context = load_variant([0xBB, 0x10], Variant::Chip8E);
+        run(&mut context, Instruction::JumpBackward(0x10));
+        assert_eq!(context.program_counter, 0x1F0);
+
+        let mut context = load_variant([0xBF, 0x10], Variant::Chip8E);
+        run(&mut context, Instruction::JumpForward(0x10));
+        assert_eq!(context.program_counter, 0x210);
+    }
+
+    #[test]
+    fn execute_input() {
+        let mut context = load_variant([0xF1, 0xE7], Variant::Chip8E);
+        context.registers[0x1] = 0xFF;
+        run(&mut context, Instruction::Input(1));
+        assert_eq!(context.registers[0x1], 0);
+        assert_eq!(context.program_counter, 0x202);
+    }
+
+    #[test]
+    fn execute_set_long_i() {
+        let mut context = Context::new(&[0x01, 0x12, 0x34, 0x56], 1);
+        context.set_variant(Variant::MegaChip);
+        context.tick();
+        assert_eq!(context.i_register, 0x123456);
+        assert_eq!(context.program_counter, 0x204);
+    }
+
+    #[test]
+    fn execute_mega_chip_draw_and_clear() {
+        let mut context = load_variant([0x00, 0x11], Variant::MegaChip);
+        context.execute(Instruction::EnableMegaChip);
+        context.execute(Instruction::SetSpriteWidth(2));
+        context.execute(Instruction::SetSpriteHeight(1));
+        context.execute(Instruction::SetCollisionColor(5));
+        context.memory_map[0x10000..0x10002].copy_from_slice(&[5, 6]);
+        context.i_register = 0x10000;
+        context.registers[0x1] = 200;
+        context.registers[0x2] = 100;
+
+        context.execute(Instruction::Display(1, 2, 0));
+        assert_eq!(context.registers[0xF], 0);
+        context.execute(Instruction::Display(1, 2, 0));
+        assert_eq!(context.registers[0xF], 1);
+
+        let mega = context.mega_chip.as_ref().unwrap();
+        assert_eq!(mega.pixels[100 * 256 + 200..][..2], [5, 6]);
+        assert_eq!(mega.frame[100 * 256 + 200], [0, 0, 0, 0xFF]);
+        assert_eq!(context.get_flat_graphics_buffer(), [0; 256]);
+
+        context.execute(Instruction::ClearScreen);
+        let mega = context.mega_chip.as_ref().unwrap();
+        assert_eq!(
+            mega.frame[100 * 256 + 200..][..2],
+            [[5, 5, 5, 0xFF], [6, 6, 6, 0xFF]]
+        );
+        assert_eq!(mega.pixels[100 * 256 + 200], 0);
+    }
+
+    #[test]
+    fn execute_load_palette_and_sound() {
+        let mut context = load_variant([0x02, 0x01], Variant::MegaChip);
+        context.memory_map[0x300..0x304].copy_from_slice(&[0xFF, 0x11, 0x22, 0x33]);
+        context.i_register = 0x300;
+        context.execute(Instruction::LoadPalette(1));
+        let mega = context.mega_chip.as_ref().unwrap();
+        assert_eq!(mega.palette[1], [0x11, 0x22, 0x33, 0xFF]);
+
+        let sound = [0x1F, 0x40, 0x00, 0x00, 0x02, 0x80, 0x90];
+        context.memory_map[0x400..0x407].copy_from_slice(&sound);
+        context.i_register = 0x400;
+        context.execute(Instruction::PlaySound(1));
+        let played = context.mega_chip.as_ref().unwrap().sound.clone().unwrap();
+        assert_eq!((played.sample_rate, played.looped), (8000, false));
+        assert_eq!(*played.samples, [0x80, 0x90]);
+        context.execute(Instruction::StopSound);
+        assert_eq!(context.mega_chip.as_ref().unwrap().sound, None);
+    }
+
+    #[test]
+    fn execute_scroll_64x32_display() {
+        let mut context = load_variant([0x00, 0xFB], Variant::MegaChip);
+        context.graphics_buffer[0][0] = 0xFF;
+        context.graphics_buffer[0][7] = 0x0F;
+        context.execute(Instruction::ScrollRight);
+        assert_eq!(context.graphics_buffer[0], [0x0F, 0xF0, 0, 0, 0, 0, 0, 0]);
+        context.execute(Instruction::ScrollDown(3));
+        assert_eq!(context.graphics_buffer[0], [0; 8]);
+        assert_eq!(context.graphics_buffer[3][0..2], [0x0F, 0xF0]);
+        context.execute(Instruction::ScrollLeft);
+        context.execute(Instruction::ScrollUp(3));
+        assert_eq!(context.graphics_buffer[0][0], 0xFF);
+    }
+
+    #[test]
+    fn execute_exit() {
+        let mut context = load_variant([0x00, 0xFD], Variant::MegaChip);
+        run(&mut context, Instruction::Exit);
+        assert_eq!(context.fault, Some(Fault::Stopped(0x200)));
+    }
 }
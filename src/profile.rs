@@ -0,0 +1,246 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{
+    context::{Context, MEMORY_SIZE},
+    instructions::Instruction,
+};
+
+// Side of the square heatmap, one pixel per byte of memory
+pub const HEATMAP_SIZE: usize = 64;
+
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+pub struct Subroutine {
+    pub calls: u64,
+    // Cycles between the call and the return, including nested calls.
+    // Cycles are counted as one per instruction.
+    pub cycles: u64,
+}
+
+/**
+ *  Records what a running ROM does with memory: how many times each address
+ *  was executed, read as data by `Fx65` or read as a sprite by `Dxyn`, and
+ *  the cycles spent in every subroutine.
+ */
+pub struct Profiler {
+    pub executions: Vec<u64>,
+    pub data_reads: Vec<u64>,
+    pub sprite_reads: Vec<u64>,
+    pub subroutines: BTreeMap<u16, Subroutine>,
+    pub cycles: u64,
+    // Subroutines being run and the cycle they were called at
+    calls: Vec<(u16, u64)>,
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler {
+            executions: vec![0; MEMORY_SIZE],
+            data_reads: vec![0; MEMORY_SIZE],
+            sprite_reads: vec![0; MEMORY_SIZE],
+            subroutines: BTreeMap::new(),
+            cycles: 0,
+            calls: vec![],
+        }
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    // Must be called right before the context runs its next instruction
+    pub fn record(&mut self, context: &Context) {
+        let address = context.program_counter as usize;
+        let instruction = context.fetch();
+        let i = context.i_register as usize;
+
+        self.executions[address] += 1;
+        self.cycles += 1;
+        match instruction {
+            Instruction::LoadRegRange(x) => count(&mut self.data_reads, i, x as usize + 1),
+            Instruction::Display(_, _, n) => count(&mut self.sprite_reads, i, n as usize),
+            Instruction::Call(target) => {
+                self.subroutines.entry(target).or_default().calls += 1;
+                self.calls.push((target, self.cycles));
+            }
+            Instruction::Return => {
+                if let Some((entry, start)) = self.calls.pop() {
+                    let subroutine = self.subroutines.entry(entry).or_default();
+                    subroutine.cycles += self.cycles - start;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /**
+     *  Subroutines sorted by cycles, then every executed instruction and every
+     *  byte read as data or sprite with its counts:
+     *
+     *  ```text
+     *  ; 0x2A0  calls 12  cycles 3400
+     *  0x200  00E0  CLS              ; executed 1
+     *  0x22A  FF                     ; sprite 3
+     *  ```
+     */
+    pub fn annotated_disassembly(&self, context: &Context) -> String {
+        let mut report = String::new();
+        let mut subroutines = self.subroutines.iter().collect::<Vec<_>>();
+        subroutines.sort_by_key(|(_, subroutine)| std::cmp::Reverse(subroutine.cycles));
+        for (entry, subroutine) in subroutines {
+            let _ = writeln!(
+                report,
+                "; {entry:#05X}  calls {}  cycles {}",
+                subroutine.calls, subroutine.cycles
+            );
+        }
+
+        let mut address = 0;
+        while address < MEMORY_SIZE {
+            let executions = self.executions[address];
+            if executions > 0 && address + 1 < MEMORY_SIZE {
+                let instruction = context.fetch_at(address as u16);
+                let length = instruction.length().min(MEMORY_SIZE - address);
+                let bytes = &context.memory_map[address..address + length];
+                let hex = bytes
+                    .iter()
+                    .map(|byte| format!("{byte:02X}"))
+                    .collect::<String>();
+                let line = format!("{address:#05X}  {hex}  {instruction}");
+                let _ = writeln!(report, "{line:<30}; executed {executions}");
+                address += length;
+                continue;
+            }
+
+            let (data, sprite) = (self.data_reads[address], self.sprite_reads[address]);
+            if data > 0 || sprite > 0 {
+                let line = format!("{address:#05X}  {:02X}", context.memory_map[address]);
+                let mut counts = vec![];
+                if data > 0 {
+                    counts.push(format!("data {data}"));
+                }
+                if sprite > 0 {
+                    counts.push(format!("sprite {sprite}"));
+                }
+                let _ = writeln!(report, "{line:<30}; {}", counts.join(", "));
+            }
+            address += 1;
+        }
+        report
+    }
+
+    // RGBA image of memory, `HEATMAP_SIZE` bytes per row. Red is how often
+    // an address ran, green how often it was drawn as a sprite and blue how
+    // often it was read as data. Untouched memory is transparent.
+    pub fn heatmap(&self) -> Vec<u8> {
+        let mut image = Vec::with_capacity(MEMORY_SIZE * 4);
+        for address in 0..MEMORY_SIZE {
+            let red = heat(self.executions[address]);
+            let green = heat(self.sprite_reads[address]);
+            let blue = heat(self.data_reads[address]);
+            let alpha = if red | green | blue == 0 { 0x00 } else { 0xFF };
+            image.extend([red, green, blue, alpha]);
+        }
+        image
+    }
+}
+
+fn count(counts: &mut [u64], start: usize, length: usize) {
+    let end = (start + length).min(counts.len());
+    for count in &mut counts[start.min(end)..end] {
+        *count += 1;
+    }
+}
+
+// Brightness on a log scale, so rarely used addresses still show up
+fn heat(count: u64) -> u8 {
+    match count {
+        0 => 0,
+        _ => (0x40 + 0x18 * (count + 1).ilog2()).min(0xFF) as u8,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Profiler, Subroutine, HEATMAP_SIZE};
+    use crate::{context::Context, test_data::DATA};
+
+    fn profile(program: &[u8], ticks: usize) -> (Context, Profiler) {
+        let mut context = Context::new(program, 1);
+        let mut profiler = Profiler::new();
+        for _ in 0..ticks {
+            profiler.record(&context);
+            context.tick();
+        }
+        (context, profiler)
+    }
+
+    #[test]
+    fn count_executions_and_sprite_reads() {
+        let (_, profiler) = profile(&DATA, 30);
+
+        assert_eq!(profiler.executions[0x200], 1);
+        assert_eq!(profiler.executions[0x201], 0);
+        assert_eq!(profiler.executions[0x228], 10);
+        assert_eq!(profiler.sprite_reads[0x22A..0x22A + 15], [1; 15]);
+        assert_eq!(profiler.sprite_reads[0x22A + 15], 1);
+        assert_eq!(profiler.sprite_reads[0x229], 0);
+        assert_eq!(profiler.cycles, 30);
+    }
+
+    #[test]
+    fn count_cycles_per_subroutine() {
+        let program = [
+            0x22, 0x06, // 200: CALL 0x206
+            0x22, 0x06, // 202: CALL 0x206
+            0x12, 0x04, // 204: JP 0x204
+            0x60, 0x01, // 206: LD V0, 1
+            0x00, 0xEE, // 208: RET
+        ];
+        let (_, profiler) = profile(&program, 7);
+
+        assert_eq!(
+            profiler.subroutines[&0x206],
+            Subroutine {
+                calls: 2,
+                cycles: 4
+            }
+        );
+    }
+
+    #[test]
+    fn annotate_disassembly() {
+        // LD I, 0x208 ; LD V1, [I] ; JP 0x204 ; data
+        let (context, profiler) = profile(
+            &[0xA2, 0x08, 0xF1, 0x65, 0x12, 0x04, 0x00, 0x00, 0xAB, 0xCD],
+            4,
+        );
+        let report = profiler.annotated_disassembly(&context);
+        let lines = report.lines().collect::<Vec<&str>>();
+
+        assert_eq!(
+            lines,
+            [
+                "0x200  A208  LD I, 0x208      ; executed 1",
+                "0x202  F165  LD V1, [I]       ; executed 1",
+                "0x204  1204  JP 0x204         ; executed 2",
+                "0x208  AB                     ; data 1",
+                "0x209  CD                     ; data 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn draw_heatmap() {
+        let (_, profiler) = profile(&DATA, 30);
+        let heatmap = profiler.heatmap();
+        assert_eq!(heatmap.len(), HEATMAP_SIZE * HEATMAP_SIZE * 4);
+
+        let pixel = |address: usize| &heatmap[address * 4..address * 4 + 4];
+        assert_eq!(pixel(0x000), [0, 0, 0, 0]);
+        assert_eq!(pixel(0x200)[0], 0x58);
+        assert!(pixel(0x228)[0] > pixel(0x200)[0]);
+        assert_eq!(pixel(0x22A)[1], 0x58);
+    }
+}
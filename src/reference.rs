@@ -0,0 +1,477 @@
+use crate::context::{
+    Quirks, DEFAULT_STACK_DEPTH, FLAG_COUNT, FONT, FONT_ADDRESS, MEMORY_SIZE, PROGRAM_ADDRESS,
+    STACK_ADDRESS,
+};
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+/**
+ *  A CHIP-8 interpreter written to be easy to check against the spec rather
+ *  than to be fast. Opcodes are decoded from their nibbles without the parser
+ *  and the screen is one bool per pixel, so it shares no code with `Context`,
+ *  which is tested against it.
+ */
+#[derive(Clone, Debug)]
+pub struct Reference {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub stack: Vec<u16>,
+    pub stack_depth: usize,
+    pub memory: Vec<u8>,
+    pub screen: [[bool; WIDTH]; HEIGHT],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    // Key held down
+    pub key: Option<u8>,
+    pub flags: [u8; FLAG_COUNT],
+    pub quirks: Quirks,
+    // Stopped by a stack overflow or underflow
+    pub fault: bool,
+}
+
+impl Reference {
+    pub fn new(program: &[u8]) -> Reference {
+        let mut memory = vec![0; MEMORY_SIZE];
+        for (offset, byte) in program.iter().enumerate() {
+            if let Some(cell) = memory.get_mut(PROGRAM_ADDRESS as usize + offset) {
+                *cell = *byte;
+            }
+        }
+        for (offset, byte) in FONT.iter().enumerate() {
+            memory[FONT_ADDRESS as usize + offset] = *byte;
+        }
+
+        Reference {
+            v: [0; 16],
+            i: 0,
+            pc: PROGRAM_ADDRESS,
+            stack: vec![],
+            stack_depth: DEFAULT_STACK_DEPTH,
+            memory,
+            screen: [[false; WIDTH]; HEIGHT],
+            delay_timer: 0,
+            sound_timer: 0,
+            key: None,
+            flags: [0; FLAG_COUNT],
+            quirks: Quirks::default(),
+            fault: false,
+        }
+    }
+
+    // Opcode at the program counter. Addresses are 12 bits, so an opcode at
+    // 0xFFF ends at 0x000.
+    pub fn opcode(&self) -> u16 {
+        let high = self.memory[self.pc as usize % MEMORY_SIZE];
+        let low = self.memory[(self.pc as usize + 1) % MEMORY_SIZE];
+        u16::from_be_bytes([high, low])
+    }
+
+    /**
+     *  Runs one instruction. `random` is the byte `Cxkk` masks with kk, so
+     *  a run can be compared with an interpreter using any random number
+     *  generator. Memory past 0xFFF is neither read nor written.
+     */
+    pub fn step(&mut self, random: u8) {
+        if self.fault {
+            return;
+        }
+        let opcode = self.opcode();
+        let x = (opcode >> 8 & 0xF) as usize;
+        let y = (opcode >> 4 & 0xF) as usize;
+        let n = (opcode & 0xF) as usize;
+        let kk = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+
+        let next = self.pc + 2;
+        let skip_if = |condition: bool| if condition { next + 2 } else { next };
+        let mut pc = next;
+
+        match (opcode >> 12, x, y, n) {
+            // 00E0 CLS
+            (0x0, 0x0, 0xE, 0x0) => self.screen = [[false; WIDTH]; HEIGHT],
+            // 00EE RET, an empty stack is a fault
+            (0x0, 0x0, 0xE, 0xE) => match self.stack.pop() {
+                Some(address) if self.quirks.stack_in_memory => {
+                    let slot = self.stack_address(self.stack.len());
+                    pc = match (self.memory.get(slot), self.memory.get(slot + 1)) {
+                        (Some(&high), Some(&low)) => u16::from_be_bytes([high, low]),
+                        _ => address,
+                    };
+                }
+                Some(address) => pc = address,
+                None => {
+                    self.fault = true;
+                    return;
+                }
+            },
+            // 1nnn JP nnn
+            (0x1, _, _, _) => pc = nnn,
+            // 2nnn CALL nnn, a full stack is a fault
+            (0x2, _, _, _) => {
+                if self.stack.len() >= self.stack_depth {
+                    self.fault = true;
+                    return;
+                }
+                let address = next % MEMORY_SIZE as u16;
+                if self.quirks.stack_in_memory {
+                    let [high, low] = address.to_be_bytes();
+                    let slot = self.stack_address(self.stack.len());
+                    self.write(slot, high);
+                    self.write(slot + 1, low);
+                }
+                self.stack.push(address);
+                pc = nnn;
+            }
+            // 3xkk SE Vx, kk
+            (0x3, _, _, _) => pc = skip_if(self.v[x] == kk),
+            // 4xkk SNE Vx, kk
+            (0x4, _, _, _) => pc = skip_if(self.v[x] != kk),
+            // 5xy0 SE Vx, Vy
+            (0x5, _, _, 0x0) => pc = skip_if(self.v[x] == self.v[y]),
+            // 6xkk LD Vx, kk
+            (0x6, _, _, _) => self.v[x] = kk,
+            // 7xkk ADD Vx, kk, VF is left alone
+            (0x7, _, _, _) => self.v[x] = self.v[x].wrapping_add(kk),
+            // 8xy0 LD Vx, Vy
+            (0x8, _, _, 0x0) => self.v[x] = self.v[y],
+            // 8xy1 OR, 8xy2 AND, 8xy3 XOR
+            (0x8, _, _, 0x1..=0x3) => {
+                self.v[x] = match n {
+                    0x1 => self.v[x] | self.v[y],
+                    0x2 => self.v[x] & self.v[y],
+                    _ => self.v[x] ^ self.v[y],
+                };
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0;
+                }
+            }
+            // The flag of arithmetic is always written after the result
+            // 8xy4 ADD Vx, Vy, VF is the carry
+            (0x8, _, _, 0x4) => {
+                let (sum, carry) = self.v[x].overflowing_add(self.v[y]);
+                self.v[x] = sum;
+                self.v[0xF] = carry as u8;
+            }
+            // 8xy5 SUB Vx, Vy, VF is 1 when there's no borrow
+            (0x8, _, _, 0x5) => {
+                let no_borrow = self.v[x] >= self.v[y];
+                self.v[x] = self.v[x].wrapping_sub(self.v[y]);
+                self.v[0xF] = no_borrow as u8;
+            }
+            // 8xy6 SHR Vx {, Vy}, VF is the bit shifted out
+            (0x8, _, _, 0x6) => {
+                let source = self.shift_source(x, y);
+                self.v[x] = source >> 1;
+                self.v[0xF] = source & 0x1;
+            }
+            // 8xy7 SUBN Vx, Vy, VF is 1 when there's no borrow
+            (0x8, _, _, 0x7) => {
+                let no_borrow = self.v[y] >= self.v[x];
+                self.v[x] = self.v[y].wrapping_sub(self.v[x]);
+                self.v[0xF] = no_borrow as u8;
+            }
+            // 8xyE SHL Vx {, Vy}, VF is the bit shifted out
+            (0x8, _, _, 0xE) => {
+                let source = self.shift_source(x, y);
+                self.v[x] = source << 1;
+                self.v[0xF] = source >> 7;
+            }
+            // 9xy0 SNE Vx, Vy
+            (0x9, _, _, 0x0) => pc = skip_if(self.v[x] != self.v[y]),
+            // Annn LD I, nnn
+            (0xA, _, _, _) => self.i = nnn,
+            // Bnnn JP V0, nnn (or JP Vx, nnn)
+            (0xB, _, _, _) => {
+                let offset = if self.quirks.jump_uses_vx {
+                    self.v[x]
+                } else {
+                    self.v[0]
+                };
+                pc = nnn + offset as u16;
+            }
+            // Cxkk RND Vx, kk
+            (0xC, _, _, _) => self.v[x] = random & kk,
+            // Dxyn DRW Vx, Vy, n, wrapping around the edges of the screen
+            (0xD, _, _, _) => {
+                let mut erased = false;
+                for row in 0..n {
+                    let Some(&byte) = self.memory.get(self.i as usize + row) else {
+                        break;
+                    };
+                    for column in 0..8 {
+                        if byte & (0x80 >> column) != 0 {
+                            let screen_y = (self.v[y] as usize + row) % HEIGHT;
+                            let screen_x = (self.v[x] as usize + column) % WIDTH;
+                            let pixel = &mut self.screen[screen_y][screen_x];
+                            erased |= *pixel;
+                            *pixel = !*pixel;
+                        }
+                    }
+                }
+                self.v[0xF] = erased as u8;
+            }
+            // Ex9E SKP Vx
+            (0xE, _, 0x9, 0xE) => pc = skip_if(self.key == Some(self.v[x])),
+            // ExA1 SKNP Vx
+            (0xE, _, 0xA, 0x1) => pc = skip_if(self.key != Some(self.v[x])),
+            // Fx07 LD Vx, DT
+            (0xF, _, 0x0, 0x7) => self.v[x] = self.delay_timer,
+            // Fx0A LD Vx, K, runs again until a key is down
+            (0xF, _, 0x0, 0xA) => match self.key {
+                Some(key) => self.v[x] = key,
+                None => pc = self.pc,
+            },
+            // Fx15 LD DT, Vx
+            (0xF, _, 0x1, 0x5) => self.delay_timer = self.v[x],
+            // Fx18 LD ST, Vx
+            (0xF, _, 0x1, 0x8) => self.sound_timer = self.v[x],
+            // Fx1E ADD I, Vx
+            (0xF, _, 0x1, 0xE) => self.i = self.i.wrapping_add(self.v[x] as u16),
+            // Fx29 LD F, Vx
+            (0xF, _, 0x2, 0x9) => self.i = FONT_ADDRESS + 5 * (self.v[x] & 0xF) as u16,
+            // Fx33 LD B, Vx
+            (0xF, _, 0x3, 0x3) => {
+                let digits = [self.v[x] / 100, self.v[x] / 10 % 10, self.v[x] % 10];
+                for (offset, digit) in digits.into_iter().enumerate() {
+                    self.write(self.i as usize + offset, digit);
+                }
+            }
+            // Fx55 LD [I], Vx
+            (0xF, _, 0x5, 0x5) => {
+                for register in 0..=x {
+                    self.write(self.i as usize + register, self.v[register]);
+                }
+                self.increment_i(x);
+            }
+            // Fx65 LD Vx, [I]
+            (0xF, _, 0x6, 0x5) => {
+                for register in 0..=x {
+                    if let Some(&byte) = self.memory.get(self.i as usize + register) {
+                        self.v[register] = byte;
+                    }
+                }
+                self.increment_i(x);
+            }
+            // Fx75 LD R, Vx
+            (0xF, _, 0x7, 0x5) => self.flags[..=x].copy_from_slice(&self.v[..=x]),
+            // Fx85 LD Vx, R
+            (0xF, _, 0x8, 0x5) => self.v[..=x].copy_from_slice(&self.flags[..=x]),
+            // 0nnn SYS and unknown opcodes do nothing
+            _ => {}
+        }
+
+        self.pc = pc % MEMORY_SIZE as u16;
+    }
+
+    fn shift_source(&self, x: usize, y: usize) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.v[y]
+        } else {
+            self.v[x]
+        }
+    }
+
+    fn increment_i(&mut self, x: usize) {
+        if self.quirks.load_store_increments_i {
+            self.i = self.i.wrapping_add(x as u16 + 1);
+        }
+    }
+
+    fn stack_address(&self, depth: usize) -> usize {
+        STACK_ADDRESS as usize + 2 * depth
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        if let Some(byte) = self.memory.get_mut(address) {
+            *byte = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{Reference, WIDTH};
+    use crate::{
+        context::{Context, Quirks, DEFAULT_STACK_DEPTH, MEMORY_SIZE, PROGRAM_ADDRESS},
+        instructions::Instruction,
+        test_data::DATA,
+    };
+
+    // Instructions run and compared for each generated program
+    const STEPS: usize = 64;
+    // Longest generated program, in instructions
+    const MAX_LENGTH: usize = 48;
+
+    fn screen(context: &Context) -> Vec<Vec<bool>> {
+        context
+            .graphics_buffer
+            .iter()
+            .map(|row| {
+                (0..WIDTH)
+                    .map(|x| row[x / 8] & (0x80 >> (x % 8)) != 0)
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Compares everything a program can observe
+    fn compare(context: &Context, reference: &Reference) -> Result<(), TestCaseError> {
+        prop_assert_eq!(context.registers, reference.v);
+        prop_assert_eq!(context.i_register, reference.i as u32);
+        prop_assert_eq!(context.program_counter, reference.pc);
+        prop_assert_eq!(&context.stack_pointer, &reference.stack);
+        prop_assert_eq!(context.fault.is_some(), reference.fault);
+        prop_assert_eq!(context.delay_timer, reference.delay_timer);
+        prop_assert_eq!(context.sound_timer, reference.sound_timer);
+        prop_assert_eq!(context.flags, reference.flags);
+        prop_assert!(context.memory_map == reference.memory, "memory differs");
+        prop_assert_eq!(screen(context), reference.screen.map(Vec::from).to_vec());
+        Ok(())
+    }
+
+    // Random instructions, with jumps and calls kept inside the program
+    fn program() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(any::<u16>(), 1..MAX_LENGTH).prop_map(|words| {
+            let length = words.len() as u16;
+            words
+                .iter()
+                .flat_map(|&word| {
+                    let word = match word >> 12 {
+                        0x1 | 0x2 => word & 0xF000 | (PROGRAM_ADDRESS + word % length * 2),
+                        _ => word,
+                    };
+                    word.to_be_bytes()
+                })
+                .collect()
+        })
+    }
+
+    #[derive(Debug, Clone)]
+    struct State {
+        registers: [u8; 16],
+        i: u16,
+        // Return addresses in the program
+        stack: Vec<u16>,
+        stack_depth: usize,
+        timers: (u8, u8),
+        key: Option<u8>,
+        quirks: [bool; 5],
+        seed: u64,
+    }
+
+    fn state() -> impl Strategy<Value = State> {
+        (
+            any::<[u8; 16]>(),
+            prop_oneof![0..MEMORY_SIZE as u16, any::<u16>()],
+            prop::collection::vec(0..MAX_LENGTH as u16, 0..=DEFAULT_STACK_DEPTH),
+            0..=DEFAULT_STACK_DEPTH,
+            any::<(u8, u8)>(),
+            prop::option::of(0..16u8),
+            any::<[bool; 5]>(),
+            any::<u64>(),
+        )
+            .prop_map(
+                |(registers, i, stack, room, timers, key, quirks, seed)| State {
+                    registers,
+                    i,
+                    stack_depth: (stack.len() + room).min(DEFAULT_STACK_DEPTH),
+                    stack: stack
+                        .into_iter()
+                        .map(|index| PROGRAM_ADDRESS + 2 * index)
+                        .collect(),
+                    timers,
+                    key,
+                    quirks,
+                    seed,
+                },
+            )
+    }
+
+    fn start(program: &[u8], state: &State) -> (Context, Reference) {
+        let [shift_uses_vy, load_store_increments_i, jump_uses_vx, logic_resets_vf, stack_in_memory] =
+            state.quirks;
+        let quirks = Quirks {
+            shift_uses_vy,
+            load_store_increments_i,
+            jump_uses_vx,
+            logic_resets_vf,
+            stack_in_memory,
+        };
+
+        let mut context = Context::new(program, state.seed);
+        context.registers = state.registers;
+        context.i_register = state.i.into();
+        context.stack_pointer = state.stack.clone();
+        context.stack_depth = state.stack_depth;
+        (context.delay_timer, context.sound_timer) = state.timers;
+        context.keyboard_input = state.key;
+        context.quirks = quirks;
+
+        let mut reference = Reference::new(program);
+        reference.v = state.registers;
+        reference.i = state.i;
+        reference.stack = state.stack.clone();
+        reference.stack_depth = state.stack_depth;
+        (reference.delay_timer, reference.sound_timer) = state.timers;
+        reference.key = state.key;
+        reference.quirks = quirks;
+        (context, reference)
+    }
+
+    // Runs both interpreters one instruction at a time
+    fn run(
+        context: &mut Context,
+        reference: &mut Reference,
+        steps: usize,
+    ) -> Result<(), TestCaseError> {
+        for _ in 0..steps {
+            let instruction = context.tick();
+            let random = match instruction {
+                Instruction::SetRandom(x, _) => context.registers[x as usize],
+                _ => 0,
+            };
+            reference.step(random);
+            compare(context, reference)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn draw_ibm_logo_like_reference() {
+        let (mut context, mut reference) = start(
+            &DATA,
+            &State {
+                registers: [0; 16],
+                i: 0,
+                stack: vec![],
+                stack_depth: DEFAULT_STACK_DEPTH,
+                timers: (0, 0),
+                key: None,
+                quirks: [false; 5],
+                seed: 1,
+            },
+        );
+        run(&mut context, &mut reference, 30).unwrap();
+        assert!(
+            reference
+                .screen
+                .iter()
+                .flatten()
+                .filter(|pixel| **pixel)
+                .count()
+                > 100
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn run_like_reference(program in program(), state in state()) {
+            let (mut context, mut reference) = start(&program, &state);
+            run(&mut context, &mut reference, STEPS)?;
+        }
+    }
+}
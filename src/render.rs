@@ -0,0 +1,231 @@
+use crate::{context::COLOR_ZONES, filter::PersistenceFilter, palette::Palette};
+
+// Size of a CHIP-8 pixel on screen and in captures
+pub const DEFAULT_SCALE: usize = 10;
+
+// RGBA pixels of a frame as shown to the user, with the display filter applied
+pub fn render_frame(
+    buffer: &[u8],
+    filter: Option<&mut PersistenceFilter>,
+    palette: &Palette,
+) -> Vec<u8> {
+    match filter {
+        Some(filter) => convert_levels(&filter.apply(buffer), palette),
+        None => convert_graphics_buffer(buffer, palette),
+    }
+}
+
+// Nearest neighbor upscaling of RGBA pixels, so every pixel becomes a
+// `scale` x `scale` square
+pub fn scale_frame(frame: &[u8], width: usize, scale: usize) -> Vec<u8> {
+    let mut result = Vec::with_capacity(frame.len() * scale * scale);
+    for row in frame.chunks(width * 4) {
+        let scaled_row = row
+            .chunks(4)
+            .flat_map(|pixel| pixel.repeat(scale))
+            .collect::<Vec<u8>>();
+        for _ in 0..scale {
+            result.extend(&scaled_row);
+        }
+    }
+    result
+}
+
+// Expands the 1-bit graphics buffer into RGBA pixels, one byte per channel
+pub fn convert_graphics_buffer(buffer: &[u8], palette: &Palette) -> Vec<u8> {
+    convert_bitplanes(&[buffer], palette)
+}
+
+// Combines up to two 1-bit planes of the same size into RGBA pixels. The bit
+// of plane `n` is bit `n` of the palette index, so a pixel lit in both planes
+// of an XO-CHIP display uses the fourth color.
+pub fn convert_bitplanes(planes: &[&[u8]], palette: &Palette) -> Vec<u8> {
+    let length = planes.first().map_or(0, |plane| plane.len());
+    let mut result = Vec::with_capacity(length * 8 * 4);
+
+    for byte in 0..length {
+        for bit in (0..8).rev() {
+            let index = planes.iter().enumerate().fold(0, |index, (plane, bits)| {
+                index | (((bits[byte] >> bit) & 1) as usize) << plane
+            });
+            result.extend(palette.colors[index]);
+        }
+    }
+
+    result
+}
+
+// CHIP-8X background colors: blue, black, green and red
+pub const BACKGROUND_COLORS: [[u8; 4]; 4] = [
+    [0x00, 0x00, 0x80, 0xFF],
+    [0x00, 0x00, 0x00, 0xFF],
+    [0x00, 0x80, 0x00, 0xFF],
+    [0x80, 0x00, 0x00, 0xFF],
+];
+
+// Foreground colors of the VP-590 color board: black, red, blue, violet,
+// green, yellow, aqua and white
+pub const ZONE_COLORS: [[u8; 4]; 8] = [
+    [0x00, 0x00, 0x00, 0xFF],
+    [0xFF, 0x00, 0x00, 0xFF],
+    [0x00, 0x00, 0xFF, 0xFF],
+    [0xFF, 0x00, 0xFF, 0xFF],
+    [0x00, 0xFF, 0x00, 0xFF],
+    [0xFF, 0xFF, 0x00, 0xFF],
+    [0x00, 0xFF, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xFF, 0xFF],
+];
+
+// Expands a CHIP-8X display into RGBA pixels, lit pixels take the color of
+// their 8x4 zone
+pub fn convert_color_zones(
+    buffer: &[u8],
+    background: u8,
+    zones: &[[u8; COLOR_ZONES]; COLOR_ZONES],
+) -> Vec<u8> {
+    let mut result = Vec::with_capacity(buffer.len() * 8 * 4);
+    for (index, byte) in buffer.iter().enumerate() {
+        let (row, column) = (index / 8, index % 8);
+        let zone = zones[row / 4 % COLOR_ZONES][column];
+        for bit in (0..8).rev() {
+            if byte >> bit & 1 == 1 {
+                result.extend(ZONE_COLORS[zone as usize % ZONE_COLORS.len()]);
+            } else {
+                result.extend(BACKGROUND_COLORS[background as usize % BACKGROUND_COLORS.len()]);
+            }
+        }
+    }
+    result
+}
+
+// Flattens MEGA-CHIP colors into RGBA pixels, faded towards black by the
+// screen alpha
+pub fn convert_faded(colors: &[[u8; 4]], alpha: u8) -> Vec<u8> {
+    let fade = |channel: u8| (channel as u16 * alpha as u16 / 0xFF) as u8;
+    colors
+        .iter()
+        .flat_map(|[r, g, b, _]| [fade(*r), fade(*g), fade(*b), 0xFF])
+        .collect()
+}
+
+// Adds `rows` rows of `color` above and below RGBA pixels, to show a frame
+// in a taller one
+pub fn pad_frame(frame: &[u8], width: usize, rows: usize, color: [u8; 4]) -> Vec<u8> {
+    let padding = color.repeat(width * rows);
+    [padding.as_slice(), frame, padding.as_slice()].concat()
+}
+
+// Converts per pixel brightness, like the output of a `PersistenceFilter`,
+// into RGBA pixels blended between the background and foreground colors.
+pub fn convert_levels(levels: &[u8], palette: &Palette) -> Vec<u8> {
+    let background = palette.background();
+    let foreground = palette.foreground();
+
+    levels
+        .iter()
+        .flat_map(|level| {
+            let level = *level as u16;
+            let mut color = [0; 4];
+            for channel in 0..4 {
+                let from = background[channel] as u16;
+                let to = foreground[channel] as u16;
+                color[channel] = ((from * (0xFF - level) + to * level) / 0xFF) as u8;
+            }
+            color
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        convert_bitplanes, convert_color_zones, convert_faded, convert_graphics_buffer,
+        convert_levels, pad_frame, scale_frame, BACKGROUND_COLORS, ZONE_COLORS,
+    };
+    use crate::{context::COLOR_ZONES, palette::Palette};
+
+    #[test]
+    fn convert_bits_to_palette_colors() {
+        let palette = Palette::default();
+        let result = convert_graphics_buffer(&[0b1000_0001], &palette);
+
+        assert_eq!(result.len(), 8 * 4);
+        assert_eq!(result[0..4], palette.foreground());
+        assert_eq!(result[4..8], palette.background());
+        assert_eq!(result[28..32], palette.foreground());
+    }
+
+    #[test]
+    fn convert_bitplanes_to_four_colors() {
+        let palette = Palette::named("octo").unwrap();
+        let result = convert_bitplanes(&[&[0b1010_0000], &[0b1100_0000]], &palette);
+
+        let pixels = result.chunks(4).take(4).collect::<Vec<&[u8]>>();
+        assert_eq!(
+            pixels,
+            [
+                palette.colors[3],
+                palette.colors[2],
+                palette.colors[1],
+                palette.colors[0]
+            ]
+        );
+    }
+
+    #[test]
+    fn color_pixels_by_zone() {
+        let mut buffer = [0; 256];
+        // Row 4 is in the second row of zones, byte 1 in the second column
+        buffer[4 * 8 + 1] = 0b1000_0000;
+        let mut zones = [[1; COLOR_ZONES]; COLOR_ZONES];
+        zones[1][1] = 6;
+        let result = convert_color_zones(&buffer, 2, &zones);
+
+        let pixel = |x: usize, y: usize| &result[(y * 64 + x) * 4..][..4];
+        assert_eq!(pixel(8, 4), ZONE_COLORS[6]);
+        assert_eq!(pixel(9, 4), BACKGROUND_COLORS[2]);
+        assert_eq!(pixel(0, 0), BACKGROUND_COLORS[2]);
+    }
+
+    #[test]
+    fn fade_colors_with_alpha() {
+        let colors = [[0x00, 0x00, 0x00, 0xFF], [0x80, 0x40, 0x20, 0xFF]];
+        let result = convert_faded(&colors, 0x80);
+
+        assert_eq!(result[0..4], [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(result[4..8], [0x40, 0x20, 0x10, 0xFF]);
+    }
+
+    #[test]
+    fn pad_above_and_below() {
+        let result = pad_frame(&[1; 8], 2, 1, [9; 4]);
+        assert_eq!(result.len(), 3 * 8);
+        assert_eq!(result[0..8], [9; 8]);
+        assert_eq!(result[8..16], [1; 8]);
+        assert_eq!(result[16..24], [9; 8]);
+    }
+
+    #[test]
+    fn blend_levels_between_background_and_foreground() {
+        let palette = Palette::parse("#000000,#FF8040").unwrap();
+        let result = convert_levels(&[0x00, 0x80, 0xFF], &palette);
+
+        assert_eq!(result[0..4], [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(result[4..8], [0x80, 0x40, 0x20, 0xFF]);
+        assert_eq!(result[8..12], [0xFF, 0x80, 0x40, 0xFF]);
+    }
+
+    #[test]
+    fn scale_frame_pixels() {
+        let frame = [1, 1, 1, 1, 2, 2, 2, 2];
+        let result = scale_frame(&frame, 2, 2);
+
+        assert_eq!(
+            result,
+            [
+                1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, //
+                1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2,
+            ]
+        );
+    }
+}
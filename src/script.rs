@@ -0,0 +1,400 @@
+use std::{cell::RefCell, fmt, fs, mem, path::Path, rc::Rc};
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, FuncArgs, ParseError, AST};
+
+use crate::{
+    capture::save_png,
+    context::{Context, MEMORY_SIZE},
+    instructions::Instruction,
+    palette::Palette,
+    render::{render_frame, scale_frame},
+};
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct ScriptError(pub String);
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<ParseError> for ScriptError {
+    fn from(error: ParseError) -> ScriptError {
+        ScriptError(error.to_string())
+    }
+}
+
+impl From<Box<EvalAltResult>> for ScriptError {
+    fn from(error: Box<EvalAltResult>) -> ScriptError {
+        ScriptError(error.to_string())
+    }
+}
+
+#[derive(Default)]
+struct Hooks {
+    frame: Vec<FnPtr>,
+    exec: Vec<(u16, FnPtr)>,
+    write: Vec<(u16, FnPtr)>,
+}
+
+// What the script functions work on
+struct Machine {
+    // The context of the frontend is swapped in while the script runs
+    context: Context,
+    // Key held down by the script, it overrides the keyboard
+    key: Option<u8>,
+    frame: u64,
+    palette: Palette,
+    scale: usize,
+}
+
+/**
+ *  A Rhai script driving the emulator. Scripts register callbacks and call
+ *  these functions, numbers being Rhai integers:
+ *  - `on_frame(f)`: calls `f()` at the end of every frame
+ *  - `on_exec(address, f)`: calls `f(address)` before running the
+ *    instruction at `address`
+ *  - `on_write(address, f)`: calls `f(address, value)` after an instruction
+ *    writes to `address`
+ *  - `register(x)`, `set_register(x, value)`, `memory(address)`,
+ *    `set_memory(address, value)`, `i()`, `set_i(value)`, `pc()`,
+ *    `set_pc(value)`, `delay_timer()`, `sound_timer()`
+ *  - `press(key)` holds a keypad key until `release()`
+ *  - `screenshot(path)` saves the display to a PNG file
+ *  - `frame()` is the number of frames run so far
+ *
+ *  Frontends call `tick` instead of `Context::tick` and `end_frame` after
+ *  every frame.
+ */
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    hooks: Rc<RefCell<Hooks>>,
+    machine: Rc<RefCell<Machine>>,
+}
+
+impl Script {
+    pub fn load(
+        path: &Path,
+        context: &mut Context,
+        palette: &Palette,
+        scale: usize,
+    ) -> Result<Script, ScriptError> {
+        let source = fs::read_to_string(path).map_err(|error| ScriptError(error.to_string()))?;
+        Script::compile(&source, context, palette, scale)
+    }
+
+    // Compiles the script and runs its top level statements, which usually
+    // register the callbacks
+    pub fn compile(
+        source: &str,
+        context: &mut Context,
+        palette: &Palette,
+        scale: usize,
+    ) -> Result<Script, ScriptError> {
+        let hooks = Rc::new(RefCell::new(Hooks::default()));
+        let machine = Rc::new(RefCell::new(Machine {
+            context: Context::new(&[], 0),
+            key: None,
+            frame: 0,
+            palette: palette.clone(),
+            scale,
+        }));
+
+        let mut engine = Engine::new();
+        register_hooks(&mut engine, &hooks);
+        register_functions(&mut engine, &machine);
+        let ast = engine.compile(source)?;
+
+        let script = Script {
+            engine,
+            ast,
+            hooks,
+            machine,
+        };
+        script.with_context(context, |script| {
+            script
+                .engine
+                .run_ast(&script.ast)
+                .map_err(ScriptError::from)
+        })?;
+        Ok(script)
+    }
+
+    // Runs one instruction like `Context::tick`, calling the hooks around it
+    pub fn tick(&mut self, context: &mut Context) -> Result<Instruction, ScriptError> {
+        if let Some(key) = self.machine.borrow().key {
+            context.keyboard_input = Some(key);
+        }
+
+        let address = context.program_counter;
+        let exec = self.hooks_at(&self.hooks.borrow().exec, address);
+        for hook in exec {
+            self.call(context, &hook, (address as i64,))?;
+        }
+
+        let instruction = context.fetch();
+        let written = context.memory_written(&instruction);
+        context.execute(instruction);
+
+        if let Some(range) = written {
+            let write = self.hooks.borrow().write.clone();
+            for (address, hook) in write {
+                if range.contains(&(address as usize)) {
+                    let value = context.memory_map[address as usize];
+                    self.call(context, &hook, (address as i64, value as i64))?;
+                }
+            }
+        }
+        Ok(instruction)
+    }
+
+    pub fn end_frame(&mut self, context: &mut Context) -> Result<(), ScriptError> {
+        self.machine.borrow_mut().frame += 1;
+        let frame = self.hooks.borrow().frame.clone();
+        for hook in frame {
+            self.call(context, &hook, ())?;
+        }
+        Ok(())
+    }
+
+    fn hooks_at(&self, hooks: &[(u16, FnPtr)], address: u16) -> Vec<FnPtr> {
+        hooks
+            .iter()
+            .filter(|(hook_address, _)| *hook_address == address)
+            .map(|(_, hook)| hook.clone())
+            .collect()
+    }
+
+    fn call(
+        &self,
+        context: &mut Context,
+        hook: &FnPtr,
+        args: impl FuncArgs,
+    ) -> Result<(), ScriptError> {
+        self.with_context(context, |script| {
+            hook.call::<Dynamic>(&script.engine, &script.ast, args)
+                .map(|_| ())
+                .map_err(ScriptError::from)
+        })
+    }
+
+    // Lends `context` to the script functions while `run` runs
+    fn with_context<T>(&self, context: &mut Context, run: impl FnOnce(&Script) -> T) -> T {
+        mem::swap(context, &mut self.machine.borrow_mut().context);
+        let result = run(self);
+        mem::swap(context, &mut self.machine.borrow_mut().context);
+        result
+    }
+}
+
+fn register_hooks(engine: &mut Engine, hooks: &Rc<RefCell<Hooks>>) {
+    let frame = hooks.clone();
+    engine.register_fn("on_frame", move |hook: FnPtr| {
+        frame.borrow_mut().frame.push(hook);
+    });
+    let exec = hooks.clone();
+    engine.register_fn(
+        "on_exec",
+        move |address: i64, hook: FnPtr| -> Result<(), Box<EvalAltResult>> {
+            exec.borrow_mut().exec.push((address_arg(address)?, hook));
+            Ok(())
+        },
+    );
+    let write = hooks.clone();
+    engine.register_fn(
+        "on_write",
+        move |address: i64, hook: FnPtr| -> Result<(), Box<EvalAltResult>> {
+            write.borrow_mut().write.push((address_arg(address)?, hook));
+            Ok(())
+        },
+    );
+}
+
+fn register_functions(engine: &mut Engine, machine: &Rc<RefCell<Machine>>) {
+    let m = machine.clone();
+    engine.register_fn(
+        "register",
+        move |x: i64| -> Result<i64, Box<EvalAltResult>> {
+            Ok(m.borrow().context.registers[register_arg(x)?] as i64)
+        },
+    );
+    let m = machine.clone();
+    engine.register_fn(
+        "set_register",
+        move |x: i64, value: i64| -> Result<(), Box<EvalAltResult>> {
+            m.borrow_mut().context.registers[register_arg(x)?] = byte_arg(value)?;
+            Ok(())
+        },
+    );
+    let m = machine.clone();
+    engine.register_fn(
+        "memory",
+        move |address: i64| -> Result<i64, Box<EvalAltResult>> {
+            Ok(m.borrow().context.memory_map[address_arg(address)? as usize] as i64)
+        },
+    );
+    let m = machine.clone();
+    engine.register_fn(
+        "set_memory",
+        move |address: i64, value: i64| -> Result<(), Box<EvalAltResult>> {
+            m.borrow_mut().context.memory_map[address_arg(address)? as usize] = byte_arg(value)?;
+            Ok(())
+        },
+    );
+    let m = machine.clone();
+    engine.register_fn("i", move || m.borrow().context.i_register as i64);
+    let m = machine.clone();
+    engine.register_fn(
+        "set_i",
+        move |value: i64| -> Result<(), Box<EvalAltResult>> {
+            m.borrow_mut().context.i_register = address_arg(value)?.into();
+            Ok(())
+        },
+    );
+    let m = machine.clone();
+    engine.register_fn("pc", move || m.borrow().context.program_counter as i64);
+    let m = machine.clone();
+    engine.register_fn(
+        "set_pc",
+        move |value: i64| -> Result<(), Box<EvalAltResult>> {
+            m.borrow_mut().context.program_counter = address_arg(value)?;
+            Ok(())
+        },
+    );
+    let m = machine.clone();
+    engine.register_fn("delay_timer", move || m.borrow().context.delay_timer as i64);
+    let m = machine.clone();
+    engine.register_fn("sound_timer", move || m.borrow().context.sound_timer as i64);
+
+    let m = machine.clone();
+    engine.register_fn("press", move |key: i64| -> Result<(), Box<EvalAltResult>> {
+        m.borrow_mut().key = Some(register_arg(key)? as u8);
+        Ok(())
+    });
+    let m = machine.clone();
+    engine.register_fn("release", move || {
+        let mut machine = m.borrow_mut();
+        machine.key = None;
+        machine.context.keyboard_input = None;
+    });
+    let m = machine.clone();
+    engine.register_fn("frame", move || m.borrow().frame as i64);
+
+    let m = machine.clone();
+    engine.register_fn(
+        "screenshot",
+        move |path: &str| -> Result<(), Box<EvalAltResult>> {
+            let machine = m.borrow();
+            let buffer = machine.context.get_flat_graphics_buffer();
+            let frame = render_frame(&buffer, None, &machine.palette);
+            let width = machine.context.graphics_buffer[0].len() * 8;
+            let height = machine.context.graphics_buffer.len();
+            let scale = machine.scale;
+            save_png(
+                Path::new(path),
+                &scale_frame(&frame, width, scale),
+                width * scale,
+                height * scale,
+            )
+            .map_err(|error| error.to_string().into())
+        },
+    );
+}
+
+fn register_arg(x: i64) -> Result<usize, Box<EvalAltResult>> {
+    match x {
+        0..=0xF => Ok(x as usize),
+        _ => Err(format!("{x} isn't a register or key (0 - 15)").into()),
+    }
+}
+
+fn address_arg(address: i64) -> Result<u16, Box<EvalAltResult>> {
+    match address {
+        0.. if (address as usize) < MEMORY_SIZE => Ok(address as u16),
+        _ => Err(format!("{address} is out of memory").into()),
+    }
+}
+
+fn byte_arg(value: i64) -> Result<u8, Box<EvalAltResult>> {
+    u8::try_from(value).map_err(|_| format!("{value} doesn't fit in a byte").into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Script;
+    use crate::{context::Context, palette::Palette};
+
+    // LD V0, 5 ; LD V1, 7 ; LD I, 0x300 ; LD [I], V1 ; JP 0x208
+    const PROGRAM: [u8; 10] = [0x60, 0x05, 0x61, 0x07, 0xA3, 0x00, 0xF1, 0x55, 0x12, 0x08];
+
+    fn compile(source: &str, context: &mut Context) -> Script {
+        Script::compile(source, context, &Palette::default(), 1).unwrap()
+    }
+
+    #[test]
+    fn call_exec_and_write_hooks() {
+        let mut context = Context::new(&PROGRAM, 1);
+        let mut script = compile(
+            "
+            on_exec(0x202, |address| set_register(0, register(0) + 1));
+            on_write(0x301, |address, value| set_memory(0x400, value * 2));
+            ",
+            &mut context,
+        );
+
+        for _ in 0..4 {
+            script.tick(&mut context).unwrap();
+        }
+        assert_eq!(context.registers[..2], [6, 7]);
+        assert_eq!(context.memory_map[0x400], 14);
+    }
+
+    #[test]
+    fn run_frame_hooks_and_press_keys() {
+        let mut context = Context::new(&PROGRAM, 1);
+        let mut script = compile(
+            "
+            fn count() {
+                set_memory(0x500, frame());
+                if frame() == 2 { press(0xA) }
+            }
+            set_i(0x123);
+            on_frame(Fn(\"count\"));
+            ",
+            &mut context,
+        );
+        assert_eq!(context.i_register, 0x123);
+
+        script.end_frame(&mut context).unwrap();
+        script.end_frame(&mut context).unwrap();
+        script.tick(&mut context).unwrap();
+        assert_eq!(context.memory_map[0x500], 2);
+        assert_eq!(context.keyboard_input, Some(0xA));
+    }
+
+    #[test]
+    fn report_script_errors() {
+        let mut context = Context::new(&PROGRAM, 1);
+        assert!(Script::compile("set_register(", &mut context, &Palette::default(), 1).is_err());
+
+        let mut script = compile("on_frame(|| set_register(16, 0));", &mut context);
+        let error = script.end_frame(&mut context).unwrap_err();
+        assert!(error.0.contains("isn't a register"));
+        // The context is given back even when the script fails
+        assert_eq!(context.program_counter, 0x200);
+    }
+
+    #[test]
+    fn take_screenshots() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("screenshot.png");
+        let mut context = Context::new(&PROGRAM, 1);
+        let source = format!("on_frame(|| screenshot({:?}));", path.to_str().unwrap());
+        let mut script = compile(&source, &mut context);
+
+        script.end_frame(&mut context).unwrap();
+        assert!(path.exists());
+    }
+}
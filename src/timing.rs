@@ -0,0 +1,225 @@
+use crate::{context::Context, instructions::Instruction};
+
+// Machine cycles of the COSMAC VIP per 60Hz frame. The CDP1802 runs at
+// 1.7609MHz and takes 8 clock cycles per machine cycle.
+pub const FRAME_CYCLES: u32 = 3668;
+// Cycles taken each frame by the DMA of the display, one per byte of the
+// 128 lines, and by the interrupt routine that counts the timers down
+pub const DISPLAY_CYCLES: u32 = 1024 + 46;
+// Cycles the interpreter takes to fetch and decode any instruction
+pub const FETCH_CYCLES: u32 = 40;
+
+/**
+ *  Machine cycles the original interpreter takes to run `instruction` in the
+ *  current state of `context`, before it runs. The costs are approximations
+ *  from analyses of the interpreter's code. Waiting for the display
+ *  interrupt before drawing isn't included, see `VipTiming`.
+ */
+pub fn cycles(context: &Context, instruction: &Instruction) -> u32 {
+    let v = |x: u8| context.registers[x as usize];
+    let skip = |skipped: bool| if skipped { 14 } else { 10 };
+    let skip_registers = |skipped: bool| if skipped { 18 } else { 14 };
+    let key = |x: u8| context.keyboard_input == Some(v(x));
+
+    FETCH_CYCLES
+        + match *instruction {
+            // Clears the 256 bytes of the display one at a time
+            Instruction::ClearScreen => 3078,
+            Instruction::Return => 10,
+            Instruction::Jump(_) => 12,
+            Instruction::Call(_) => 26,
+            // Machine code runs for as long as it wants, this is the call
+            Instruction::Sys(_) => 26,
+            Instruction::SkipIfEqual(x, value) => skip(v(x) == value),
+            Instruction::SkipIfNotEqual(x, value) => skip(v(x) != value),
+            Instruction::SkipIfEqualReg(x, y) => skip_registers(v(x) == v(y)),
+            Instruction::SkipIfNotEqualReg(x, y) => skip_registers(v(x) != v(y)),
+            Instruction::Set(_, _) => 6,
+            Instruction::Add(_, _) => 10,
+            Instruction::SetReg(_, _) => 12,
+            Instruction::Or(_, _)
+            | Instruction::And(_, _)
+            | Instruction::Xor(_, _)
+            | Instruction::AddReg(_, _)
+            | Instruction::SubReg(_, _)
+            | Instruction::ShiftRight(_, _)
+            | Instruction::SubN(_, _)
+            | Instruction::ShiftLeft(_, _) => 44,
+            Instruction::SetI(_) => 12,
+            Instruction::JumpToPlusV0(_) => 22,
+            Instruction::SetRandom(_, _) => 36,
+            Instruction::Display(x, _, n) => draw_cycles(v(x), n),
+            Instruction::SkipIfKeyPressed(x) => skip_registers(key(x)),
+            Instruction::SkipIfKeyNotPressed(x) => skip_registers(!key(x)),
+            Instruction::SetDelayTimer(_)
+            | Instruction::SetDelayTimerReg(_)
+            | Instruction::SetSoundTimerReg(_) => 10,
+            // Checked again every time until a key is down
+            Instruction::WaitForKey(_) => 10,
+            Instruction::AddToI(_) => 18,
+            Instruction::SetSpriteLocation(_) => 20,
+            // Counts hundreds and tens down one at a time
+            Instruction::StoreBCD(x) => {
+                let value = v(x) as u32;
+                80 + 16 * (value / 100 + value / 10 % 10 + value % 10)
+            }
+            Instruction::StoreRegRange(x) | Instruction::LoadRegRange(x) => {
+                14 + 14 * (x as u32 + 1)
+            }
+            // Not part of the original interpreter, costed like Fx55
+            Instruction::StoreFlags(x) | Instruction::LoadFlags(x) => 14 + 14 * (x as u32 + 1),
+            // The variants are costed like the closest CHIP-8 instruction
+            Instruction::CycleBackground
+            | Instruction::OutputTone(_)
+            | Instruction::Output(_)
+            | Instruction::Input(_)
+            | Instruction::WaitForInput(_)
+            | Instruction::WaitForStrobe(_)
+            | Instruction::WaitForDelay
+            | Instruction::DelayFor(_)
+            | Instruction::Stop => 10,
+            Instruction::AddNibbles(_, _)
+            | Instruction::SetColorZones(_, _)
+            | Instruction::SetColorArea(_, _, _) => 44,
+            Instruction::SkipIfKeyPressed2(x) => {
+                skip_registers(context.second_keypad == Some(v(x)))
+            }
+            Instruction::SkipIfKeyNotPressed2(x) => {
+                skip_registers(context.second_keypad != Some(v(x)))
+            }
+            Instruction::SkipIfGreater(x, y) => skip_registers(v(x) > v(y)),
+            Instruction::SkipNext | Instruction::JumpBackward(_) | Instruction::JumpForward(_) => {
+                12
+            }
+            Instruction::SkipBytes(_) => 22,
+            Instruction::StoreRange(x, y) | Instruction::LoadRange(x, y) => {
+                14 + 14 * (y.saturating_sub(x) as u32 + 1)
+            }
+            // MEGA-CHIP never ran on the VIP, its instructions are costed
+            // like the simplest ones
+            Instruction::DisableMegaChip
+            | Instruction::EnableMegaChip
+            | Instruction::SetLongI(_)
+            | Instruction::LoadPalette(_)
+            | Instruction::SetSpriteWidth(_)
+            | Instruction::SetSpriteHeight(_)
+            | Instruction::SetScreenAlpha(_)
+            | Instruction::PlaySound(_)
+            | Instruction::StopSound
+            | Instruction::SetBlendMode(_)
+            | Instruction::SetCollisionColor(_)
+            | Instruction::ScrollUp(_)
+            | Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit => 10,
+            Instruction::Data(_) => 0,
+        }
+}
+
+// Sprites are shifted one bit at a time to their horizontal position, so a
+// row costs more the further it is from a byte boundary
+fn draw_cycles(x: u8, rows: u8) -> u32 {
+    let shift = (x % 8) as u32;
+    26 + rows as u32 * (34 + 4 * shift)
+}
+
+/**
+ *  Runs instructions for the cycles the CPU gets each frame instead of a
+ *  fixed number of instructions. Cycles an instruction goes over are taken
+ *  from the next frame. `Dxyn` waits for the display interrupt: the sprite
+ *  is drawn right away, in the current frame, and the rest of the frame's
+ *  cycles are given up so nothing else runs until the next one.
+ */
+#[derive(Default)]
+pub struct VipTiming {
+    // Cycles left this frame, negative when the last instruction went over
+    budget: i64,
+}
+
+impl VipTiming {
+    pub fn new() -> VipTiming {
+        VipTiming::default()
+    }
+
+    // Must be called at the start of each frame
+    pub fn start_frame(&mut self) {
+        self.budget += (FRAME_CYCLES - DISPLAY_CYCLES) as i64;
+    }
+
+    // Whether the next instruction starts this frame
+    pub fn can_run(&self) -> bool {
+        self.budget > 0
+    }
+
+    // Takes the cycles of the instruction about to run from the frame
+    pub fn charge(&mut self, context: &Context) {
+        let instruction = context.fetch();
+        if let Instruction::Display(_, _, _) = instruction {
+            // The rest of the frame is spent waiting
+            self.budget = self.budget.min(0);
+        }
+        self.budget -= cycles(context, &instruction) as i64;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cycles, VipTiming, DISPLAY_CYCLES, FETCH_CYCLES, FRAME_CYCLES};
+    use crate::{context::Context, instructions::Instruction};
+
+    // Runs frames with VIP timing, returns the instructions run in each
+    fn run(program: &[u8], frames: usize) -> Vec<usize> {
+        let mut context = Context::new(program, 1);
+        let mut timing = VipTiming::new();
+        let mut counts = vec![];
+        for _ in 0..frames {
+            let mut count = 0;
+            timing.start_frame();
+            while timing.can_run() {
+                timing.charge(&context);
+                context.tick();
+                count += 1;
+            }
+            counts.push(count);
+        }
+        counts
+    }
+
+    #[test]
+    fn cost_depends_on_state() {
+        let mut context = Context::new(&[], 1);
+        context.registers[0] = 5;
+        let skip = Instruction::SkipIfEqual(0, 5);
+        let no_skip = Instruction::SkipIfEqual(0, 6);
+        assert_eq!(cycles(&context, &skip), cycles(&context, &no_skip) + 4);
+
+        let aligned = cycles(&context, &Instruction::Display(1, 1, 5));
+        let shifted = cycles(&context, &Instruction::Display(0, 1, 5));
+        assert_eq!(shifted - aligned, 5 * 4 * 5);
+
+        context.registers[0] = 255;
+        let bcd = cycles(&context, &Instruction::StoreBCD(0));
+        assert_eq!(bcd, FETCH_CYCLES + 80 + 16 * (2 + 5 + 5));
+    }
+
+    #[test]
+    fn run_cycles_per_frame() {
+        // LD V0, 1 ; JP 0x200
+        let counts = run(&[0x60, 0x01, 0x12, 0x00], 3);
+        let pair = FETCH_CYCLES * 2 + 6 + 12;
+        let per_frame = (FRAME_CYCLES - DISPLAY_CYCLES) as f64 / pair as f64 * 2.0;
+        for count in counts {
+            assert!((count as f64 - per_frame).abs() <= 1.0, "{count}");
+        }
+    }
+
+    #[test]
+    fn draw_once_per_frame() {
+        // DRW V0, V0, 1 ; JP 0x200
+        let counts = run(&[0xD0, 0x01, 0x12, 0x00], 4);
+        // Each draw waits for the interrupt, so a frame only runs the jump
+        // back and the next draw
+        assert_eq!(counts, [1, 2, 2, 2]);
+    }
+}
@@ -0,0 +1,300 @@
+use std::{fmt, ops::Range};
+
+use crate::{
+    cdp1802::{Bus, Cdp1802},
+    context::{MEMORY_SIZE, PROGRAM_ADDRESS},
+    timing::FRAME_CYCLES,
+};
+
+// Most the CHIP-8 interpreter can take, it's followed by the program
+pub const INTERPRETER_SIZE: usize = PROGRAM_ADDRESS as usize;
+// The monitor ROM is mirrored from here to the end of the address space
+pub const MONITOR_ADDRESS: u16 = 0x8000;
+pub const MONITOR_SIZE: usize = 0x200;
+
+// The CDP1861 draws 262 lines of 14 machine cycles per frame. It asserts
+// the interrupt 2 lines before the 128 display lines, and EF1 during the 4
+// lines before the display starts and ends.
+const LINE_CYCLES: u32 = 14;
+const INTERRUPT_LINES: Range<u32> = 78..80;
+const DISPLAY_LINES: Range<u32> = 80..208;
+const EF1_LINES: [Range<u32>; 2] = [76..80, 204..208];
+// Machine cycle of a display line from which its 8 DMA cycles can start
+const DMA_CYCLE: u32 = 2;
+// Each row of the 64x32 display is drawn on 4 lines
+const LINES_PER_ROW: u32 = 4;
+
+#[derive(PartialEq, Debug)]
+pub enum VipError {
+    InterpreterTooLarge(usize),
+    MonitorTooLarge(usize),
+}
+
+impl fmt::Display for VipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VipError::InterpreterTooLarge(size) => write!(
+                f,
+                "the interpreter has {size} bytes, at most {INTERPRETER_SIZE} fit before the program"
+            ),
+            VipError::MonitorTooLarge(size) => {
+                write!(f, "the monitor ROM has {size} bytes, at most {MONITOR_SIZE} fit")
+            }
+        }
+    }
+}
+
+// Memory and devices of the VIP
+struct Board {
+    ram: Vec<u8>,
+    // Empty without a monitor ROM image, reads 0 then
+    monitor: Vec<u8>,
+    keyboard_input: Option<u8>,
+    // Keypad key selected with OUT 2, EF3 is asserted while it's down
+    key_latch: u8,
+    // Switched on with INP 1 and off with OUT 1
+    display_on: bool,
+    ef1: bool,
+}
+
+impl Bus for Board {
+    fn read(&mut self, address: u16) -> u8 {
+        if address >= MONITOR_ADDRESS {
+            let offset = (address - MONITOR_ADDRESS) as usize % MONITOR_SIZE;
+            self.monitor.get(offset).copied().unwrap_or(0)
+        } else {
+            self.ram[address as usize % MEMORY_SIZE]
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address < MONITOR_ADDRESS {
+            self.ram[address as usize % MEMORY_SIZE] = value;
+        }
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => self.display_on = false,
+            2 => self.key_latch = value & 0xF,
+            _ => {}
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            self.display_on = true;
+        }
+        0
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        match flag {
+            1 => self.ef1,
+            3 => self.keyboard_input == Some(self.key_latch),
+            _ => false,
+        }
+    }
+}
+
+/**
+ *  A COSMAC VIP with 4KB of RAM, running the original CHIP-8 interpreter on
+ *  its CDP1802 so that timing and machine code calls (`0nnn`) behave like
+ *  the real thing. The interpreter image isn't included and has to be
+ *  supplied, as does the monitor ROM for the routines the interpreter calls
+ *  in it, like the keypad routine of `Fx0A`.
+ *
+ *  The keypad and the 64x32 display work like `Context`: set
+ *  `keyboard_input` and read `graphics_buffer` after each frame.
+ */
+pub struct Vip {
+    pub cpu: Cdp1802,
+    board: Board,
+    pub keyboard_input: Option<u8>,
+    // 32 rows of 8 bytes, as the display showed them in the last frame
+    pub graphics_buffer: Vec<Vec<u8>>,
+    // Machine cycles run into the current frame
+    frame_cycle: u32,
+}
+
+impl Vip {
+    /**
+     *  Loads the interpreter at 0 and the program after it, then starts at 0
+     *  the way the monitor does after a reset, with R1 pointing at the last
+     *  page of RAM.
+     */
+    pub fn new(
+        interpreter: &[u8],
+        monitor: Option<&[u8]>,
+        program: &[u8],
+    ) -> Result<Vip, VipError> {
+        if interpreter.len() > INTERPRETER_SIZE {
+            return Err(VipError::InterpreterTooLarge(interpreter.len()));
+        }
+        let monitor = monitor.unwrap_or_default();
+        if monitor.len() > MONITOR_SIZE {
+            return Err(VipError::MonitorTooLarge(monitor.len()));
+        }
+
+        // Programs that don't fit in memory are cut short
+        let mut ram = vec![0; MEMORY_SIZE];
+        ram[..interpreter.len()].copy_from_slice(interpreter);
+        let length = program.len().min(MEMORY_SIZE - INTERPRETER_SIZE);
+        ram[INTERPRETER_SIZE..][..length].copy_from_slice(&program[..length]);
+
+        let mut cpu = Cdp1802::new();
+        cpu.r[1] = (MEMORY_SIZE - 0x100) as u16;
+        Ok(Vip {
+            cpu,
+            board: Board {
+                ram,
+                monitor: monitor.to_vec(),
+                keyboard_input: None,
+                key_latch: 0,
+                display_on: false,
+                ef1: false,
+            },
+            keyboard_input: None,
+            graphics_buffer: vec![vec![0; 8]; 32],
+            frame_cycle: 0,
+        })
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.board.ram
+    }
+
+    // The VIP beeps while Q is set
+    pub fn sound_on(&self) -> bool {
+        self.cpu.q
+    }
+
+    // Runs the machine cycles of one 60Hz frame, interrupts and DMA included
+    pub fn run_frame(&mut self) {
+        self.board.keyboard_input = self.keyboard_input;
+        let mut interrupted = false;
+        let mut dma_line = None;
+        let mut displayed = false;
+
+        while self.frame_cycle < FRAME_CYCLES {
+            let line = self.frame_cycle / LINE_CYCLES;
+            let display_on = self.board.display_on;
+            self.board.ef1 = display_on && EF1_LINES.iter().any(|lines| lines.contains(&line));
+
+            if display_on && !interrupted && self.cpu.ie && INTERRUPT_LINES.contains(&line) {
+                self.cpu.interrupt();
+                interrupted = true;
+                self.frame_cycle += 1;
+                continue;
+            }
+
+            let dma_due = self.frame_cycle % LINE_CYCLES >= DMA_CYCLE && dma_line != Some(line);
+            if display_on && dma_due && DISPLAY_LINES.contains(&line) {
+                dma_line = Some(line);
+                displayed = true;
+                let bytes = (0..8).map(|_| self.cpu.dma_out(&mut self.board)).collect();
+                let display_line = line - DISPLAY_LINES.start;
+                if display_line.is_multiple_of(LINES_PER_ROW) {
+                    self.graphics_buffer[(display_line / LINES_PER_ROW) as usize] = bytes;
+                }
+                self.frame_cycle += 8;
+                continue;
+            }
+
+            self.frame_cycle += self.cpu.step(&mut self.board);
+        }
+        self.frame_cycle -= FRAME_CYCLES;
+
+        if !displayed {
+            self.graphics_buffer = vec![vec![0; 8]; 32];
+        }
+    }
+
+    pub fn get_flat_graphics_buffer(&self) -> Vec<u8> {
+        self.graphics_buffer.iter().flatten().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Vip, VipError, INTERPRETER_SIZE};
+
+    // Sets up the stack and the interrupt routine, turns the display on and
+    // loops. The routine is laid out like the one of the CHIP-8 interpreter:
+    // it shows each row on 4 lines by moving R0 back after 3 of them.
+    const DISPLAY: &[u8] = &[
+        0xF8, 0x0E, 0xB2, // LDI 0E; PHI R2
+        0xF8, 0xFF, 0xA2, // LDI FF; PLO R2
+        0xF8, 0x00, 0xB1, // LDI 00; PHI R1
+        0xF8, 0x15, 0xA1, // LDI 15; PLO R1
+        0xE2, 0x69, // SEX R2; INP 1
+        0x30, 0x0E, // 0E: BR 0E
+        0x00, 0x00, 0x00, // padding
+        // 13: return from the interrupt
+        0x72, 0x70, // LDXA; RET
+        // 15: the interrupt routine
+        0x22, 0x78, 0x22, 0x52, // DEC R2; SAV; DEC R2; STR R2
+        0xC4, 0xC4, 0xC4, // NOP; NOP; NOP
+        0xF8, 0x0F, 0xB0, // LDI 0F; PHI R0
+        0xF8, 0x00, 0xA0, // LDI 00; PLO R0
+        0x80, 0xE2, // 22: GLO R0; SEX R2
+        0xE2, 0x20, 0xA0, // SEX R2; DEC R0; PLO R0
+        0xE2, 0x20, 0xA0, // SEX R2; DEC R0; PLO R0
+        0xE2, 0x20, 0xA0, // SEX R2; DEC R0; PLO R0
+        0x3C, 0x22, // BN1 22
+        0x30, 0x13, // BR 13
+    ];
+
+    #[test]
+    fn display_rows_from_dma() {
+        let mut vip = Vip::new(DISPLAY, None, &[]).unwrap();
+        for (index, byte) in vip.board.ram[0xF00..].iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+        vip.run_frame();
+        vip.run_frame();
+
+        let expected: Vec<u8> = (0..=255).collect();
+        assert_eq!(vip.get_flat_graphics_buffer(), expected);
+        // Interrupts return to the main loop with R2 back where it was
+        assert_eq!(vip.cpu.r[2], 0x0EFF);
+        assert_eq!(vip.cpu.p, 0);
+    }
+
+    #[test]
+    fn keypad_and_sound() {
+        let keypad = [
+            0xF8, 0x80, 0xA3, // LDI 80; PLO R3
+            0xF8, 0x05, 0x53, // LDI 05; STR R3
+            0xE3, 0x62, // SEX R3; OUT 2
+            0x36, 0x0C, // B3 0C
+            0x30, 0x0A, // 0A: BR 0A
+            0x7B, // 0C: SEQ
+            0x30, 0x0D, // 0D: BR 0D
+        ];
+        for (key, sound) in [(None, false), (Some(4), false), (Some(5), true)] {
+            let mut vip = Vip::new(&keypad, None, &[]).unwrap();
+            vip.keyboard_input = key;
+            vip.run_frame();
+            assert_eq!(vip.sound_on(), sound);
+            assert_eq!(vip.get_flat_graphics_buffer(), [0; 256]);
+        }
+    }
+
+    #[test]
+    fn load_images() {
+        let vip = Vip::new(&[0xAB], Some(&[0xCD]), &[0x12, 0x34]).unwrap();
+        assert_eq!(vip.memory()[0], 0xAB);
+        assert_eq!(vip.memory()[INTERPRETER_SIZE..][..2], [0x12, 0x34]);
+        assert_eq!(vip.cpu.r[1], 0x0F00);
+
+        let large = vec![0; INTERPRETER_SIZE + 1];
+        let error = Vip::new(&large, None, &[]).err();
+        assert_eq!(
+            error,
+            Some(VipError::InterpreterTooLarge(INTERPRETER_SIZE + 1))
+        );
+        let error = Vip::new(&[], Some(&large), &[]).err();
+        assert_eq!(error, Some(VipError::MonitorTooLarge(INTERPRETER_SIZE + 1)));
+    }
+}